@@ -6,14 +6,55 @@
 struct Material {
     color: vec4<f32>,
     is_hover: f32,
+    // see `Terrain::style`
+    terrain: f32,
 };
 
 @group(1) @binding(0) var<uniform> material: Material;
 
+const ROUGH: f32 = 1.0;
+const BOOST_PAD: f32 = 2.0;
+const WATER: f32 = 3.0;
+const VOID: f32 = 4.0;
+const ENERGY_NODE: f32 = 5.0;
+
+fn is_terrain(style: f32) -> bool {
+    return abs(material.terrain - style) < 0.5;
+}
+
+// adds a neon line of `color` where `d` is close to 0
+fn glow(base: vec4<f32>, color: vec3<f32>, d: f32, width: f32) -> vec4<f32> {
+    let intensity = clamp(1.0 - abs(d) / width, 0.0, 1.0);
+    return vec4<f32>(mix(base.rgb, color, intensity), base.a);
+}
+
 @fragment
 fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
     if (material.is_hover > 0.5) {
         return vec4<f32>(1.0, 0.0, 0.0, 1.0);
     }
-    return material.color;
+    let uv = mesh.uv - vec2<f32>(0.5, 0.5);
+    let center = length(uv);
+    var color = material.color;
+    if (is_terrain(ROUGH)) {
+        // scattered orange dots
+        let cell = fract(uv * 6.0) - vec2<f32>(0.5, 0.5);
+        color = glow(color, vec3<f32>(1.0, 0.5, 0.1), length(cell), 0.18);
+    } else if (is_terrain(BOOST_PAD)) {
+        // green chevrons scrolling towards the top
+        let chevron = fract(uv.y * 3.0 - abs(uv.x) * 2.0 - globals.time) - 0.5;
+        color = glow(color, vec3<f32>(0.2, 1.0, 0.4), chevron, 0.12);
+    } else if (is_terrain(WATER)) {
+        // blue waves
+        let wave = sin((uv.x + sin(uv.y * 8.0 + globals.time) * 0.05) * 25.0);
+        color = vec4<f32>(mix(vec3<f32>(0.0, 0.1, 0.3), vec3<f32>(0.1, 0.5, 1.0), wave * 0.5 + 0.5), color.a);
+    } else if (is_terrain(VOID)) {
+        // a dark hole with a magenta rim
+        color = glow(vec4<f32>(0.02, 0.0, 0.04, color.a), vec3<f32>(1.0, 0.1, 0.8), center - 0.45, 0.08);
+    } else if (is_terrain(ENERGY_NODE)) {
+        // a pulsing yellow core
+        let pulse = 0.15 + 0.05 * sin(globals.time * 3.0);
+        color = glow(color, vec3<f32>(1.0, 0.9, 0.2), center, pulse);
+    }
+    return color;
 }
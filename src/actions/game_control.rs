@@ -1,28 +1,77 @@
 use bevy::prelude::{Input, KeyCode, Res};
 
-#[allow(dead_code)]
 pub enum GameControl {
     Up,
     Down,
     Left,
     Right,
+    ShowCoverage,
+    HoldBuilding,
+    RerollBuildings,
+    SaveRun,
+    SaveReplay,
+    OpenPortal,
+    QueueDrone,
+    QueueRunner,
+    QueueTank,
+    QueueFlyer,
+    CrystalBrush,
+    PortalBrush,
+    BlockerBrush,
+    ObstacleBrush,
+    PlainBrush,
+    RoughBrush,
+    BoostPadBrush,
+    WaterBrush,
+    VoidBrush,
+    EnergyNodeBrush,
+    GrowMap,
+    ShrinkMap,
+    ExportMap,
+    ImportMap,
+    Back,
 }
 
 impl GameControl {
-    pub fn _pressed(&self, keyboard_input: &Res<Input<KeyCode>>) -> bool {
+    fn keys(&self) -> &'static [KeyCode] {
         match self {
-            GameControl::Up => {
-                keyboard_input.pressed(KeyCode::W) || keyboard_input.pressed(KeyCode::Up)
-            }
-            GameControl::Down => {
-                keyboard_input.pressed(KeyCode::S) || keyboard_input.pressed(KeyCode::Down)
-            }
-            GameControl::Left => {
-                keyboard_input.pressed(KeyCode::A) || keyboard_input.pressed(KeyCode::Left)
-            }
-            GameControl::Right => {
-                keyboard_input.pressed(KeyCode::D) || keyboard_input.pressed(KeyCode::Right)
-            }
+            GameControl::Up => &[KeyCode::W, KeyCode::Up],
+            GameControl::Down => &[KeyCode::S, KeyCode::Down],
+            GameControl::Left => &[KeyCode::A, KeyCode::Left],
+            GameControl::Right => &[KeyCode::D, KeyCode::Right],
+            GameControl::ShowCoverage => &[KeyCode::Tab],
+            GameControl::HoldBuilding => &[KeyCode::H],
+            GameControl::RerollBuildings => &[KeyCode::R],
+            GameControl::SaveRun => &[KeyCode::F5],
+            GameControl::SaveReplay => &[KeyCode::F6],
+            GameControl::OpenPortal => &[KeyCode::Return],
+            GameControl::QueueDrone => &[KeyCode::Key1],
+            GameControl::QueueRunner => &[KeyCode::Key2],
+            GameControl::QueueTank => &[KeyCode::Key3],
+            GameControl::QueueFlyer => &[KeyCode::Key4],
+            GameControl::CrystalBrush => &[KeyCode::Key1],
+            GameControl::PortalBrush => &[KeyCode::Key2],
+            GameControl::BlockerBrush => &[KeyCode::Key3],
+            GameControl::ObstacleBrush => &[KeyCode::Key0],
+            GameControl::PlainBrush => &[KeyCode::Key4],
+            GameControl::RoughBrush => &[KeyCode::Key5],
+            GameControl::BoostPadBrush => &[KeyCode::Key6],
+            GameControl::WaterBrush => &[KeyCode::Key7],
+            GameControl::VoidBrush => &[KeyCode::Key8],
+            GameControl::EnergyNodeBrush => &[KeyCode::Key9],
+            GameControl::GrowMap => &[KeyCode::Equals, KeyCode::NumpadAdd],
+            GameControl::ShrinkMap => &[KeyCode::Minus, KeyCode::NumpadSubtract],
+            GameControl::ExportMap => &[KeyCode::F5],
+            GameControl::ImportMap => &[KeyCode::F9],
+            GameControl::Back => &[KeyCode::Escape],
         }
     }
+
+    pub fn pressed(&self, keyboard_input: &Res<Input<KeyCode>>) -> bool {
+        keyboard_input.any_pressed(self.keys().iter().copied())
+    }
+
+    pub fn just_pressed(&self, keyboard_input: &Res<Input<KeyCode>>) -> bool {
+        keyboard_input.any_just_pressed(self.keys().iter().copied())
+    }
 }
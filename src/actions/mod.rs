@@ -1,19 +1,31 @@
 use bevy::prelude::*;
 
+use crate::{
+    tick::{TickEventApp, TickSet},
+    GameState,
+};
+
+use self::player_command::{
+    collect_player_commands, execute_player_commands, EventPlayerCommand, TickCommands,
+};
+
 pub mod cursor;
-mod game_control;
+pub mod game_control;
+pub mod player_command;
 
 pub struct ActionsPlugin;
 
-// This plugin listens for keyboard input and converts the input into Actions
-// Actions can then be used as a resource in other systems to act on the player input.
+// What the player does to the run goes through player commands, carried out at the next tick.
 impl Plugin for ActionsPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<Actions>();
+        app.init_resource::<TickCommands>()
+            .add_tick_event::<EventPlayerCommand>()
+            .add_systems(
+                FixedUpdate,
+                (collect_player_commands, execute_player_commands)
+                    .chain()
+                    .in_set(TickSet::Commands)
+                    .run_if(in_state(GameState::Playing)),
+            );
     }
 }
-
-#[derive(Default, Resource)]
-pub struct Actions {
-    pub player_movement: Option<Vec2>,
-}
@@ -0,0 +1,164 @@
+use bevy::{
+    ecs::system::{EntityCommand, SystemState},
+    prelude::*,
+};
+use hexx::Hex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    balance::Balance,
+    buildings::{Building, EventHoldBuilding, EventRerollBuildings},
+    economy::{NotEnoughEnergy, TransactionReason, Wallet},
+    entities::{enemy::EnemyKind, portal::SpawnPortalCmd, turret::SpawnTurretCmd},
+    grid::{GridChanged, HexCell, HexGrid, NonConstructible, Obstacle},
+    inventory::Inventory,
+    versus::Versus,
+};
+
+/// Something the player does that changes the run.
+/// Input systems send them with [`EventPlayerCommand`], they are carried out at the start of the next tick
+/// so that the same commands at the same ticks always give the same run.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerCommand {
+    /// builds the item of an inventory slot, on the hex at these axial coordinates
+    Build {
+        hex: [i32; 2],
+        slot: usize,
+    },
+    OpenPortal {
+        hex: [i32; 2],
+    },
+    Hold,
+    Reroll,
+    /// pays to remove the obstacle on the hex at these axial coordinates
+    ClearObstacle {
+        hex: [i32; 2],
+    },
+    /// the attacker of a versus match buys an enemy for its next portal
+    QueueEnemy {
+        kind: EnemyKind,
+    },
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct EventPlayerCommand(pub PlayerCommand);
+
+/// The commands carried out during the current tick
+#[derive(Resource, Debug, Default)]
+pub struct TickCommands(pub Vec<PlayerCommand>);
+
+impl PlayerCommand {
+    pub fn apply(self, world: &mut World) {
+        match self {
+            PlayerCommand::Build { hex, slot } => {
+                let Some(parent_hex) = free_hex(world, hex) else {
+                    return;
+                };
+                // the portals of the map need their hex
+                if world
+                    .resource::<HexGrid>()
+                    .portals
+                    .contains(&Hex::new(hex[0], hex[1]))
+                {
+                    return;
+                }
+                let item = world
+                    .query::<&Inventory<Building>>()
+                    .get_single(world)
+                    .ok()
+                    .and_then(|inventory| inventory.items.get(slot).copied());
+                let Some(item) = item else {
+                    return;
+                };
+                let id = world.spawn_empty().id();
+                SpawnTurretCmd {
+                    parent_hex,
+                    item: Some(item),
+                }
+                .apply(id, world);
+                world.send_event(GridChanged);
+            }
+            PlayerCommand::OpenPortal { hex } => {
+                let Some(parent_hex) = free_hex(world, hex) else {
+                    return;
+                };
+                let portals = &world.resource::<HexGrid>().portals;
+                if !portals.is_empty() && !portals.contains(&Hex::new(hex[0], hex[1])) {
+                    return;
+                }
+                // the attacker of a versus match opens portals with the enemies it bought
+                if world
+                    .get_resource::<Versus>()
+                    .is_some_and(|versus| versus.queued.is_empty())
+                {
+                    return;
+                }
+                let id = world.spawn_empty().id();
+                SpawnPortalCmd { parent_hex }.apply(id, world);
+                world.send_event(GridChanged);
+            }
+            PlayerCommand::Hold => world.send_event(EventHoldBuilding),
+            PlayerCommand::Reroll => world.send_event(EventRerollBuildings),
+            PlayerCommand::ClearObstacle { hex } => clear_obstacle(world, hex),
+            PlayerCommand::QueueEnemy { kind } => {
+                if let Some(mut versus) = world.get_resource_mut::<Versus>() {
+                    versus.buy(kind);
+                }
+            }
+        }
+    }
+}
+
+fn clear_obstacle(world: &mut World, [x, y]: [i32; 2]) {
+    let Some(&hex_entity) = world.resource::<HexGrid>().hex_to_entity(&Hex::new(x, y)) else {
+        return;
+    };
+    let obstacle = world
+        .query_filtered::<(Entity, &Parent), With<Obstacle>>()
+        .iter(world)
+        .find_map(|(obstacle, parent)| (parent.get() == hex_entity).then_some(obstacle));
+    let Some(obstacle) = obstacle else {
+        return;
+    };
+    let mut state: SystemState<(Wallet, Res<Balance>, EventWriter<NotEnoughEnergy>)> =
+        SystemState::new(world);
+    let (mut wallet, balance, mut not_enough_energy) = state.get_mut(world);
+    let cost = balance.economy.obstacle_clear_cost;
+    if !wallet.try_spend(cost, TransactionReason::ClearObstacle) {
+        not_enough_energy.send(NotEnoughEnergy { cost });
+        return;
+    }
+    world.entity_mut(obstacle).remove_parent();
+    world.entity_mut(obstacle).despawn_recursive();
+    world.send_event(GridChanged);
+}
+
+/// The hex at `[x, y]`, if something can be built on it
+pub(crate) fn free_hex(world: &World, [x, y]: [i32; 2]) -> Option<Entity> {
+    let grid = world.get_resource::<HexGrid>()?;
+    let hex = Hex::new(x, y);
+    if grid.crystals.contains(&hex) {
+        return None;
+    }
+    let entity = *grid.hex_to_entity(&hex)?;
+    let hex = world.get_entity(entity)?;
+    let terrain = hex.get::<HexCell>()?.terrain;
+    (terrain.is_constructible()
+        && !hex.contains::<NonConstructible>()
+        && !hex.contains::<Children>())
+    .then_some(entity)
+}
+
+pub(crate) fn collect_player_commands(
+    mut events: EventReader<EventPlayerCommand>,
+    mut tick_commands: ResMut<TickCommands>,
+) {
+    tick_commands.0.extend(events.read().map(|event| event.0));
+}
+
+pub(crate) fn execute_player_commands(world: &mut World) {
+    let commands = std::mem::take(&mut world.resource_mut::<TickCommands>().0);
+    for command in commands {
+        command.apply(world);
+    }
+}
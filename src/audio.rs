@@ -1,4 +1,3 @@
-use crate::actions::Actions;
 use crate::loading::AudioAssets;
 use crate::GameState;
 use bevy::prelude::*;
@@ -11,17 +10,14 @@ impl Plugin for InternalAudioPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(AudioPlugin)
             .add_systems(OnEnter(GameState::Playing), start_audio);
-        // .add_systems(
-        //     Update,
-        //     control_flying_sound
-        //         .after(set_movement_actions)
-        //         .run_if(in_state(GameState::Playing)),
-        // )
     }
 }
 
 #[derive(Resource)]
-struct FlyingAudio(Handle<AudioInstance>);
+struct FlyingAudio(
+    // the sound stops once its handle is dropped
+    #[allow(dead_code)] Handle<AudioInstance>,
+);
 
 fn start_audio(mut commands: Commands, audio_assets: Res<AudioAssets>, audio: Res<Audio>) {
     audio.pause();
@@ -32,25 +28,3 @@ fn start_audio(mut commands: Commands, audio_assets: Res<AudioAssets>, audio: Re
         .handle();
     commands.insert_resource(FlyingAudio(handle));
 }
-
-fn _control_flying_sound(
-    actions: Res<Actions>,
-    audio: Res<FlyingAudio>,
-    mut audio_instances: ResMut<Assets<AudioInstance>>,
-) {
-    if let Some(instance) = audio_instances.get_mut(&audio.0) {
-        match instance.state() {
-            PlaybackState::Paused { .. } => {
-                if actions.player_movement.is_some() {
-                    instance.resume(AudioTween::default());
-                }
-            }
-            PlaybackState::Playing { .. } => {
-                if actions.player_movement.is_none() {
-                    instance.pause(AudioTween::default());
-                }
-            }
-            _ => {}
-        }
-    }
-}
@@ -0,0 +1,481 @@
+use std::time::Duration;
+
+use bevy::{
+    ecs::system::SystemParam,
+    input::mouse::MouseButtonInput,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use hexx::{Hex, HexBounds, HexLayout};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::player_command::{collect_player_commands, EventPlayerCommand, PlayerCommand},
+    balance::Balance,
+    buildings::Building,
+    economy::Energy,
+    entities::portal::Portal,
+    grid::{distances_to_crystals, path_changes, HexCell, HexGrid, NonConstructible, Terrain},
+    inventory::Inventory,
+    network::NetworkSession,
+    overload::Overload,
+    random::{RandomStreams, RngStream},
+    tick::{Tick, TickSet, TICKS_PER_SECOND},
+    GameState,
+};
+
+pub struct AutoPlayerPlugin;
+
+/// This plugin plays the run while an [`AutoPlayer`] resource exists.
+/// It reads the board like the player does and sends the same commands: it opens a portal
+/// whenever the overload gets low, and builds the next building of the inventory
+/// where its [`PlacementStrategy`] says, as soon as it can be paid for
+impl Plugin for AutoPlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            play.before(collect_player_commands)
+                .in_set(TickSet::Commands)
+                .run_if(resource_exists::<AutoPlayer>())
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+pub struct AttractModePlugin;
+
+/// When the menu is left alone for a while, the autoplayer starts a run by itself.
+/// Any key or click hands that run over to the player
+impl Plugin for AttractModePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            start_demo
+                .run_if(not(resource_exists::<NetworkSession>()))
+                .run_if(in_state(GameState::Menu)),
+        )
+        .add_systems(
+            Update,
+            take_over_demo
+                .run_if(resource_exists::<Demo>())
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+/// how long the menu waits for the player before starting a demo
+const DEMO_DELAY: Duration = Duration::from_secs(20);
+
+/// Where the autoplayer builds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PlacementStrategy {
+    /// where the path of the enemies to the crystal gets the longest
+    GreedyMaze,
+    /// where the turret sees the most of the path of the enemies
+    Coverage,
+    /// anywhere something can be built
+    Random,
+    /// on these axial coordinates, in order. Hexes that can't be built on are skipped
+    Scripted(Vec<[i32; 2]>),
+}
+
+/// The board as the autoplayer sees it
+#[derive(Debug, Clone)]
+pub struct Board {
+    pub layout: HexLayout,
+    pub bounds: HexBounds,
+    /// hexes something can be built on, in a stable order
+    pub free: Vec<Hex>,
+    /// hexes the enemies can't walk through
+    pub walls: HashSet<Hex>,
+    /// the hexes that aren't plain
+    pub terrain: HashMap<Hex, Terrain>,
+    pub crystals: Vec<Hex>,
+    /// where the enemies come from: the opened portals and the ones still to open
+    pub entries: Vec<Hex>,
+    /// the view range of a turret, in world units
+    pub turret_range: f32,
+}
+
+impl Board {
+    /// The hexes the enemies walk through, from every entry to the crystal
+    fn path(&self, distances: &HashMap<Hex, u32>) -> HashSet<Hex> {
+        let mut path = HashSet::new();
+        for &entry in &self.entries {
+            let mut hex = entry;
+            loop {
+                let next = hex
+                    .all_neighbors()
+                    .into_iter()
+                    .filter_map(|neighbor| distances.get(&neighbor).map(|&d| (neighbor, d)))
+                    .min_by_key(|&(neighbor, dist)| (dist, [neighbor.x, neighbor.y]));
+                let Some((next, dist)) = next else {
+                    break;
+                };
+                if distances.get(&hex).is_some_and(|&here| here <= dist) {
+                    break;
+                }
+                path.insert(next);
+                hex = next;
+            }
+        }
+        path
+    }
+
+    fn candidates(&self) -> impl Iterator<Item = Hex> + '_ {
+        self.free
+            .iter()
+            .copied()
+            .filter(|hex| !self.crystals.contains(hex) && !self.entries.contains(hex))
+    }
+}
+
+impl PlacementStrategy {
+    /// Where to build next, if anywhere
+    pub fn choose(&self, board: &Board, rng: &mut impl rand::Rng) -> Option<Hex> {
+        match self {
+            PlacementStrategy::GreedyMaze => board
+                .candidates()
+                .filter_map(|hex| {
+                    let changes = path_changes(
+                        &board.bounds,
+                        &board.walls,
+                        &board.terrain,
+                        &board.crystals,
+                        &board.entries,
+                        hex,
+                    );
+                    let lengthening = changes
+                        .iter()
+                        .try_fold(0, |lengthening, change| Some(lengthening + change.delta()?))?;
+                    Some((hex, lengthening))
+                })
+                .max_by_key(|&(hex, lengthening)| (lengthening, std::cmp::Reverse([hex.x, hex.y])))
+                .map(|(hex, _)| hex),
+            PlacementStrategy::Coverage => {
+                let path = board.path(&distances_to_crystals(
+                    &board.bounds,
+                    &board.walls,
+                    &board.terrain,
+                    &board.crystals,
+                ));
+                board
+                    .candidates()
+                    .filter(|hex| !path.contains(hex))
+                    .map(|hex| {
+                        let position = board.layout.hex_to_world_pos(hex);
+                        let covered = path
+                            .iter()
+                            .filter(|&&p| {
+                                board.layout.hex_to_world_pos(p).distance(position)
+                                    <= board.turret_range
+                            })
+                            .count();
+                        (hex, covered)
+                    })
+                    .max_by_key(|&(hex, covered)| (covered, std::cmp::Reverse([hex.x, hex.y])))
+                    .map(|(hex, _)| hex)
+            }
+            PlacementStrategy::Random => {
+                let candidates: Vec<Hex> = board.candidates().collect();
+                candidates.choose(rng).copied()
+            }
+            PlacementStrategy::Scripted(placements) => placements
+                .iter()
+                .map(|&[x, y]| Hex::new(x, y))
+                .find(|hex| board.free.contains(hex)),
+        }
+    }
+}
+
+/// Plays the run in place of the player
+#[derive(Resource, Debug, Clone)]
+pub struct AutoPlayer {
+    pub strategy: PlacementStrategy,
+    /// where the portals are opened, in turn, when the map doesn't say.
+    /// In the corners of the map when empty
+    pub portals: Vec<[i32; 2]>,
+    /// a portal is opened when the overload falls under this
+    pub portal_overload: f32,
+    /// the least time between two portals, while the enemies of the last one raise the overload
+    pub portal_cooldown_seconds: f32,
+    next_portal: usize,
+    last_portal_tick: Option<u64>,
+}
+
+impl AutoPlayer {
+    pub fn new(strategy: PlacementStrategy) -> Self {
+        Self {
+            strategy,
+            portals: Vec::new(),
+            portal_overload: 0.3,
+            portal_cooldown_seconds: 5.,
+            next_portal: 0,
+            last_portal_tick: None,
+        }
+    }
+}
+
+impl Default for AutoPlayer {
+    fn default() -> Self {
+        Self::new(PlacementStrategy::GreedyMaze)
+    }
+}
+
+/// The corners of a map of `radius`, next to the border
+fn corner_portals(radius: u32) -> Vec<[i32; 2]> {
+    let radius = radius.saturating_sub(1).max(1);
+    Hex::ZERO
+        .ring(radius)
+        .step_by(radius as usize)
+        .map(|hex| [hex.x, hex.y])
+        .collect()
+}
+
+/// The run in progress was started by the menu, not by the player
+#[derive(Resource, Debug)]
+pub struct Demo;
+
+/// What the autoplayer sees of the run, as a player would
+#[derive(SystemParam)]
+struct PlayerView<'w, 's> {
+    grid: Res<'w, HexGrid>,
+    q_cells: Query<
+        'w,
+        's,
+        (
+            &'static HexCell,
+            Option<&'static NonConstructible>,
+            Option<&'static Children>,
+        ),
+    >,
+    q_portals: Query<'w, 's, &'static Parent, With<Portal>>,
+    q_inventory: Query<'w, 's, &'static Inventory<Building>>,
+    q_buildings: Query<'w, 's, &'static Building>,
+    q_overload: Query<'w, 's, &'static Overload>,
+    energy: Res<'w, Energy>,
+    balance: Res<'w, Balance>,
+}
+
+impl PlayerView<'_, '_> {
+    fn overload_below(&self, threshold: f32) -> bool {
+        self.q_overload
+            .get_single()
+            .is_ok_and(|overload| overload.0 < threshold)
+    }
+
+    /// Whether there is a building to place and enough energy to pay for it
+    fn can_build(&self) -> bool {
+        let cost = self
+            .q_inventory
+            .get_single()
+            .ok()
+            .and_then(|inventory| inventory.items.front())
+            .and_then(|&item| self.q_buildings.get(item).ok())
+            .map(|building| self.balance.economy.building_cost.of(building));
+        cost.is_some_and(|cost| self.energy.balance() >= cost)
+    }
+
+    /// The board as the strategies see it, the enemies coming from the open portals and `portals`
+    fn board(&self, portals: &[[i32; 2]]) -> Board {
+        let grid = &self.grid;
+        let mut free = Vec::new();
+        let mut walls = HashSet::new();
+        let mut terrain = HashMap::new();
+        for hex in grid.bounds.all_coords() {
+            let Some(&entity) = grid.hex_to_entity(&hex) else {
+                continue;
+            };
+            let Ok((cell, non_constructible, content)) = self.q_cells.get(entity) else {
+                continue;
+            };
+            if cell.terrain != Terrain::Plain {
+                terrain.insert(hex, cell.terrain);
+            }
+            if content.is_some() {
+                walls.insert(hex);
+            } else if non_constructible.is_none() && cell.terrain.is_constructible() {
+                free.push(hex);
+            }
+        }
+        free.sort_by_key(|hex| [hex.x, hex.y]);
+        let mut entries: Vec<Hex> = self
+            .q_portals
+            .iter()
+            .filter_map(|parent| grid.entity_to_hex(parent.get()))
+            .chain(portals.iter().map(|&[x, y]| Hex::new(x, y)))
+            .collect();
+        entries.sort_by_key(|hex| [hex.x, hex.y]);
+        entries.dedup();
+        Board {
+            layout: grid.layout.clone(),
+            bounds: grid.bounds,
+            free,
+            walls,
+            terrain,
+            crystals: grid.crystals.clone(),
+            entries,
+            turret_range: self.balance.turrets.range_in_hexes * grid.layout.hex_size.length(),
+        }
+    }
+}
+
+fn play(
+    tick: Res<Tick>,
+    mut autoplayer: ResMut<AutoPlayer>,
+    view: PlayerView,
+    mut streams: ResMut<RandomStreams>,
+    mut player_commands: EventWriter<EventPlayerCommand>,
+) {
+    let cooldown = (autoplayer.portal_cooldown_seconds as f64 * TICKS_PER_SECOND) as u64;
+    let cooled_down = autoplayer
+        .last_portal_tick
+        .is_none_or(|last| tick.0 >= last + cooldown);
+    let low_overload = view.overload_below(autoplayer.portal_overload);
+    let grid = &view.grid;
+    let portals: Vec<[i32; 2]> = if !grid.portals.is_empty() {
+        grid.portals.iter().map(|hex| [hex.x, hex.y]).collect()
+    } else if !autoplayer.portals.is_empty() {
+        autoplayer.portals.clone()
+    } else {
+        corner_portals(grid.bounds.radius)
+    };
+    if cooled_down && low_overload && !portals.is_empty() {
+        let hex = portals[autoplayer.next_portal % portals.len()];
+        autoplayer.next_portal += 1;
+        autoplayer.last_portal_tick = Some(tick.0);
+        player_commands.send(EventPlayerCommand(PlayerCommand::OpenPortal { hex }));
+    }
+
+    if !view.can_build() {
+        return;
+    }
+    let board = view.board(&portals);
+
+    let rng = &mut streams.get(RngStream::AutoPlayer).random;
+    if let Some(hex) = autoplayer.strategy.choose(&board, rng) {
+        player_commands.send(EventPlayerCommand(PlayerCommand::Build {
+            hex: [hex.x, hex.y],
+            slot: 0,
+        }));
+    }
+}
+
+fn start_demo(
+    mut commands: Commands,
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut mouse_input: EventReader<MouseButtonInput>,
+    mut cursor_moved: EventReader<CursorMoved>,
+    mut state: ResMut<NextState<GameState>>,
+    mut idle: Local<Duration>,
+) {
+    let touched = keyboard_input.get_just_pressed().next().is_some()
+        || mouse_input.read().count() > 0
+        || cursor_moved.read().count() > 0;
+    if touched {
+        *idle = Duration::ZERO;
+        return;
+    }
+    *idle += time.delta();
+    if *idle < DEMO_DELAY {
+        return;
+    }
+    *idle = Duration::ZERO;
+    info!("Starting a demo run");
+    commands.insert_resource(AutoPlayer::default());
+    commands.insert_resource(Demo);
+    state.set(GameState::Playing);
+}
+
+fn take_over_demo(
+    mut commands: Commands,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+) {
+    if keyboard_input.get_just_pressed().next().is_some()
+        || mouse_input.get_just_pressed().next().is_some()
+    {
+        info!("The player takes over the demo run");
+        commands.remove_resource::<AutoPlayer>();
+        commands.remove_resource::<Demo>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::MAP_RADIUS;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    /// An empty map with a single entry on the border
+    fn board() -> Board {
+        let free = Hex::ZERO
+            .range(MAP_RADIUS)
+            .filter(|hex| *hex != Hex::ZERO)
+            .collect();
+        Board {
+            layout: HexLayout::default(),
+            bounds: HexBounds::new(Hex::ZERO, MAP_RADIUS),
+            free,
+            walls: HashSet::new(),
+            terrain: HashMap::new(),
+            crystals: vec![Hex::ZERO],
+            entries: vec![Hex::new(MAP_RADIUS as i32, 0)],
+            turret_range: 2.5,
+        }
+    }
+
+    #[test]
+    fn the_default_portals_follow_the_size_of_the_map() {
+        for radius in [3, 5, 15] {
+            let portals = corner_portals(radius);
+            assert_eq!(portals.len(), 6);
+            for [x, y] in portals {
+                assert_eq!(Hex::new(x, y).ulength(), radius - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn greedy_maze_makes_the_path_longer() {
+        let board = board();
+        let rng = &mut ChaCha20Rng::seed_from_u64(0);
+        let hex = PlacementStrategy::GreedyMaze.choose(&board, rng).unwrap();
+        let changes = path_changes(
+            &board.bounds,
+            &board.walls,
+            &board.terrain,
+            &board.crystals,
+            &board.entries,
+            hex,
+        );
+        assert!(changes[0].delta().unwrap() > 0);
+    }
+
+    #[test]
+    fn coverage_builds_next_to_the_path() {
+        let board = board();
+        let rng = &mut ChaCha20Rng::seed_from_u64(0);
+        let hex = PlacementStrategy::Coverage.choose(&board, rng).unwrap();
+        let path = board.path(&distances_to_crystals(
+            &board.bounds,
+            &board.walls,
+            &board.terrain,
+            &board.crystals,
+        ));
+        assert!(!path.contains(&hex));
+        assert!(path.iter().any(|p| p.unsigned_distance_to(hex) == 1));
+    }
+
+    #[test]
+    fn scripted_skips_what_cant_be_built_on() {
+        let mut board = board();
+        board.free.retain(|hex| *hex != Hex::new(1, 0));
+        let strategy = PlacementStrategy::Scripted(vec![[1, 0], [2, 0]]);
+        let rng = &mut ChaCha20Rng::seed_from_u64(0);
+        assert_eq!(strategy.choose(&board, rng), Some(Hex::new(2, 0)));
+    }
+}
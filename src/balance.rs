@@ -0,0 +1,612 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    ecs::system::SystemParam,
+    prelude::*,
+    utils::BoxedFuture,
+};
+use ron::Value;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    buildings::{Building, BuildingSize},
+    challenge::ActiveChallenge,
+    difficulty::{Difficulty, DifficultySettings},
+    entities::enemy::EnemyKind,
+    grid::MapDefinition,
+    loot::LootTable,
+};
+
+pub struct BalancePlugin;
+
+/// This plugin loads the gameplay tuning from `assets/balance/default.balance.ron`
+/// and exposes it through the [`Balance`] resource.
+/// The file can be edited while the game runs when the `dev` feature is enabled (hot-reload),
+/// and a map can layer its own [`BalancePatch`] on top of it, see [`MapDefinition::balance`].
+/// The selected [`Difficulty`] is applied next, then the modifiers of the daily challenge.
+impl Plugin for BalancePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<BalanceConfig>()
+            .init_asset::<BalancePatch>()
+            .register_asset_loader(BalanceConfigLoader)
+            .register_asset_loader(BalancePatchLoader)
+            .init_resource::<Balance>()
+            .init_resource::<MapBalance>()
+            .add_systems(Startup, load_base_balance)
+            .add_systems(Update, (load_map_balance, apply_balance_changes).chain());
+    }
+}
+
+/// All the numbers designers may want to tweak without recompiling.
+/// Every field has a default, so a balance file only needs to list what it changes.
+#[derive(Asset, TypePath, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct BalanceConfig {
+    pub overload: OverloadBalance,
+    pub economy: EconomyBalance,
+    pub enemies: EnemiesBalance,
+    pub turrets: TurretBalance,
+    pub bullets: BulletBalance,
+    pub difficulty: DifficultyPresets,
+    /// which buildings the inventory draws
+    pub loot: LootTable,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct OverloadBalance {
+    /// overload when a game starts, between 0 and 1
+    pub start: f32,
+    pub decay_per_second: f32,
+    pub gain_per_spawned_enemy: f32,
+    /// the game is lost under this overload
+    pub depleted_threshold: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct EconomyBalance {
+    pub starting_energy: u32,
+    pub interest_period_seconds: f32,
+    pub interest_rate: f32,
+    pub max_interest: u32,
+    pub building_cost: BuildingCosts,
+    /// energy paid to replace all the queued buildings
+    pub reroll_cost: u32,
+    /// energy paid to clear an obstacle off the map
+    pub obstacle_clear_cost: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct BuildingCosts {
+    pub small: u32,
+    pub medium: u32,
+    pub big: u32,
+    /// added to the cost of pink and blue buildings
+    pub rare_color: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct EnemiesBalance {
+    /// delay between two frames of the enemy animation
+    pub animation_frame_seconds: f32,
+    /// distance under which an enemy considers it reached the hex it was heading to
+    pub target_reached_epsilon: f32,
+    pub hitbox: f32,
+    pub drone: EnemyStats,
+    pub runner: EnemyStats,
+    pub tank: EnemyStats,
+    pub flyer: EnemyStats,
+}
+
+/// The stats of one kind of enemy. Fields missing from a balance file keep the defaults of
+/// the same kind, see [`BalanceConfig::from_bytes`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct EnemyStats {
+    pub health: f32,
+    pub velocity: f32,
+    /// energy earned when killing this kind of enemy
+    pub reward: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TurretBalance {
+    /// delay in seconds between two shots
+    pub fire_rate: f32,
+    pub damage: f32,
+    /// view range, expressed in hexes
+    pub range_in_hexes: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct BulletBalance {
+    pub velocity: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DifficultyPresets {
+    pub story: DifficultyModifiers,
+    pub normal: DifficultyModifiers,
+    pub hard: DifficultyModifiers,
+    pub nightmare: DifficultyModifiers,
+}
+
+/// Multipliers applied to the base balance, 1 leaves a value untouched
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DifficultyModifiers {
+    pub enemy_health: f32,
+    pub enemy_speed: f32,
+    pub overload_decay: f32,
+    pub rewards: f32,
+}
+
+impl Default for OverloadBalance {
+    fn default() -> Self {
+        Self {
+            start: 0.5,
+            decay_per_second: 0.03,
+            gain_per_spawned_enemy: 0.1,
+            depleted_threshold: 0.001,
+        }
+    }
+}
+
+impl Default for EconomyBalance {
+    fn default() -> Self {
+        Self {
+            starting_energy: 60,
+            interest_period_seconds: 10.,
+            interest_rate: 0.05,
+            max_interest: 10,
+            building_cost: BuildingCosts::default(),
+            reroll_cost: 20,
+            obstacle_clear_cost: 30,
+        }
+    }
+}
+
+impl Default for BuildingCosts {
+    fn default() -> Self {
+        Self {
+            small: 10,
+            medium: 15,
+            big: 25,
+            rare_color: 10,
+        }
+    }
+}
+
+impl Default for EnemiesBalance {
+    fn default() -> Self {
+        Self {
+            animation_frame_seconds: 0.1,
+            target_reached_epsilon: 1.5,
+            hitbox: 10.,
+            drone: EnemyStats {
+                health: 2.,
+                velocity: 20.,
+                reward: 5,
+            },
+            runner: EnemyStats {
+                health: 1.,
+                velocity: 35.,
+                reward: 4,
+            },
+            tank: EnemyStats {
+                health: 6.,
+                velocity: 12.,
+                reward: 15,
+            },
+            flyer: EnemyStats {
+                health: 2.,
+                velocity: 15.,
+                reward: 8,
+            },
+        }
+    }
+}
+
+impl Default for TurretBalance {
+    fn default() -> Self {
+        Self {
+            fire_rate: 1.,
+            damage: 1.,
+            range_in_hexes: 2.,
+        }
+    }
+}
+
+impl Default for BulletBalance {
+    fn default() -> Self {
+        Self { velocity: 200. }
+    }
+}
+
+impl Default for DifficultyPresets {
+    fn default() -> Self {
+        Self {
+            story: DifficultyModifiers {
+                enemy_health: 0.6,
+                enemy_speed: 0.8,
+                overload_decay: 0.6,
+                rewards: 1.5,
+            },
+            normal: DifficultyModifiers {
+                enemy_health: 1.,
+                enemy_speed: 1.,
+                overload_decay: 1.,
+                rewards: 1.,
+            },
+            hard: DifficultyModifiers {
+                enemy_health: 1.5,
+                enemy_speed: 1.15,
+                overload_decay: 1.25,
+                rewards: 0.8,
+            },
+            nightmare: DifficultyModifiers {
+                enemy_health: 2.,
+                enemy_speed: 1.3,
+                overload_decay: 1.5,
+                rewards: 0.6,
+            },
+        }
+    }
+}
+
+impl DifficultyPresets {
+    pub fn of(&self, difficulty: Difficulty) -> &DifficultyModifiers {
+        match difficulty {
+            Difficulty::Story => &self.story,
+            Difficulty::Normal => &self.normal,
+            Difficulty::Hard => &self.hard,
+            Difficulty::Nightmare => &self.nightmare,
+        }
+    }
+}
+
+impl EnemyStats {
+    fn scaled(&self, modifiers: &DifficultyModifiers) -> Self {
+        Self {
+            health: self.health * modifiers.enemy_health,
+            velocity: self.velocity * modifiers.enemy_speed,
+            reward: (self.reward as f32 * modifiers.rewards).round() as u32,
+        }
+    }
+}
+
+impl EnemiesBalance {
+    pub fn stats(&self, kind: EnemyKind) -> &EnemyStats {
+        match kind {
+            EnemyKind::Drone => &self.drone,
+            EnemyKind::Runner => &self.runner,
+            EnemyKind::Tank => &self.tank,
+            EnemyKind::Flyer => &self.flyer,
+        }
+    }
+}
+
+impl BuildingCosts {
+    /// Bigger and rarer buildings cost more energy
+    pub fn of(&self, building: &Building) -> u32 {
+        let size_cost = match building.size() {
+            BuildingSize::Small => self.small,
+            BuildingSize::Medium => self.medium,
+            BuildingSize::Big => self.big,
+        };
+        if building.has_rare_color() {
+            size_cost + self.rare_color
+        } else {
+            size_cost
+        }
+    }
+}
+
+/// A partial [`BalanceConfig`], only containing the values to override.
+/// It is kept untyped so that overriding a single nested value keeps all its siblings.
+/// The loot table is the exception: its enum values don't survive the untyped merge, so it is
+/// read on its own and replaces the whole table.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct BalancePatch {
+    values: Value,
+    loot: Option<LootTable>,
+}
+
+/// The loot table of a patch, read with its types
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct LootPatch {
+    loot: Option<LootTable>,
+}
+
+impl BalancePatch {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ron::error::SpannedError> {
+        let mut values: Value = ron::de::from_bytes(bytes)?;
+        if let Value::Map(map) = &mut values {
+            map.remove(&Value::String("loot".to_string()));
+        }
+        let loot: LootPatch = ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_bytes(bytes)?;
+        Ok(Self {
+            values,
+            loot: loot.loot,
+        })
+    }
+}
+
+impl BalanceConfig {
+    /// Reads a balance file on top of the defaults, so that a partial entry keeps the values it
+    /// leaves out, e.g. the stats of each kind of enemy
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BalanceLoadError> {
+        Ok(Self::default().patched(&BalancePatch::from_bytes(bytes)?)?)
+    }
+
+    /// Returns a copy of this configuration where all the values listed in `patch` are replaced
+    pub fn patched(&self, patch: &BalancePatch) -> Result<Self, ron::Error> {
+        let mut base = self.clone();
+        // the loot table doesn't go through the merge
+        base.loot = LootTable {
+            entries: vec![],
+            pity: vec![],
+            banned: vec![],
+        };
+        let mut value: Value = ron::from_str(&ron::to_string(&base)?)?;
+        merge(&mut value, &patch.values);
+        let mut patched: Self = value.into_rust()?;
+        patched.loot = patch.loot.clone().unwrap_or_else(|| self.loot.clone());
+        Ok(patched)
+    }
+
+    /// Returns a copy of this configuration scaled by the modifiers of `difficulty`
+    pub fn for_difficulty(&self, difficulty: Difficulty) -> Self {
+        let modifiers = self.difficulty.of(difficulty);
+        let mut scaled = self.clone();
+        scaled.overload.decay_per_second *= modifiers.overload_decay;
+        for stats in [
+            &mut scaled.enemies.drone,
+            &mut scaled.enemies.runner,
+            &mut scaled.enemies.tank,
+            &mut scaled.enemies.flyer,
+        ] {
+            *stats = stats.scaled(modifiers);
+        }
+        scaled
+    }
+}
+
+fn merge(base: &mut Value, patch: &Value) {
+    match (base, patch) {
+        (Value::Map(base), Value::Map(patch)) => {
+            for (key, value) in patch.iter() {
+                let mut merged = base.remove(key).unwrap_or(Value::Unit);
+                merge(&mut merged, value);
+                base.insert(key.clone(), merged);
+            }
+        }
+        (base, patch) => *base = patch.clone(),
+    }
+}
+
+/// The balance currently in use: the base configuration with the map overrides,
+/// the difficulty and the daily challenge applied.
+/// Falls back to [`BalanceConfig::default`] until the balance file is loaded.
+#[derive(Resource, Debug, Clone, Default, Deref)]
+pub struct Balance(pub BalanceConfig);
+
+#[derive(Resource)]
+pub(crate) struct BaseBalance(pub(crate) Handle<BalanceConfig>);
+
+/// Overrides applied on top of the base balance for the current map, if any,
+/// loaded from [`MapDefinition::balance`]
+#[derive(Resource, Default)]
+pub struct MapBalance(pub Option<Handle<BalancePatch>>);
+
+fn load_base_balance(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(BaseBalance(
+        asset_server.load("balance/default.balance.ron"),
+    ));
+}
+
+fn load_map_balance(
+    map: Option<Res<MapDefinition>>,
+    asset_server: Res<AssetServer>,
+    mut map_balance: ResMut<MapBalance>,
+) {
+    let Some(map) = map.filter(|map| map.is_changed()) else {
+        return;
+    };
+    let handle = map.balance.as_ref().map(|path| asset_server.load(path));
+    if map_balance.0 != handle {
+        map_balance.0 = handle;
+    }
+}
+
+/// Everything the balance in use is made of
+#[derive(SystemParam)]
+struct BalanceSources<'w> {
+    base: Option<Res<'w, BaseBalance>>,
+    map_balance: Res<'w, MapBalance>,
+    difficulty: Res<'w, DifficultySettings>,
+    challenge: Res<'w, ActiveChallenge>,
+    configs: Res<'w, Assets<BalanceConfig>>,
+    patches: Res<'w, Assets<BalancePatch>>,
+}
+
+fn apply_balance_changes(
+    mut config_events: EventReader<AssetEvent<BalanceConfig>>,
+    mut patch_events: EventReader<AssetEvent<BalancePatch>>,
+    sources: BalanceSources,
+    mut balance: ResMut<Balance>,
+) {
+    let config_changed = config_events.read().count() > 0;
+    let patch_changed = patch_events.read().count() > 0;
+    let BalanceSources {
+        base,
+        map_balance,
+        difficulty,
+        challenge,
+        configs,
+        patches,
+    } = sources;
+    if !config_changed
+        && !patch_changed
+        && !map_balance.is_changed()
+        && !difficulty.is_changed()
+        && !challenge.is_changed()
+    {
+        return;
+    }
+    let Some(config) = base.and_then(|base| configs.get(&base.0)) else {
+        return;
+    };
+    let patch = map_balance
+        .0
+        .as_ref()
+        .and_then(|handle| patches.get(handle));
+    let new_balance = match patch.map(|patch| config.patched(patch)) {
+        None => config.clone(),
+        Some(Ok(patched)) => patched,
+        Some(Err(e)) => {
+            error!("Could not apply the map balance overrides: {}", e);
+            config.clone()
+        }
+    }
+    .for_difficulty(difficulty.preset);
+    let new_balance = challenge.apply(new_balance);
+    if balance.0 != new_balance {
+        info!("Balance updated");
+        balance.0 = new_balance;
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BalanceLoadError {
+    #[error("could not read the balance file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse the balance file: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+    #[error("could not apply the balance file: {0}")]
+    Apply(#[from] ron::Error),
+}
+
+#[derive(Default)]
+struct BalanceConfigLoader;
+
+impl AssetLoader for BalanceConfigLoader {
+    type Asset = BalanceConfig;
+    type Settings = ();
+    type Error = BalanceLoadError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            BalanceConfig::from_bytes(&bytes)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["balance.ron"]
+    }
+}
+
+#[derive(Default)]
+struct BalancePatchLoader;
+
+impl AssetLoader for BalancePatchLoader {
+    type Asset = BalancePatch;
+    type Settings = ();
+    type Error = BalanceLoadError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(BalancePatch::from_bytes(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["balance_patch.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_balance_file_matches_code_defaults() {
+        let config: BalanceConfig =
+            ron::from_str(include_str!("../assets/balance/default.balance.ron")).unwrap();
+        assert_eq!(config, BalanceConfig::default());
+        let loaded =
+            BalanceConfig::from_bytes(include_bytes!("../assets/balance/default.balance.ron"))
+                .unwrap();
+        assert_eq!(loaded, BalanceConfig::default());
+    }
+
+    #[test]
+    fn patch_only_overrides_listed_values() {
+        let patch = BalancePatch::from_bytes(
+            b"(overload: (decay_per_second: 0.06), enemies: (tank: (health: 10.0)))",
+        )
+        .unwrap();
+        let patched = BalanceConfig::default().patched(&patch).unwrap();
+
+        let mut expected = BalanceConfig::default();
+        expected.overload.decay_per_second = 0.06;
+        expected.enemies.tank.health = 10.;
+        assert_eq!(patched, expected);
+    }
+
+    #[test]
+    fn patch_replaces_the_whole_loot_table() {
+        let patch = BalancePatch::from_bytes(
+            b"(loot: (entries: [(pattern: (color: [Pink]), weight: ([(0, 1.0)]))]))",
+        )
+        .unwrap();
+        let patched = BalanceConfig::default().patched(&patch).unwrap();
+        assert_eq!(patched.loot.entries.len(), 1);
+        // what the table leaves out comes from the default table, as in the balance file
+        assert_eq!(patched.loot.pity, LootTable::default().pity);
+        assert_eq!(
+            patched.loot.entries[0].pattern.color,
+            [crate::buildings::BuildingColor::Pink]
+        );
+    }
+
+    #[test]
+    fn enemy_stats_only_need_the_values_they_change() {
+        let config = BalanceConfig::from_bytes(b"(enemies: (tank: (health: 10.0)))").unwrap();
+        let defaults = EnemiesBalance::default();
+        assert_eq!(config.enemies.tank.health, 10.);
+        assert_eq!(config.enemies.tank.velocity, defaults.tank.velocity);
+        assert_eq!(config.enemies.tank.reward, defaults.tank.reward);
+        assert_ne!(defaults.tank.reward, defaults.drone.reward);
+    }
+
+    #[test]
+    fn normal_difficulty_keeps_the_balance() {
+        let config = BalanceConfig::default();
+        assert_eq!(config.for_difficulty(Difficulty::Normal), config);
+        let hard = config.for_difficulty(Difficulty::Hard);
+        assert!(hard.enemies.tank.health > config.enemies.tank.health);
+        assert!(hard.overload.decay_per_second > config.overload.decay_per_second);
+    }
+}
@@ -0,0 +1,69 @@
+//! Plays a run without a window, as fast as possible, and prints how it went as JSON.
+//!
+//! `neon-sim [--seed N] [--difficulty Story|Normal|Hard|Nightmare] [--seconds S]
+//! [--strategy GreedyMaze|Coverage|Random] [--map FILE]`
+use std::path::Path;
+
+use bevy_game::{
+    autoplayer::PlacementStrategy,
+    simulation::{simulate, SimulationSettings},
+    Difficulty, MapDefinition,
+};
+
+fn main() {
+    let settings = match parse_args(std::env::args().skip(1)) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "usage: neon-sim [--seed N] [--difficulty Story|Normal|Hard|Nightmare] [--seconds S] [--strategy GreedyMaze|Coverage|Random] [--map FILE]"
+            );
+            std::process::exit(2);
+        }
+    };
+    let report = simulate(&settings);
+    match serde_json::to_string_pretty(&report) {
+        Ok(json) => println!("{}", json),
+        Err(e) => {
+            eprintln!("could not write the report: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<SimulationSettings, String> {
+    let mut settings = SimulationSettings::default();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--seed" => {
+                settings.seed = value
+                    .parse()
+                    .map_err(|_| format!("invalid seed: {}", value))?;
+            }
+            "--difficulty" => {
+                settings.difficulty = ron::from_str::<Difficulty>(&value)
+                    .map_err(|_| format!("unknown difficulty: {}", value))?;
+            }
+            "--seconds" => {
+                settings.seconds = value
+                    .parse()
+                    .map_err(|_| format!("invalid duration: {}", value))?;
+            }
+            "--strategy" => {
+                settings.strategy = ron::from_str::<PlacementStrategy>(&value)
+                    .map_err(|_| format!("unknown strategy: {}", value))?;
+            }
+            "--map" => {
+                let map = MapDefinition::load(Path::new(&value))
+                    .and_then(|map| map.validate().map(|()| map))
+                    .map_err(|e| format!("invalid map {}: {}", value, e))?;
+                settings.map = map;
+            }
+            _ => return Err(format!("unknown argument: {}", arg)),
+        }
+    }
+    Ok(settings)
+}
@@ -1,17 +1,25 @@
+use crate::actions::game_control::GameControl;
+use crate::actions::player_command::{EventPlayerCommand, PlayerCommand};
+use crate::balance::Balance;
+use crate::difficulty::Wave;
+use crate::economy::{NotEnoughEnergy, TransactionReason, Wallet};
 use crate::inventory::{self};
-use crate::inventory::{Inventory, SpawnInventory};
-use crate::random::RandomDeterministic;
-use crate::window::WindowSize;
+use crate::inventory::{
+    Inventory, InventoryLayout, RestoreInventory, SavedInventory, ScreenAnchor, SpawnInventory,
+};
+use crate::loot::LootPity;
+use crate::random::{RandomDeterministic, RandomState, RngStream, RunSeed};
+use crate::tick::{GameplaySet, TickEventApp};
 use crate::GameState;
 use bevy::ecs::system::{EntityCommand, SystemParam, SystemState};
 
 use bevy::prelude::*;
 use bevy::render::mesh::Indices;
 use bevy::render::render_resource::PrimitiveTopology;
-use bevy::sprite::MaterialMesh2dBundle;
 use bevy::sprite::Mesh2dHandle;
+use bevy::sprite::{Anchor, MaterialMesh2dBundle};
 use bevy::utils::HashMap;
-use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
 
 pub struct BuildingsPlugin;
 
@@ -19,19 +27,34 @@ impl Plugin for BuildingsPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(inventory::InventoryPlugin::<Building>::default())
             .init_resource::<BuildingInventory>()
+            .add_tick_event::<EventHoldBuilding>()
+            .add_tick_event::<EventRerollBuildings>()
             .add_systems(
                 OnEnter(GameState::Playing),
                 (create_assets, spawn_layout).chain(),
             )
             .add_systems(
                 Update,
-                update_anchor_position
-                    .run_if(resource_changed::<WindowSize>())
+                send_inventory_actions.run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                FixedUpdate,
+                (hold_building, reroll_buildings)
+                    .chain()
+                    .in_set(GameplaySet::Inventory)
                     .run_if(in_state(GameState::Playing)),
             );
     }
 }
 
+/// Stash the next building, or swap it with the one already stashed
+#[derive(Event, Debug)]
+pub struct EventHoldBuilding;
+
+/// Replace all the queued buildings with new random ones, for some energy
+#[derive(Event, Debug)]
+pub struct EventRerollBuildings;
+
 #[derive(Resource)]
 pub struct BuildingInventory {
     pub(crate) state: SystemState<GetNextBuildingParams<'static, 'static>>,
@@ -45,10 +68,15 @@ pub(crate) struct GetNextBuildingParams<'w, 's> {
         's,
         (
             &'static mut RandomDeterministic,
+            &'static mut LootPity,
             &'static mut crate::inventory::Inventory<Building>,
         ),
     >,
     q_buildings: Query<'w, 's, &'static Building>,
+    wallet: Wallet<'w>,
+    balance: Res<'w, Balance>,
+    wave: Res<'w, Wave>,
+    not_enough_energy: EventWriter<'w, NotEnoughEnergy>,
 }
 
 impl FromWorld for BuildingInventory {
@@ -60,31 +88,163 @@ impl FromWorld for BuildingInventory {
 }
 
 impl BuildingInventory {
+    /// Takes the picked building, or the first one if none is picked
     pub fn next(&mut self, world: &mut World) -> Option<Building> {
+        let params = self.state.get_mut(world);
+        let item = params.q_inventory.single().2.next_item()?;
+        self.take(world, item)
+    }
+
+    /// Takes `item` out of its slot if it can be paid for, and refills the queue
+    pub fn take(&mut self, world: &mut World, item: Entity) -> Option<Building> {
         let mut params = self.state.get_mut(world);
-        let (mut rng, mut inventory) = params.q_inventory.single_mut();
+        let (mut rng, mut pity, mut inventory) = params.q_inventory.single_mut();
 
-        let Some(first_item) = inventory.items.front().cloned() else {
+        if !inventory.is_visible(item) {
             return None;
-        };
-        let Ok(_item_to_build) = params.q_buildings.get(first_item) else {
+        }
+        let Ok(&item_to_build) = params.q_buildings.get(item) else {
             return None;
         };
-        // TODO: check if we can build item_to_build (cooldown, space available, currency, ...)
-        // TODO: send an event if not possible.
-        // TODO: pay "price" ?
-        inventory.items.pop_front();
-
-        let new_building = get_random_building(&mut rng);
-        let new_item = params.command.spawn(new_building).id();
-
-        inventory.items.push_back(new_item);
+        // TODO: check if we can build item_to_build (cooldown, space available, ...)
+        let cost = params.balance.economy.building_cost.of(&item_to_build);
+        if !params
+            .wallet
+            .try_spend(cost, TransactionReason::Build(item_to_build))
+        {
+            params.not_enough_energy.send(NotEnoughEnergy { cost });
+            self.state.apply(world);
+            return None;
+        }
+        inventory.take(item);
+        let (loot, wave) = (&params.balance.loot, params.wave.0);
+        fill_inventory(&mut params.command, &mut inventory, || {
+            loot.draw(wave, &mut rng, &mut pity)
+        });
 
         // TODO: reuse that entity to merge it with turret entity ?
-        world.despawn(first_item);
+        world.entity_mut(item).despawn_recursive();
 
         self.state.apply(world);
-        Some(new_building)
+        Some(item_to_build)
+    }
+}
+
+/// Tops up the queue with buildings from `draw`
+fn fill_inventory(
+    commands: &mut Commands,
+    inventory: &mut Inventory<Building>,
+    mut draw: impl FnMut() -> Building,
+) {
+    while !inventory.is_full() {
+        let new_item = commands.spawn(draw()).id();
+        inventory.push(new_item);
+    }
+}
+
+/// The building queue and where its random generator stands, so that a restored game draws
+/// the same buildings
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedBuildingInventory {
+    pub inventory: SavedInventory<Building>,
+    pub random: RandomState,
+    pub pity: LootPity,
+}
+
+impl SavedBuildingInventory {
+    pub fn save(world: &mut World) -> Option<Self> {
+        let mut q_inventory = world.query::<(
+            &RandomDeterministic,
+            &LootPity,
+            &crate::inventory::Inventory<Building>,
+        )>();
+        let (rng, pity, inventory) = q_inventory.get_single(world).ok()?;
+        Some(Self {
+            inventory: inventory.save(world),
+            random: rng.state(),
+            pity: pity.clone(),
+        })
+    }
+
+    pub fn restore(self, world: &mut World) {
+        let mut q_inventory = world.query_filtered::<Entity, With<Inventory<Building>>>();
+        let Ok(entity) = q_inventory.get_single(world) else {
+            return;
+        };
+        RestoreInventory {
+            saved: self.inventory,
+        }
+        .apply(entity, world);
+        world
+            .entity_mut(entity)
+            .insert((RandomDeterministic::from_state(self.random), self.pity));
+    }
+}
+
+fn send_inventory_actions(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut player_commands: EventWriter<EventPlayerCommand>,
+) {
+    if GameControl::HoldBuilding.just_pressed(&keyboard_input) {
+        player_commands.send(EventPlayerCommand(PlayerCommand::Hold));
+    }
+    if GameControl::RerollBuildings.just_pressed(&keyboard_input) {
+        player_commands.send(EventPlayerCommand(PlayerCommand::Reroll));
+    }
+}
+
+fn hold_building(
+    mut commands: Commands,
+    mut events: EventReader<EventHoldBuilding>,
+    mut q_inventory: Query<(
+        &mut RandomDeterministic,
+        &mut LootPity,
+        &mut Inventory<Building>,
+    )>,
+    balance: Res<Balance>,
+    wave: Res<Wave>,
+) {
+    for _ in events.read() {
+        let Ok((mut rng, mut pity, mut inventory)) = q_inventory.get_single_mut() else {
+            return;
+        };
+        if inventory.hold_first() {
+            fill_inventory(&mut commands, &mut inventory, || {
+                balance.loot.draw(wave.0, &mut rng, &mut pity)
+            });
+        }
+    }
+}
+
+fn reroll_buildings(
+    mut commands: Commands,
+    mut events: EventReader<EventRerollBuildings>,
+    mut q_inventory: Query<(
+        &mut RandomDeterministic,
+        &mut LootPity,
+        &mut Inventory<Building>,
+    )>,
+    mut wallet: Wallet,
+    balance: Res<Balance>,
+    wave: Res<Wave>,
+    mut not_enough_energy: EventWriter<NotEnoughEnergy>,
+) {
+    for _ in events.read() {
+        let Ok((mut rng, mut pity, mut inventory)) = q_inventory.get_single_mut() else {
+            return;
+        };
+        let cost = balance.economy.reroll_cost;
+        if !wallet.try_spend(cost, TransactionReason::Reroll) {
+            not_enough_energy.send(NotEnoughEnergy { cost });
+            continue;
+        }
+        inventory.picked = None;
+        for item in inventory.items.drain(..) {
+            commands.entity(item).despawn_recursive();
+        }
+        fill_inventory(&mut commands, &mut inventory, || {
+            balance.loot.draw(wave.0, &mut rng, &mut pity)
+        });
     }
 }
 
@@ -155,79 +315,103 @@ pub(crate) fn create_assets(
 
 const ITEM_VISUAL_SIZE: f32 = 64f32;
 const PADDING: f32 = 10f32;
+const INVENTORY_CAPACITY: usize = 10;
+const INVENTORY_SLOTS: usize = 6;
+const INVENTORY_PREVIEW: usize = 3;
 
-pub(crate) fn spawn_layout(mut commands: Commands, window_size: ResMut<WindowSize>) {
-    let mut rng = crate::random::RandomDeterministic::new_from_seed(0);
-    let inventory = vec![
-        commands.spawn(get_random_building(&mut rng)).id(),
-        commands.spawn(get_random_building(&mut rng)).id(),
-        commands.spawn(get_random_building(&mut rng)).id(),
-        commands.spawn(get_random_building(&mut rng)).id(),
-        commands.spawn(get_random_building(&mut rng)).id(),
-        commands.spawn(get_random_building(&mut rng)).id(),
-    ];
-    let anchor_point = Vec3::new(
-        -window_size.size.x / 2f32 + ITEM_VISUAL_SIZE / 2f32 + PADDING,
-        -window_size.size.y / 2f32 + (ITEM_VISUAL_SIZE + PADDING) * 5.5f32 + PADDING,
-        0f32,
-    );
+pub(crate) fn spawn_layout(mut commands: Commands, balance: Res<Balance>, seed: Res<RunSeed>) {
+    let mut rng = seed.stream(RngStream::Loot);
+    let mut pity = LootPity::default();
+    let inventory = (0..INVENTORY_CAPACITY)
+        .map(|_| {
+            let building = balance.loot.draw(0, &mut rng, &mut pity);
+            commands.spawn(building).id()
+        })
+        .collect();
 
     commands
-        .spawn_empty()
+        .spawn((
+            SpatialBundle::default(),
+            ScreenAnchor {
+                anchor: Anchor::BottomLeft,
+                offset: Vec2::splat(ITEM_VISUAL_SIZE / 2f32 + PADDING),
+            },
+            Name::new("Building inventory"),
+        ))
         .add(SpawnInventory::<Building>::new(
             inventory,
             inventory::InventoryConfiguration {
-                positions: positions_from_anchor_point(anchor_point),
+                capacity: INVENTORY_CAPACITY,
+                slots: INVENTORY_SLOTS,
+                preview: INVENTORY_PREVIEW,
+                layout: InventoryLayout::Vertical {
+                    spacing: ITEM_VISUAL_SIZE + PADDING,
+                },
+                // the held building is shown next to the first one
+                hold_position: Vec3::new((ITEM_VISUAL_SIZE + PADDING) * 1.5f32, 0f32, 0f32),
             },
         ))
-        .insert(RandomDeterministic::new_from_seed(0));
-}
-
-fn positions_from_anchor_point(anchor_point: Vec3) -> Vec<Vec3> {
-    vec![
-        anchor_point - Vec3::new(0f32, (ITEM_VISUAL_SIZE + PADDING) * 5f32, 0f32),
-        anchor_point - Vec3::new(0f32, (ITEM_VISUAL_SIZE + PADDING) * 4f32, 0f32),
-        anchor_point - Vec3::new(0f32, (ITEM_VISUAL_SIZE + PADDING) * 3f32, 0f32),
-        anchor_point - Vec3::new(0f32, (ITEM_VISUAL_SIZE + PADDING) * 2f32, 0f32),
-        anchor_point - Vec3::new(0f32, ITEM_VISUAL_SIZE + PADDING, 0f32),
-        anchor_point,
-    ]
-}
-
-pub(crate) fn update_anchor_position(
-    window_size: ResMut<WindowSize>,
-    mut q_inventory: Query<&mut Inventory<Building>>,
-) {
-    let anchor_point: Vec3 = Vec3::new(
-        -window_size.size.x / 2f32 + ITEM_VISUAL_SIZE / 2f32 + PADDING,
-        -window_size.size.y / 2f32 + (ITEM_VISUAL_SIZE + PADDING) * 5.5f32 + PADDING,
-        0f32,
-    );
-    q_inventory.for_each_mut(|mut inventory| {
-        inventory.positions = positions_from_anchor_point(anchor_point);
-    });
+        .insert((rng, pity));
 }
 
-#[derive(Component, Clone, Copy, Hash, Eq, PartialEq)]
+#[derive(Component, Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Building {
     mesh: BuildingMesh,
     size: BuildingSize,
     color: BuildingColor,
 }
 
-#[derive(Clone, Copy, Hash, Eq, PartialEq)]
+impl Building {
+    pub fn new(mesh: BuildingMesh, size: BuildingSize, color: BuildingColor) -> Self {
+        Self { mesh, size, color }
+    }
+
+    /// Every possible building
+    pub fn all() -> impl Iterator<Item = Building> {
+        BuildingMesh::ALL.into_iter().flat_map(|mesh| {
+            BuildingSize::ALL.into_iter().flat_map(move |size| {
+                BuildingColor::ALL
+                    .into_iter()
+                    .map(move |color| Building::new(mesh, size, color))
+            })
+        })
+    }
+
+    pub fn mesh(&self) -> BuildingMesh {
+        self.mesh
+    }
+
+    pub fn size(&self) -> BuildingSize {
+        self.size
+    }
+
+    pub fn color(&self) -> BuildingColor {
+        self.color
+    }
+
+    /// Triangles aim at the sky too, see [`crate::primitives::view::Airborne`]
+    pub fn is_anti_air(&self) -> bool {
+        self.mesh == BuildingMesh::Triangle
+    }
+
+    pub fn has_rare_color(&self) -> bool {
+        matches!(self.color, BuildingColor::Pink | BuildingColor::Blue)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub enum BuildingMesh {
     Triangle,
     Circle,
     Quad,
 }
-#[derive(Clone, Copy, Hash, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub enum BuildingSize {
     Small,
     Medium,
     Big,
 }
-#[derive(Clone, Copy, Hash, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub enum BuildingColor {
     Black,
     White,
@@ -235,6 +419,28 @@ pub enum BuildingColor {
     Blue,
 }
 
+impl BuildingMesh {
+    pub const ALL: [BuildingMesh; 3] = [
+        BuildingMesh::Triangle,
+        BuildingMesh::Circle,
+        BuildingMesh::Quad,
+    ];
+}
+
+impl BuildingSize {
+    pub const ALL: [BuildingSize; 3] =
+        [BuildingSize::Small, BuildingSize::Medium, BuildingSize::Big];
+}
+
+impl BuildingColor {
+    pub const ALL: [BuildingColor; 4] = [
+        BuildingColor::Black,
+        BuildingColor::White,
+        BuildingColor::Pink,
+        BuildingColor::Blue,
+    ];
+}
+
 impl inventory::ItemSpriteBuilder for Building {
     type C = BuildingItemSpriteBuilder;
     fn build_sprite(&self) -> Self::C {
@@ -261,36 +467,73 @@ impl EntityCommand for BuildingItemSpriteBuilder {
     }
 }
 
-pub fn get_random_building(rng: &mut crate::random::RandomDeterministic) -> Building {
-    let choices_mesh = [
-        (BuildingMesh::Triangle, 2),
-        (BuildingMesh::Circle, 2),
-        (BuildingMesh::Quad, 2),
-    ];
-    let choices_size = [
-        (BuildingSize::Big, 1),
-        (BuildingSize::Medium, 2),
-        (BuildingSize::Small, 1),
-    ];
-    let choices_color = [
-        (BuildingColor::Black, 5),
-        (BuildingColor::White, 5),
-        (BuildingColor::Pink, 1),
-        (BuildingColor::Blue, 1),
-    ];
-    let building = Building {
-        mesh: choices_mesh
-            .choose_weighted(&mut rng.random, |i| i.1)
-            .unwrap()
-            .0,
-        size: choices_size
-            .choose_weighted(&mut rng.random, |i| i.1)
-            .unwrap()
-            .0,
-        color: choices_color
-            .choose_weighted(&mut rng.random, |i| i.1)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loot::LootTable;
+
+    fn draw(world: &mut World, entity: Entity) -> Building {
+        let mut entity = world.entity_mut(entity);
+        let mut pity = entity.take::<LootPity>().unwrap();
+        let building = LootTable::default().draw(
+            0,
+            &mut entity.get_mut::<RandomDeterministic>().unwrap(),
+            &mut pity,
+        );
+        entity.insert(pity);
+        building
+    }
+
+    fn spawn_inventory(world: &mut World) -> Entity {
+        let entity = world
+            .spawn((RandomDeterministic::new_from_seed(7), LootPity::default()))
+            .id();
+        let items = (0..3)
+            .map(|_| {
+                let building = draw(world, entity);
+                world.spawn(building).id()
+            })
+            .collect();
+        SpawnInventory::<Building>::new(
+            items,
+            inventory::InventoryConfiguration {
+                capacity: 3,
+                slots: 2,
+                preview: 1,
+                layout: InventoryLayout::Vertical { spacing: 1.0 },
+                hold_position: Vec3::ZERO,
+            },
+        )
+        .apply(entity, world);
+        entity
+    }
+
+    #[test]
+    fn restored_inventory_draws_the_same_buildings() {
+        let mut world = World::new();
+        let entity = spawn_inventory(&mut world);
+        let saved = SavedBuildingInventory::save(&mut world).unwrap();
+        let saved: SavedBuildingInventory =
+            ron::from_str(&ron::to_string(&saved).unwrap()).unwrap();
+
+        let mut restored_world = World::new();
+        let restored_entity = spawn_inventory(&mut restored_world);
+        restored_world
+            .get_mut::<RandomDeterministic>(restored_entity)
             .unwrap()
-            .0,
-    };
-    building
+            .random
+            .set_word_pos(0);
+        saved.clone().restore(&mut restored_world);
+        assert_eq!(
+            SavedBuildingInventory::save(&mut restored_world).unwrap(),
+            saved
+        );
+
+        for _ in 0..20 {
+            assert_eq!(
+                draw(&mut world, entity),
+                draw(&mut restored_world, restored_entity)
+            );
+        }
+    }
 }
@@ -0,0 +1,343 @@
+use std::{collections::BTreeMap, path::Path};
+
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    balance::BalanceConfig,
+    buildings::BuildingColor,
+    difficulty::DifficultySettings,
+    game_over::GameOver,
+    loot::BuildingPattern,
+    random::{RngStream, RunSeed, SeedSource},
+    GameState,
+};
+
+pub struct ChallengePlugin;
+
+/// This plugin runs the daily challenge: picking the daily seed in the menu also applies a few
+/// modifiers derived from the date to the balance.
+impl Plugin for ChallengePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActiveChallenge>()
+            .init_resource::<ChallengeResult>()
+            .add_systems(OnEnter(GameState::Playing), reset_result)
+            .add_systems(Update, follow_run_seed);
+    }
+}
+
+pub struct HighScoresPlugin;
+
+/// When a daily challenge ends, its score is compared to the best one of the day at the same
+/// difficulty, kept in [`HIGH_SCORES_FILE`]. The file is written outside of the ticks, and only
+/// by the game, not by the headless simulation
+impl Plugin for HighScoresPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            record_score
+                .run_if(resource_added::<GameOver>())
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+/// where the best daily scores are kept, relative to the working directory
+pub const HIGH_SCORES_FILE: &str = "daily_scores.ron";
+const MODIFIERS_PER_DAY: usize = 2;
+const SCORE_PER_KILL: u32 = 10;
+const SCORE_PER_WAVE: u32 = 100;
+
+/// A twist on the rules for the day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeModifier {
+    NoColor(BuildingColor),
+    FastEnemies,
+    ToughEnemies,
+    FastOverloadDecay,
+    ExpensiveRerolls,
+}
+
+impl ChallengeModifier {
+    const ALL: [ChallengeModifier; 6] = [
+        ChallengeModifier::NoColor(BuildingColor::Blue),
+        ChallengeModifier::NoColor(BuildingColor::Pink),
+        ChallengeModifier::FastEnemies,
+        ChallengeModifier::ToughEnemies,
+        ChallengeModifier::FastOverloadDecay,
+        ChallengeModifier::ExpensiveRerolls,
+    ];
+
+    pub fn apply(&self, config: &mut BalanceConfig) {
+        let enemies = &mut config.enemies;
+        match self {
+            ChallengeModifier::NoColor(color) => config.loot.banned.push(BuildingPattern {
+                color: vec![*color],
+                ..default()
+            }),
+            ChallengeModifier::FastEnemies => {
+                for stats in [
+                    &mut enemies.drone,
+                    &mut enemies.runner,
+                    &mut enemies.tank,
+                    &mut enemies.flyer,
+                ] {
+                    stats.velocity *= 2.;
+                }
+            }
+            ChallengeModifier::ToughEnemies => {
+                for stats in [
+                    &mut enemies.drone,
+                    &mut enemies.runner,
+                    &mut enemies.tank,
+                    &mut enemies.flyer,
+                ] {
+                    stats.health *= 1.5;
+                }
+            }
+            ChallengeModifier::FastOverloadDecay => config.overload.decay_per_second *= 2.,
+            ChallengeModifier::ExpensiveRerolls => config.economy.reroll_cost *= 2,
+        }
+    }
+}
+
+impl std::fmt::Display for ChallengeModifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChallengeModifier::NoColor(color) => write!(f, "no {:?} buildings", color),
+            ChallengeModifier::FastEnemies => write!(f, "double-speed enemies"),
+            ChallengeModifier::ToughEnemies => write!(f, "tougher enemies"),
+            ChallengeModifier::FastOverloadDecay => write!(f, "overload decays 2x"),
+            ChallengeModifier::ExpensiveRerolls => write!(f, "rerolls cost 2x"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DailyChallenge {
+    /// days since the unix epoch
+    pub day: u64,
+    pub modifiers: Vec<ChallengeModifier>,
+}
+
+impl DailyChallenge {
+    /// Everybody playing on the same day gets the same modifiers
+    pub fn for_day(day: u64) -> Self {
+        let mut rng = RunSeed::for_day(day).stream(RngStream::Challenge);
+        let modifiers = ChallengeModifier::ALL
+            .choose_multiple(&mut rng.random, MODIFIERS_PER_DAY)
+            .copied()
+            .collect();
+        Self { day, modifiers }
+    }
+
+    pub fn date(&self) -> String {
+        date_of_day(self.day)
+    }
+}
+
+/// The `YYYY-MM-DD` date of the `day`-th day since the unix epoch
+fn date_of_day(day: u64) -> String {
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = day as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day_of_month = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day_of_month)
+}
+
+/// The challenge of the current run, if it uses the daily seed
+#[derive(Resource, Debug, Default)]
+pub struct ActiveChallenge(pub Option<DailyChallenge>);
+
+impl ActiveChallenge {
+    pub fn apply(&self, mut config: BalanceConfig) -> BalanceConfig {
+        for modifier in self.0.iter().flat_map(|challenge| &challenge.modifiers) {
+            modifier.apply(&mut config);
+        }
+        config
+    }
+}
+
+/// The score of the current run once it ended
+#[derive(Resource, Debug, Default)]
+pub struct ChallengeResult(pub Option<u32>);
+
+pub fn score(kills: u32, waves: u32) -> u32 {
+    kills * SCORE_PER_KILL + waves * SCORE_PER_WAVE
+}
+
+/// The best score of each daily challenge played on this computer
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct HighScores {
+    /// best score by [`high_score_key`]
+    pub best: BTreeMap<String, u32>,
+}
+
+/// Scores only compare between runs of the same day played at the same difficulty
+pub fn high_score_key(date: &str, settings: &DifficultySettings) -> String {
+    let adaptive = if settings.adaptive { " adaptive" } else { "" };
+    format!("{} {:?}{}", date, settings.preset, adaptive)
+}
+
+#[derive(Debug, Error)]
+pub enum HighScoresError {
+    #[error("could not access the high score file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse the high score file: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("could not write the high score file: {0}")]
+    Write(#[from] ron::Error),
+}
+
+impl HighScores {
+    /// Reads the scores from `path`, a missing file has no scores
+    pub fn load(path: &Path) -> Result<Self, HighScoresError> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(ron::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), HighScoresError> {
+        let content = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Keeps `score` if it beats the best one under `key`, returns whether it did
+    pub fn record(&mut self, key: String, score: u32) -> bool {
+        let best = self.best.entry(key).or_insert(0);
+        let beaten = score > *best;
+        *best = (*best).max(score);
+        beaten
+    }
+}
+
+fn follow_run_seed(seed: Res<RunSeed>, mut challenge: ResMut<ActiveChallenge>) {
+    if !seed.is_changed() {
+        return;
+    }
+    let new_challenge = match seed.source {
+        SeedSource::Daily(day) => Some(DailyChallenge::for_day(day)),
+        SeedSource::Random | SeedSource::Typed => None,
+    };
+    if challenge.0 != new_challenge {
+        challenge.0 = new_challenge;
+    }
+}
+
+fn reset_result(mut result: ResMut<ChallengeResult>) {
+    result.0 = None;
+}
+
+pub(crate) fn record_score(
+    game_over: Res<GameOver>,
+    challenge: Res<ActiveChallenge>,
+    settings: Res<DifficultySettings>,
+    mut result: ResMut<ChallengeResult>,
+) {
+    let Some(challenge) = &challenge.0 else {
+        return;
+    };
+    let run_score = score(game_over.kills, game_over.waves);
+    result.0 = Some(run_score);
+
+    let key = high_score_key(&challenge.date(), &settings);
+    let path = Path::new(HIGH_SCORES_FILE);
+    let recorded = HighScores::load(path).and_then(|mut scores| {
+        let beaten = scores.record(key.clone(), run_score);
+        scores.save(path)?;
+        Ok((beaten, scores.best[&key]))
+    });
+    match recorded {
+        Ok((true, _)) => info!("Daily challenge {}: new best score {}", key, run_score),
+        Ok((false, best)) => info!(
+            "Daily challenge {}: score {} (best {})",
+            key, run_score, best
+        ),
+        Err(e) => error!("Could not record the daily challenge score: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difficulty::Difficulty;
+
+    #[test]
+    fn challenges_depend_on_the_day() {
+        assert_eq!(
+            DailyChallenge::for_day(20745),
+            DailyChallenge::for_day(20745)
+        );
+        let modifiers = DailyChallenge::for_day(20745).modifiers;
+        assert_eq!(modifiers.len(), MODIFIERS_PER_DAY);
+        assert_ne!(modifiers[0], modifiers[1]);
+        assert!((20745..20775).any(|day| DailyChallenge::for_day(day).modifiers != modifiers));
+    }
+
+    #[test]
+    fn days_convert_to_dates() {
+        assert_eq!(date_of_day(0), "1970-01-01");
+        assert_eq!(date_of_day(11016), "2000-02-29");
+        assert_eq!(date_of_day(20745), "2026-10-19");
+    }
+
+    #[test]
+    fn modifiers_change_the_balance() {
+        let challenge = ActiveChallenge(Some(DailyChallenge {
+            day: 0,
+            modifiers: vec![
+                ChallengeModifier::NoColor(BuildingColor::Blue),
+                ChallengeModifier::FastEnemies,
+            ],
+        }));
+        let base = BalanceConfig::default();
+        let config = challenge.apply(base.clone());
+        assert_eq!(
+            config.enemies.tank.velocity,
+            base.enemies.tank.velocity * 2.
+        );
+        assert!(config
+            .loot
+            .distribution(0)
+            .iter()
+            .all(|(building, _)| building.color() != BuildingColor::Blue));
+        assert_eq!(ActiveChallenge(None).apply(base.clone()), base);
+    }
+
+    #[test]
+    fn high_scores_keep_the_best_of_each_day() {
+        let path = std::env::temp_dir().join(format!("daily_scores_{}.ron", std::process::id()));
+        let normal = DifficultySettings::default();
+        let hard = DifficultySettings {
+            preset: Difficulty::Hard,
+            adaptive: false,
+        };
+        let mut scores = HighScores::load(&path).unwrap();
+        assert!(scores.record(high_score_key("2026-10-19", &normal), 300));
+        assert!(!scores.record(high_score_key("2026-10-19", &normal), 200));
+        assert!(scores.record(high_score_key("2026-10-19", &hard), 200));
+        assert!(scores.record(high_score_key("2026-10-20", &normal), 100));
+        scores.save(&path).unwrap();
+
+        let loaded = HighScores::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, scores);
+        assert_eq!(loaded.best[&high_score_key("2026-10-19", &normal)], 300);
+    }
+}
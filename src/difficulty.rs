@@ -0,0 +1,316 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashSet};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    entities::{
+        crystal::CrystalTouched,
+        enemy::{EnemyKind, EventSpawnedEnemy},
+        portal::EventOpenedPortal,
+    },
+    overload::Overload,
+    random::RandomDeterministic,
+    tick::GameplaySet,
+    GameState,
+};
+
+pub struct DifficultyPlugin;
+
+/// This plugin holds the difficulty picked in the menu.
+/// The preset scales the balance (see [`crate::balance::DifficultyPresets`]), later waves bring
+/// flyers, and the optional adaptive mode watches how the last waves went to make the next ones
+/// bigger or smaller.
+/// A wave is made of the enemies coming out of one portal.
+/// Run with `RUST_LOG=bevy_game::difficulty=debug` to follow its decisions.
+impl Plugin for DifficultyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DifficultySettings>()
+            .init_resource::<AdaptiveDifficulty>()
+            .init_resource::<NextWave>()
+            .init_resource::<Wave>()
+            .add_systems(OnEnter(GameState::Playing), reset_waves)
+            .add_systems(
+                FixedUpdate,
+                (count_waves, track_current_wave, adapt_next_wave)
+                    .chain()
+                    .in_set(GameplaySet::Waves)
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// how many waves the adaptive mode looks back at
+const WAVES_WATCHED: usize = 3;
+/// above this ratio of leaked enemies, the next waves get easier
+const MAX_LEAK_RATE: f32 = 0.2;
+/// under this average overload, the next waves get easier
+const LOW_OVERLOAD: f32 = 0.25;
+/// above this average overload without any leak, the next waves get harder
+const HIGH_OVERLOAD: f32 = 0.6;
+const MIN_INTENSITY: i32 = -1;
+const MAX_INTENSITY: i32 = 4;
+/// the intensity from which the waves bring flyers
+const FLYING_INTENSITY: i32 = 2;
+/// the wave from which every wave brings a flyer, whatever the intensity
+const FLYING_WAVE: u32 = 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Difficulty {
+    Story,
+    #[default]
+    Normal,
+    Hard,
+    Nightmare,
+}
+
+impl Difficulty {
+    pub fn next(&self) -> Self {
+        match self {
+            Difficulty::Story => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Nightmare,
+            Difficulty::Nightmare => Difficulty::Story,
+        }
+    }
+
+    pub fn previous(&self) -> Self {
+        match self {
+            Difficulty::Story => Difficulty::Nightmare,
+            Difficulty::Normal => Difficulty::Story,
+            Difficulty::Hard => Difficulty::Normal,
+            Difficulty::Nightmare => Difficulty::Hard,
+        }
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct DifficultySettings {
+    pub preset: Difficulty,
+    /// let the game adjust the composition of the waves to how the player is doing
+    pub adaptive: bool,
+}
+
+/// Number of portals opened since the start of the run
+#[derive(Resource, Debug, Default)]
+pub struct Wave(pub u32);
+
+/// The enemies the next portal will spawn, in order
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct NextWave(pub Vec<EnemyKind>);
+
+impl Default for NextWave {
+    fn default() -> Self {
+        Self(wave_composition(0, 0))
+    }
+}
+
+impl NextWave {
+    /// The enemies in the order they come out: the tanks last, the others shuffled
+    pub fn draw(&self, random: &mut RandomDeterministic) -> Vec<EnemyKind> {
+        let mut enemies = self.0.clone();
+        enemies.sort_by_key(|&kind| kind == EnemyKind::Tank);
+        let others = enemies
+            .iter()
+            .filter(|&&kind| kind != EnemyKind::Tank)
+            .count();
+        enemies[..others].shuffle(&mut random.random);
+        enemies
+    }
+}
+
+/// The enemies of the `wave`-th wave, counting from 0.
+/// Bigger intensities bring more enemies and more tanks, a negative one removes the tank.
+/// Tanks always come last. From [`FLYING_WAVE`] one more enemy comes, and from there or from
+/// [`FLYING_INTENSITY`] the runners take to the air.
+fn wave_composition(intensity: i32, wave: u32) -> Vec<EnemyKind> {
+    let intensity = intensity.clamp(MIN_INTENSITY, MAX_INTENSITY);
+    let flying = wave >= FLYING_WAVE || intensity >= FLYING_INTENSITY;
+    let count = 2 + intensity.max(0) as usize + usize::from(wave >= FLYING_WAVE);
+    let tanks = if intensity < 0 {
+        0
+    } else {
+        1 + intensity as usize / 2
+    };
+    (0..count)
+        .map(|i| match i {
+            i if i >= count - tanks => EnemyKind::Tank,
+            i if i % 2 == 0 => EnemyKind::Drone,
+            _ if flying => EnemyKind::Flyer,
+            _ => EnemyKind::Runner,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default)]
+struct WaveReport {
+    spawned: u32,
+    /// enemies that reached the crystal
+    leaked: HashSet<Entity>,
+    /// overload when the next wave started
+    overload: f32,
+}
+
+/// What the adaptive mode knows about the current run
+#[derive(Resource, Debug, Default)]
+pub struct AdaptiveDifficulty {
+    intensity: i32,
+    current: WaveReport,
+    history: VecDeque<WaveReport>,
+}
+
+/// How the intensity should change after the watched waves, and why
+fn intensity_change(history: &VecDeque<WaveReport>) -> (i32, &'static str) {
+    let spawned: u32 = history.iter().map(|wave| wave.spawned).sum();
+    let leaked: usize = history.iter().map(|wave| wave.leaked.len()).sum();
+    if spawned == 0 {
+        return (0, "nothing spawned yet");
+    }
+    let leak_rate = leaked as f32 / spawned as f32;
+    let overload = history.iter().map(|wave| wave.overload).sum::<f32>() / history.len() as f32;
+
+    if leak_rate > MAX_LEAK_RATE {
+        (-1, "too many leaks")
+    } else if overload < LOW_OVERLOAD {
+        (-1, "overload running low")
+    } else if leaked == 0 && overload > HIGH_OVERLOAD {
+        (1, "no leak and overload comfortable")
+    } else {
+        (0, "holding steady")
+    }
+}
+
+fn reset_waves(
+    mut adaptive: ResMut<AdaptiveDifficulty>,
+    mut next_wave: ResMut<NextWave>,
+    mut wave: ResMut<Wave>,
+) {
+    *adaptive = AdaptiveDifficulty::default();
+    *next_wave = NextWave::default();
+    wave.0 = 0;
+}
+
+fn count_waves(mut opened_portals: EventReader<EventOpenedPortal>, mut wave: ResMut<Wave>) {
+    let opened = opened_portals.read().count() as u32;
+    if opened > 0 {
+        wave.0 += opened;
+    }
+}
+
+fn track_current_wave(
+    mut spawned: EventReader<EventSpawnedEnemy>,
+    mut crystal_touched: EventReader<CrystalTouched>,
+    mut adaptive: ResMut<AdaptiveDifficulty>,
+) {
+    adaptive.current.spawned += spawned.read().count() as u32;
+    for touched in crystal_touched.read() {
+        adaptive.current.leaked.insert(touched.enemy);
+    }
+}
+
+/// Composes the next wave once a portal opened, at an intensity the adaptive mode may change
+fn adapt_next_wave(
+    mut opened_portals: EventReader<EventOpenedPortal>,
+    settings: Res<DifficultySettings>,
+    wave: Res<Wave>,
+    q_overload: Query<&Overload>,
+    mut adaptive: ResMut<AdaptiveDifficulty>,
+    mut next_wave: ResMut<NextWave>,
+) {
+    if opened_portals.read().count() == 0 {
+        return;
+    }
+    if settings.adaptive && adaptive.current.spawned > 0 {
+        adapt_intensity(&mut adaptive, q_overload.get_single().ok());
+    }
+    next_wave.0 = wave_composition(adaptive.intensity, wave.0);
+    debug!("Next wave {:?}", next_wave.0);
+}
+
+fn adapt_intensity(adaptive: &mut AdaptiveDifficulty, overload: Option<&Overload>) {
+    let mut finished = std::mem::take(&mut adaptive.current);
+    finished.overload = overload.map_or(0., |overload| overload.0);
+    adaptive.history.push_back(finished);
+    if adaptive.history.len() > WAVES_WATCHED {
+        adaptive.history.pop_front();
+    }
+
+    let (change, reason) = intensity_change(&adaptive.history);
+    let previous = adaptive.intensity;
+    adaptive.intensity = (previous + change).clamp(MIN_INTENSITY, MAX_INTENSITY);
+    debug!(
+        "Adaptive difficulty: {}, intensity {} -> {}",
+        reason, previous, adaptive.intensity
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::{RngStream, RunSeed};
+
+    fn wave(spawned: u32, leaked: u32, overload: f32) -> WaveReport {
+        WaveReport {
+            spawned,
+            leaked: (0..leaked).map(Entity::from_raw).collect(),
+            overload,
+        }
+    }
+
+    #[test]
+    fn default_wave_is_a_drone_then_a_tank() {
+        assert_eq!(
+            wave_composition(0, 0),
+            vec![EnemyKind::Drone, EnemyKind::Tank]
+        );
+        assert!(!wave_composition(MIN_INTENSITY, 0).contains(&EnemyKind::Tank));
+        assert!(!wave_composition(FLYING_INTENSITY - 1, 0).contains(&EnemyKind::Flyer));
+        assert!(wave_composition(MAX_INTENSITY, 0).contains(&EnemyKind::Flyer));
+        assert_eq!(
+            wave_composition(MAX_INTENSITY + 10, 0).len(),
+            2 + MAX_INTENSITY as usize
+        );
+    }
+
+    #[test]
+    fn later_waves_bring_flyers_without_the_adaptive_mode() {
+        assert!(!wave_composition(0, FLYING_WAVE - 1).contains(&EnemyKind::Flyer));
+        assert_eq!(
+            wave_composition(0, FLYING_WAVE),
+            vec![EnemyKind::Drone, EnemyKind::Flyer, EnemyKind::Tank]
+        );
+        assert!(wave_composition(MIN_INTENSITY, FLYING_WAVE).contains(&EnemyKind::Flyer));
+    }
+
+    #[test]
+    fn waves_keep_their_enemies_and_the_tanks_last() {
+        let next_wave = NextWave(wave_composition(MAX_INTENSITY, FLYING_WAVE));
+        let mut random = RunSeed::typed(3).stream(RngStream::Waves);
+        let orders: Vec<Vec<EnemyKind>> = (0..10).map(|_| next_wave.draw(&mut random)).collect();
+        for order in &orders {
+            let mut sorted = order.clone();
+            sorted.sort_by_key(|&kind| kind as u8);
+            let mut expected = next_wave.0.clone();
+            expected.sort_by_key(|&kind| kind as u8);
+            assert_eq!(sorted, expected);
+            assert_eq!(order.last(), Some(&EnemyKind::Tank));
+        }
+        assert!(orders.iter().any(|order| *order != orders[0]));
+    }
+
+    #[test]
+    fn intensity_follows_how_the_player_is_doing() {
+        let leaking = VecDeque::from([wave(4, 2, 0.7), wave(4, 0, 0.7)]);
+        assert_eq!(intensity_change(&leaking).0, -1);
+
+        let low_overload = VecDeque::from([wave(4, 0, 0.1)]);
+        assert_eq!(intensity_change(&low_overload).0, -1);
+
+        let comfortable = VecDeque::from([wave(4, 0, 0.8), wave(4, 0, 0.7)]);
+        assert_eq!(intensity_change(&comfortable).0, 1);
+
+        let steady = VecDeque::from([wave(10, 1, 0.5)]);
+        assert_eq!(intensity_change(&steady).0, 0);
+    }
+}
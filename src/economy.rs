@@ -0,0 +1,292 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{
+    balance::{Balance, EconomyBalance},
+    buildings::Building,
+    entities::enemy::EnemyKind,
+    primitives::destructible::{apply_damage, destroy_if_no_health, EventDestroyed},
+    tick::GameplaySet,
+    GameState,
+};
+
+pub struct EconomyPlugin;
+
+/// This plugin handles the energy, the currency used to pay for buildings.
+/// Energy is earned by killing enemies and through interest on the current balance.
+impl Plugin for EconomyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Energy>()
+            .init_resource::<Ledger>()
+            .add_event::<NotEnoughEnergy>()
+            .add_systems(
+                OnEnter(GameState::Playing),
+                (reset_economy, spawn_energy_display),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    // rewards need the destroyed enemy to still be there to know its kind
+                    reward_kills
+                        .after(apply_damage)
+                        .before(destroy_if_no_health)
+                        .in_set(GameplaySet::Damage),
+                    pay_interest.in_set(GameplaySet::Outcome),
+                )
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                Update,
+                update_energy_display.run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+/// how long the last transaction stays displayed next to the balance
+const TRANSACTION_DISPLAY_DURATION: Duration = Duration::from_secs(2);
+
+/// how many of the latest transactions the ledger keeps
+const LEDGER_LENGTH: usize = 32;
+
+/// The currency used to pay for buildings
+#[derive(Resource, Debug, Default)]
+pub struct Energy(u32);
+
+impl Energy {
+    pub fn balance(&self) -> u32 {
+        self.0
+    }
+
+    /// Sets the balance without recording a transaction, e.g. when restoring a saved run
+    pub fn restore(&mut self, balance: u32) {
+        self.0 = balance;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransactionReason {
+    Kill(EnemyKind),
+    Build(Building),
+    Reroll,
+    ClearObstacle,
+    Interest,
+}
+
+impl TransactionReason {
+    fn label(&self) -> &'static str {
+        match self {
+            TransactionReason::Kill(_) => "kill",
+            TransactionReason::Build(_) => "build",
+            TransactionReason::Reroll => "reroll",
+            TransactionReason::ClearObstacle => "clear",
+            TransactionReason::Interest => "interest",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    /// elapsed time since startup when the transaction happened
+    pub at: Duration,
+    /// positive when earning energy, negative when spending it
+    pub amount: i64,
+    pub reason: TransactionReason,
+}
+
+/// The latest energy transactions of the current run, oldest first,
+/// and the totals of the whole run
+#[derive(Resource, Debug, Default)]
+pub struct Ledger {
+    /// at most [`LEDGER_LENGTH`] transactions, the oldest are dropped first
+    transactions: VecDeque<Transaction>,
+    kills: u32,
+}
+
+impl Ledger {
+    pub fn transactions(&self) -> impl DoubleEndedIterator<Item = &Transaction> {
+        self.transactions.iter()
+    }
+
+    /// Enemies killed since the start of the run
+    pub fn kills(&self) -> u32 {
+        self.kills
+    }
+
+    /// Starts over from the kills of a saved run
+    pub fn restore(&mut self, kills: u32) {
+        self.transactions.clear();
+        self.kills = kills;
+    }
+
+    fn record(&mut self, transaction: Transaction) {
+        if matches!(transaction.reason, TransactionReason::Kill(_)) {
+            self.kills += 1;
+        }
+        if self.transactions.len() == LEDGER_LENGTH {
+            self.transactions.pop_front();
+        }
+        self.transactions.push_back(transaction);
+    }
+}
+
+/// Sent when the player tries to pay for something they can't afford
+#[derive(Event, Debug)]
+pub struct NotEnoughEnergy {
+    pub cost: u32,
+}
+
+/// Gives access to the energy while keeping the ledger up to date
+#[derive(SystemParam)]
+pub struct Wallet<'w> {
+    energy: ResMut<'w, Energy>,
+    ledger: ResMut<'w, Ledger>,
+    time: Res<'w, Time>,
+}
+
+impl<'w> Wallet<'w> {
+    pub fn earn(&mut self, amount: u32, reason: TransactionReason) {
+        self.energy.0 += amount;
+        self.record(amount as i64, reason);
+    }
+
+    /// Spends `amount` if the balance allows it, returns false otherwise
+    pub fn try_spend(&mut self, amount: u32, reason: TransactionReason) -> bool {
+        if self.energy.0 < amount {
+            return false;
+        }
+        self.energy.0 -= amount;
+        self.record(-(amount as i64), reason);
+        true
+    }
+
+    fn record(&mut self, amount: i64, reason: TransactionReason) {
+        let at = self.time.elapsed();
+        self.ledger.record(Transaction { at, amount, reason });
+    }
+}
+
+fn interest(energy: u32, economy: &EconomyBalance) -> u32 {
+    ((energy as f32 * economy.interest_rate) as u32).min(economy.max_interest)
+}
+
+fn reset_economy(mut energy: ResMut<Energy>, mut ledger: ResMut<Ledger>, balance: Res<Balance>) {
+    energy.0 = balance.economy.starting_energy;
+    ledger.restore(0);
+}
+
+fn reward_kills(
+    mut events: EventReader<EventDestroyed>,
+    enemies: Query<&EnemyKind>,
+    mut wallet: Wallet,
+    balance: Res<Balance>,
+) {
+    for event in events.read() {
+        if let Ok(&kind) = enemies.get(event.entity) {
+            let reward = balance.enemies.stats(kind).reward;
+            wallet.earn(reward, TransactionReason::Kill(kind));
+        }
+    }
+}
+
+pub(crate) fn pay_interest(
+    mut wallet: Wallet,
+    balance: Res<Balance>,
+    time: Res<Time>,
+    mut since_last_interest: Local<Duration>,
+) {
+    *since_last_interest += time.delta();
+    if since_last_interest.as_secs_f32() < balance.economy.interest_period_seconds {
+        return;
+    }
+    *since_last_interest = Duration::ZERO;
+
+    let amount = interest(wallet.energy.balance(), &balance.economy);
+    if amount > 0 {
+        wallet.earn(amount, TransactionReason::Interest);
+    }
+}
+
+#[derive(Component)]
+struct EnergyDisplay;
+
+const ENERGY_COLOR: Color = Color::rgb(0.2, 0.9, 1.0);
+const NOT_ENOUGH_ENERGY_COLOR: Color = Color::rgb(1.0, 0.2, 0.3);
+
+fn spawn_energy_display(mut commands: Commands) {
+    let style = TextStyle {
+        font_size: 28.0,
+        color: ENERGY_COLOR,
+        ..default()
+    };
+    commands.spawn((
+        TextBundle::from_sections([
+            TextSection::new("", style.clone()),
+            TextSection::new("", style),
+        ])
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        EnergyDisplay,
+        Name::new("Energy display"),
+    ));
+}
+
+fn update_energy_display(
+    energy: Res<Energy>,
+    ledger: Res<Ledger>,
+    time: Res<Time>,
+    mut not_enough_energy: EventReader<NotEnoughEnergy>,
+    mut q_display: Query<&mut Text, With<EnergyDisplay>>,
+    mut shown_at: Local<Duration>,
+) {
+    let Ok(mut text) = q_display.get_single_mut() else {
+        return;
+    };
+    text.sections[0].value = format!("Energy: {}", energy.balance());
+
+    // next to the balance, show either why the last purchase was refused or the last transaction
+    if let Some(refused) = not_enough_energy.read().last() {
+        text.sections[1].value = format!("  (needs {})", refused.cost);
+        text.sections[1].style.color = NOT_ENOUGH_ENERGY_COLOR;
+        *shown_at = time.elapsed();
+    } else if let Some(last) = ledger
+        .transactions()
+        .next_back()
+        .filter(|_| ledger.is_changed())
+    {
+        text.sections[1].value = format!("  {:+} {}", last.amount, last.reason.label());
+        text.sections[1].style.color = ENERGY_COLOR;
+        *shown_at = last.at;
+    } else if time.elapsed().saturating_sub(*shown_at) > TRANSACTION_DISPLAY_DURATION {
+        text.sections[1].value.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ledger_keeps_the_latest_transactions_and_every_kill() {
+        let mut ledger = Ledger::default();
+        ledger.restore(3);
+        for i in 0..LEDGER_LENGTH as u64 * 2 {
+            ledger.record(Transaction {
+                at: Duration::from_secs(i),
+                amount: 1,
+                reason: TransactionReason::Kill(EnemyKind::default()),
+            });
+        }
+
+        assert_eq!(ledger.transactions().count(), LEDGER_LENGTH);
+        assert_eq!(
+            ledger.transactions().next().unwrap().at,
+            Duration::from_secs(LEDGER_LENGTH as u64)
+        );
+        assert_eq!(ledger.kills(), 3 + LEDGER_LENGTH as u32 * 2);
+    }
+}
@@ -0,0 +1,281 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+use bevy_mod_picking::prelude::PointerButton;
+use hexx::Hex;
+
+use crate::{
+    actions::game_control::GameControl,
+    grid::{despawn_grid, setup, HexClicked, HexGrid, MapDefinition, MapTile, Terrain, MAP_FILE},
+    loading::TextureAssets,
+    GameState,
+};
+
+pub struct EditorPlugin;
+
+/// This plugin lets the designers author the map the runs are played on, from the menu.
+/// A left click puts the picked tile on a hex and a right click clears it; 1, 2 and 3 pick
+/// a crystal, a portal or a blocker and 0 an obstacle, 4 to 9 pick a terrain to paint the ground of the hexes
+/// with. + and - resize the map, F5 exports it to [`MAP_FILE`]
+/// and F9 imports it back. The next runs are played on the edited map once back to the menu,
+/// which is only allowed when the enemies of every portal can reach a crystal
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Brush>()
+            .add_systems(OnEnter(GameState::Editor), (spawn_editor_hud, open_map))
+            .add_systems(
+                Update,
+                (
+                    pick_brush,
+                    paint,
+                    resize_map,
+                    export_map,
+                    import_map,
+                    leave_editor,
+                    (
+                        despawn_grid,
+                        despawn_markers,
+                        apply_deferred,
+                        setup,
+                        apply_deferred,
+                        spawn_markers,
+                    )
+                        .chain()
+                        .run_if(resource_changed::<MapDefinition>()),
+                    update_editor_hud,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Editor)),
+            )
+            .add_systems(
+                OnExit(GameState::Editor),
+                (despawn_grid, despawn_markers, despawn_editor_hud),
+            );
+    }
+}
+
+/// What a left click puts on a hex
+#[derive(Resource, Debug, Clone, Copy)]
+enum Brush {
+    Tile(MapTile),
+    /// a right click brings the hex back to plain
+    Terrain(Terrain),
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self::Tile(MapTile::Blocker)
+    }
+}
+
+/// Shows a crystal or a portal of the map, the blockers and the obstacles are drawn by the grid
+#[derive(Component)]
+struct EditorMarker;
+
+#[derive(Component)]
+struct EditorHud;
+
+fn spawn_editor_hud(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 24.0,
+                color: Color::rgb(0.9, 0.9, 0.9),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        EditorHud,
+    ));
+}
+
+/// The grid is built from the map as soon as the editor opens
+fn open_map(mut map: ResMut<MapDefinition>) {
+    map.set_changed();
+}
+
+fn pick_brush(keyboard_input: Res<Input<KeyCode>>, mut brush: ResMut<Brush>) {
+    let brushes = [
+        (GameControl::CrystalBrush, Brush::Tile(MapTile::Crystal)),
+        (GameControl::PortalBrush, Brush::Tile(MapTile::Portal)),
+        (GameControl::BlockerBrush, Brush::Tile(MapTile::Blocker)),
+        (GameControl::ObstacleBrush, Brush::Tile(MapTile::Obstacle)),
+        (GameControl::PlainBrush, Brush::Terrain(Terrain::Plain)),
+        (GameControl::RoughBrush, Brush::Terrain(Terrain::Rough)),
+        (
+            GameControl::BoostPadBrush,
+            Brush::Terrain(Terrain::BoostPad),
+        ),
+        (GameControl::WaterBrush, Brush::Terrain(Terrain::Water)),
+        (GameControl::VoidBrush, Brush::Terrain(Terrain::Void)),
+        (
+            GameControl::EnergyNodeBrush,
+            Brush::Terrain(Terrain::EnergyNode),
+        ),
+    ];
+    for (control, picked) in brushes {
+        if control.just_pressed(&keyboard_input) {
+            *brush = picked;
+        }
+    }
+}
+
+fn paint(
+    mut clicks: EventReader<HexClicked>,
+    grid: Option<Res<HexGrid>>,
+    brush: Res<Brush>,
+    mut map: ResMut<MapDefinition>,
+) {
+    let Some(grid) = grid else {
+        return;
+    };
+    for click in clicks.read() {
+        let Some(hex) = grid.entity_to_hex(click.target) else {
+            continue;
+        };
+        let hex = [hex.x, hex.y];
+        let erase = match click.event.button {
+            PointerButton::Primary => false,
+            PointerButton::Secondary => true,
+            PointerButton::Middle => continue,
+        };
+        // the grid is only rebuilt when the map really changes
+        match *brush {
+            Brush::Tile(tile) => {
+                let tile = (!erase).then_some(tile);
+                if map.tile(hex) != tile {
+                    map.set(hex, tile);
+                }
+            }
+            Brush::Terrain(terrain) => {
+                let terrain = if erase { Terrain::Plain } else { terrain };
+                if map.terrain_at(hex) != terrain {
+                    map.set_terrain(hex, terrain);
+                }
+            }
+        }
+    }
+}
+
+fn resize_map(keyboard_input: Res<Input<KeyCode>>, mut map: ResMut<MapDefinition>) {
+    if GameControl::GrowMap.just_pressed(&keyboard_input) {
+        let radius = map.radius + 1;
+        map.resize(radius);
+    }
+    if GameControl::ShrinkMap.just_pressed(&keyboard_input) {
+        let radius = map.radius.saturating_sub(1);
+        map.resize(radius);
+    }
+}
+
+fn export_map(keyboard_input: Res<Input<KeyCode>>, map: Res<MapDefinition>) {
+    if !GameControl::ExportMap.just_pressed(&keyboard_input) {
+        return;
+    }
+    if let Err(e) = map.validate() {
+        error!("Not exporting the map: {}", e);
+        return;
+    }
+    match map.save(Path::new(MAP_FILE)) {
+        Ok(()) => info!("Map exported to {}", MAP_FILE),
+        Err(e) => error!("Could not export the map: {}", e),
+    }
+}
+
+fn import_map(keyboard_input: Res<Input<KeyCode>>, mut map: ResMut<MapDefinition>) {
+    if !GameControl::ImportMap.just_pressed(&keyboard_input) {
+        return;
+    }
+    // an invalid map is still imported, to be fixed
+    match MapDefinition::load(Path::new(MAP_FILE)) {
+        Ok(imported) => {
+            info!("Map imported from {}", MAP_FILE);
+            *map = imported;
+        }
+        Err(e) => error!("Could not import the map: {}", e),
+    }
+}
+
+fn leave_editor(
+    keyboard_input: Res<Input<KeyCode>>,
+    map: Res<MapDefinition>,
+    mut state: ResMut<NextState<GameState>>,
+) {
+    if GameControl::Back.just_pressed(&keyboard_input) && map.validate().is_ok() {
+        state.set(GameState::Menu);
+    }
+}
+
+fn spawn_markers(
+    mut commands: Commands,
+    map: Res<MapDefinition>,
+    grid: Res<HexGrid>,
+    asset_server: Res<AssetServer>,
+    texture_assets: Res<TextureAssets>,
+) {
+    let crystals = map
+        .crystals
+        .iter()
+        .map(|&hex| (hex, asset_server.load("textures/RandomBuildings/B10.png")));
+    let portals = map
+        .portals
+        .iter()
+        .map(|&hex| (hex, texture_assets.portal.clone_weak()));
+    for ([x, y], texture) in crystals.chain(portals) {
+        let position = grid.layout.hex_to_world_pos(Hex::new(x, y));
+        commands.spawn((
+            SpriteBundle {
+                transform: Transform::from_xyz(position.x, position.y, 0.0)
+                    .with_scale(Vec3::new(0.5, 0.5, 1.)),
+                texture,
+                ..default()
+            },
+            EditorMarker,
+        ));
+    }
+}
+
+fn despawn_markers(mut commands: Commands, q_markers: Query<Entity, With<EditorMarker>>) {
+    for marker in &q_markers {
+        commands.entity(marker).despawn();
+    }
+}
+
+fn update_editor_hud(
+    brush: Res<Brush>,
+    map: Res<MapDefinition>,
+    mut hud: Query<&mut Text, With<EditorHud>>,
+) {
+    if !brush.is_changed() && !map.is_changed() {
+        return;
+    }
+    let Ok(mut text) = hud.get_single_mut() else {
+        return;
+    };
+    let brush = match *brush {
+        Brush::Tile(tile) => format!("{:?}", tile),
+        Brush::Terrain(terrain) => format!("{:?} terrain", terrain),
+    };
+    let status = match map.validate() {
+        Ok(()) => "The map is playable, Esc goes back to the menu".to_string(),
+        Err(e) => format!("{}, fix it before going back", e),
+    };
+    text.sections[0].value = format!(
+        "Brush: {} (1 crystal, 2 portal, 3 blocker, 0 obstacle, right click clears)\n\
+         Terrain: 4 plain, 5 rough, 6 boost pad, 7 water, 8 void, 9 energy node\n\
+         Radius: {} (+/-)    F5: export to {}    F9: import\n{}",
+        brush, map.radius, MAP_FILE, status
+    );
+}
+
+fn despawn_editor_hud(mut commands: Commands, q_hud: Query<Entity, With<EditorHud>>) {
+    for hud in &q_hud {
+        commands.entity(hud).despawn_recursive();
+    }
+}
@@ -7,6 +7,7 @@ use crate::{
         movable::{move_towards_target, AutoMovable},
         target::{face_target, AutoLookAtTarget, OnTargetDespawned, Target},
     },
+    tick::{GameplaySet, Interpolated},
     GameState,
 };
 
@@ -15,11 +16,13 @@ pub(super) struct BulletPlugin;
 impl Plugin for BulletPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_systems(
-            Update,
+            FixedUpdate,
             (
                 move_towards_target::<Bullet, Enemy>,
                 face_target::<Bullet, Enemy, 3>,
             )
+                .chain()
+                .in_set(GameplaySet::Projectiles)
                 .run_if(in_state(GameState::Playing)),
         );
     }
@@ -34,6 +37,8 @@ pub struct SpawnBullet {
     pub velocity: f32,
     pub target: Entity,
     pub damage: f32,
+    /// the entity that fired the bullet
+    pub source: Entity,
 }
 
 impl Command for SpawnBullet {
@@ -44,13 +49,15 @@ impl Command for SpawnBullet {
                 asset_server.load("textures/Bullets/P02.png")
             });
 
+        let transform = Transform::from_xyz(self.position.x, self.position.y, 0.0)
+            .with_scale(Vec3::new(0.8, 0.8, 1.));
         world.spawn((
             SpriteBundle {
-                transform: Transform::from_xyz(self.position.x, self.position.y, 0.0)
-                    .with_scale(Vec3::new(0.8, 0.8, 1.)),
+                transform,
                 texture: image,
                 ..Default::default()
             },
+            Interpolated::at(transform.translation),
             Bullet,
             Target::new(self.target, OnTargetDespawned::DespawnSelf),
             AutoMovable {
@@ -58,7 +65,7 @@ impl Command for SpawnBullet {
                 follow_grid: false,
             },
             AutoLookAtTarget,
-            Damage::new(self.damage),
+            Damage::new(self.damage).with_source(self.source),
         ));
     }
 }
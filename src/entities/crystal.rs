@@ -6,16 +6,26 @@ use bevy::{
     transform::components::Transform,
 };
 
-use crate::{entities::enemy::Enemy, GameState};
+use crate::{
+    entities::enemy::Enemy,
+    grid::HexGrid,
+    tick::{GameplaySet, TickEventApp},
+    GameState,
+};
 
 pub(super) struct CrystalPlugin;
 
 impl Plugin for CrystalPlugin {
     fn build(&self, app: &mut App) {
-        app.add_event::<CrystalTouched>();
+        app.add_tick_event::<CrystalTouched>();
 
         app.add_systems(OnEnter(GameState::Playing), setup);
-        app.add_systems(Update, crystal_touched.run_if(in_state(GameState::Playing)));
+        app.add_systems(
+            FixedUpdate,
+            crystal_touched
+                .in_set(GameplaySet::Overload)
+                .run_if(in_state(GameState::Playing)),
+        );
     }
 }
 
@@ -23,29 +33,38 @@ impl Plugin for CrystalPlugin {
 pub struct Crystal;
 
 #[derive(Event, Debug)]
-pub struct CrystalTouched;
-
-pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    commands.spawn((
-        SpriteBundle {
-            transform: Transform::from_xyz(0.0, 0.0, 0.0).with_scale(Vec3::new(0.5, 0.5, 1.)),
-            texture: asset_server.load("textures/RandomBuildings/B10.png"),
-            ..Default::default()
-        },
-        Crystal,
-        Name::new("Crystal"),
-    ));
+pub struct CrystalTouched {
+    pub enemy: Entity,
 }
 
+pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>, grid: Res<HexGrid>) {
+    for &hex in &grid.crystals {
+        let position = grid.layout.hex_to_world_pos(hex);
+        commands.spawn((
+            SpriteBundle {
+                transform: Transform::from_xyz(position.x, position.y, 0.0)
+                    .with_scale(Vec3::new(0.5, 0.5, 1.)),
+                texture: asset_server.load("textures/RandomBuildings/B10.png"),
+                ..Default::default()
+            },
+            Crystal,
+            Name::new("Crystal"),
+        ));
+    }
+}
+
+/// The enemies, apart from the crystals
+type EnemyOnly = (With<Enemy>, Without<Crystal>);
+
 pub fn crystal_touched(
     mut crystal_touched: EventWriter<CrystalTouched>,
     q_crystals: Query<&Transform, (With<Crystal>, Without<Enemy>)>,
-    q_enemies: Query<&Transform, (With<Enemy>, Without<Crystal>)>,
+    q_enemies: Query<(Entity, &Transform), EnemyOnly>,
 ) {
-    for enemy in q_enemies.iter() {
+    for (enemy, enemy_transform) in q_enemies.iter() {
         for crystal in q_crystals.iter() {
-            if (crystal.translation - enemy.translation).length() < 10. {
-                crystal_touched.send(CrystalTouched);
+            if (crystal.translation - enemy_transform.translation).length() < 10. {
+                crystal_touched.send(CrystalTouched { enemy });
             }
         }
     }
@@ -1,6 +1,7 @@
 use std::time::Duration;
 
 use crate::{
+    balance::Balance,
     grid::{HexCell, HexGrid},
     primitives::{
         destructible::Destructible,
@@ -9,7 +10,10 @@ use crate::{
             face_target, AutoLookAtTarget, OnTargetDespawned, SourceWithTargetAccessor,
             SrcWithoutTargetQuery, Target,
         },
+        view::Airborne,
     },
+    random::{RandomStreams, RngStream},
+    tick::{GameplaySet, Interpolated, TickEventApp},
     GameState,
 };
 
@@ -18,36 +22,35 @@ use bevy::{
     math::Vec3,
     prelude::*,
     sprite::SpriteBundle,
-    time::common_conditions::on_timer,
 };
-use rand::{seq::SliceRandom, thread_rng};
+use hexx::Hex;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
 
 pub(super) struct EnemyPlugin;
 
-// TODO: make this a config resource
-const FIXED_TIMESTEP: f32 = 0.1;
-const TARGET_REACHED_EPSILON: f32 = 1.5;
-
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_event::<EventSpawnedEnemy>();
+        app.add_tick_event::<EventSpawnedEnemy>();
         app.add_systems(
-            Update,
+            FixedUpdate,
             (
                 face_target::<Enemy, HexCell, 0>,
+                adapt_speed_to_terrain,
                 move_towards_target::<Enemy, HexCell>,
                 move_towards_center,
+                fly_towards_crystal,
                 remove_reached_target,
             )
+                .chain()
+                .in_set(GameplaySet::Move)
+                .run_if(in_state(GameState::Playing)),
+        );
+        app.add_systems(
+            Update,
+            animate
+                .run_if(resource_exists::<EnemyAnimation>())
                 .run_if(in_state(GameState::Playing)),
-        )
-        .add_systems(
-            FixedUpdate,
-            (animate.run_if(
-                on_timer(Duration::from_secs_f32(FIXED_TIMESTEP))
-                    .and_then(resource_exists::<EnemyAnimation>()),
-            ))
-            .run_if(in_state(GameState::Playing)),
         );
     }
 }
@@ -55,63 +58,136 @@ impl Plugin for EnemyPlugin {
 #[derive(Component)]
 pub struct Enemy;
 
+const FLYER_COLOR: Color = Color::rgb(0.5, 1.0, 1.0);
+
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EnemyKind {
+    #[default]
+    Drone,
+    /// fast but fragile
+    Runner,
+    /// slow but hard to take down
+    Tank,
+    /// flies straight over the maze, only anti-air turrets can shoot it
+    Flyer,
+}
+
+impl EnemyKind {
+    fn scale(&self) -> f32 {
+        match self {
+            EnemyKind::Drone => 1.8,
+            EnemyKind::Runner => 1.4,
+            EnemyKind::Tank => 2.4,
+            EnemyKind::Flyer => 1.6,
+        }
+    }
+
+    pub fn is_airborne(&self) -> bool {
+        *self == EnemyKind::Flyer
+    }
+}
+
 #[derive(Resource)]
 pub struct EnemyAnimation(Vec<Handle<Image>>);
 
 #[derive(Event)]
-pub struct EventSpawnedEnemy(pub Entity);
+pub struct EventSpawnedEnemy;
 
 pub struct SpawnEnemyCmd {
     pub position: Vec2,
+    pub kind: EnemyKind,
 }
 
 impl Command for SpawnEnemyCmd {
     fn apply(self, world: &mut World) {
-        let mut textures: Vec<Handle<Image>> = Vec::new();
-        // TODO: make this a resource
-        for i in 0..9 {
-            let path = format!("textures/Ship_01/AnimIdle/ship01P000{}.png", i);
-            world.resource_scope(|_world, asset_server: Mut<AssetServer>| {
-                textures.push(asset_server.load(path));
-            });
-        }
-
-        let spawned_enemy = world
-            .spawn((
-                SpriteBundle {
-                    transform: Transform::from_xyz(self.position.x, self.position.y, 0.0)
-                        .with_scale(Vec3::new(1.8, 1.8, 1.)),
-                    texture: textures[0].clone(),
-                    ..Default::default()
-                },
-                Enemy,
-                Destructible {
-                    health: 2.,
-                    hitbox: 10.,
-                },
-                AutoMovable {
-                    // FIXME: when velocity is too high, the enemy can go through the target
-                    velocity: 20.,
-                    follow_grid: true,
-                },
-            ))
-            .id();
-
-        world.insert_resource(EnemyAnimation(textures));
+        let health = world.resource::<Balance>().enemies.stats(self.kind).health;
+        spawn_enemy(world, self.position, self.kind, health);
 
         let mut q_event: SystemState<EventWriter<EventSpawnedEnemy>> = SystemState::new(world);
 
         let mut event_writer = q_event.get_mut(world);
-        event_writer.send(EventSpawnedEnemy(spawned_enemy));
+        event_writer.send(EventSpawnedEnemy);
+    }
+}
+
+/// Puts back an enemy from a saved run, without counting it as a new spawn
+pub struct RestoreEnemyCmd {
+    pub position: Vec2,
+    pub kind: EnemyKind,
+    pub health: f32,
+}
+
+impl Command for RestoreEnemyCmd {
+    fn apply(self, world: &mut World) {
+        spawn_enemy(world, self.position, self.kind, self.health);
     }
 }
 
+fn spawn_enemy(world: &mut World, position: Vec2, kind: EnemyKind, health: f32) -> Entity {
+    let mut textures: Vec<Handle<Image>> = Vec::new();
+    // TODO: make this a resource
+    for i in 0..9 {
+        let path = format!("textures/Ship_01/AnimIdle/ship01P000{}.png", i);
+        world.resource_scope(|_world, asset_server: Mut<AssetServer>| {
+            textures.push(asset_server.load(path));
+        });
+    }
+
+    let balance = &world.resource::<Balance>().enemies;
+    let velocity = balance.stats(kind).velocity;
+    let hitbox = balance.hitbox;
+
+    // the flyers are drawn above the turrets
+    let (z, color) = if kind.is_airborne() {
+        (1.0, FLYER_COLOR)
+    } else {
+        (0.0, Color::WHITE)
+    };
+    let mut spawned_enemy = world.spawn((
+        SpriteBundle {
+            sprite: Sprite { color, ..default() },
+            transform: Transform::from_xyz(position.x, position.y, z).with_scale(Vec3::new(
+                kind.scale(),
+                kind.scale(),
+                1.,
+            )),
+            texture: textures[0].clone(),
+            ..Default::default()
+        },
+        Interpolated::at(position.extend(z)),
+        Enemy,
+        kind,
+        Destructible { health, hitbox },
+        AutoMovable {
+            // FIXME: when velocity is too high, the enemy can go through the target
+            velocity,
+            follow_grid: !kind.is_airborne(),
+        },
+    ));
+    if kind.is_airborne() {
+        spawned_enemy.insert(Airborne);
+    }
+    let spawned_enemy = spawned_enemy.id();
+
+    world.insert_resource(EnemyAnimation(textures));
+    spawned_enemy
+}
+
 pub fn animate(
     animations: Res<EnemyAnimation>,
     mut enemy_query: Query<&mut Handle<Image>, With<Enemy>>,
+    balance: Res<Balance>,
+    time: Res<Time>,
+    mut since_last_frame: Local<Duration>,
     // TODO: move that inside enemy to have a different animation for each enemy
     mut frame_id: Local<usize>,
 ) {
+    *since_last_frame += time.delta();
+    if since_last_frame.as_secs_f32() < balance.enemies.animation_frame_seconds {
+        return;
+    }
+    *since_last_frame = Duration::ZERO;
+
     enemy_query.for_each_mut(|mut sprite| {
         if *frame_id >= animations.0.len() {
             *sprite = animations.0[animations.0.len() * 2 - *frame_id - 1].clone();
@@ -122,19 +198,52 @@ pub fn animate(
     });
 }
 
+/// The enemies go at the speed of the terrain they are on
+pub fn adapt_speed_to_terrain(
+    mut enemies: Query<(&EnemyKind, &Transform, &mut AutoMovable), With<Enemy>>,
+    hexes: Query<&HexCell>,
+    grid: Res<HexGrid>,
+    balance: Res<Balance>,
+) {
+    for (kind, transform, mut movable) in &mut enemies {
+        // the flyers are above it all
+        if !movable.follow_grid {
+            continue;
+        }
+        let hex = grid.layout.world_pos_to_hex(transform.translation.xy());
+        let speed_factor = grid
+            .hex_to_entity(&hex)
+            .and_then(|&entity| hexes.get(entity).ok())
+            .map_or(1., |cell| cell.terrain.speed_factor());
+        let velocity = balance.enemies.stats(*kind).velocity * speed_factor;
+        if movable.velocity != velocity {
+            movable.velocity = velocity;
+        }
+    }
+}
+
 pub fn move_towards_center(
     mut commands: Commands,
     mut enemies: Query<SrcWithoutTargetQuery<Enemy, HexCell>>,
+    movables: Query<&AutoMovable>,
     hexes: Query<&HexCell>,
     grid: Res<HexGrid>,
+    mut streams: ResMut<RandomStreams>,
     _time: Res<Time>,
 ) {
+    let rng = &mut streams.get(RngStream::EnemyPathing).random;
     for enemy in &mut enemies {
+        if movables
+            .get(enemy.entity)
+            .is_ok_and(|movable| !movable.follow_grid)
+        {
+            continue;
+        }
         let mut all_neighbors = grid
             .layout
             .world_pos_to_hex(enemy.global_transform.compute_transform().translation.xy())
             .all_neighbors();
-        all_neighbors.shuffle(&mut thread_rng());
+        all_neighbors.shuffle(rng);
         let target_position = all_neighbors
             .iter()
             .filter_map(|hex| {
@@ -154,9 +263,41 @@ pub fn move_towards_center(
     }
 }
 
+/// Enemies that aren't heading anywhere yet
+type IdleEnemy = (With<Enemy>, Without<Target>);
+
+/// The enemies that don't follow the grid head straight to the closest crystal
+pub fn fly_towards_crystal(
+    mut commands: Commands,
+    enemies: Query<(Entity, &Transform, &AutoMovable), IdleEnemy>,
+    grid: Res<HexGrid>,
+) {
+    for (enemy, transform, movable) in &enemies {
+        if movable.follow_grid {
+            continue;
+        }
+        let position = transform.translation.xy();
+        let closest = grid
+            .crystals
+            .iter()
+            .min_by(|a, b| {
+                let distance = |hex: &Hex| grid.layout.hex_to_world_pos(*hex).distance(position);
+                distance(a).total_cmp(&distance(b))
+            })
+            .and_then(|crystal| grid.hex_to_entity(crystal));
+        if let Some(&crystal_hex) = closest {
+            commands.entity(enemy).insert((
+                Target::new(crystal_hex, OnTargetDespawned::DoNothing),
+                AutoLookAtTarget,
+            ));
+        }
+    }
+}
+
 pub fn remove_reached_target(
     mut commands: Commands,
     accessor: SourceWithTargetAccessor<Enemy, HexCell>,
+    balance: Res<Balance>,
 ) {
     for enemy in &accessor.srcs_query {
         if let Ok(target) = accessor.targets_query.get(enemy.target.entity) {
@@ -164,9 +305,67 @@ pub fn remove_reached_target(
                 .transform
                 .translation
                 .distance(enemy.global_transform.translation());
-            if distance <= TARGET_REACHED_EPSILON {
+            if distance <= balance.enemies.target_reached_epsilon {
                 commands.entity(enemy.entity).remove::<Target>();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        buildings::{Building, BuildingColor, BuildingMesh, BuildingSize},
+        testing::TestRun,
+    };
+
+    #[test]
+    fn an_enemy_walks_from_the_border_to_the_crystal() {
+        let mut run = TestRun::new();
+        let enemy = run.spawn_enemy(EnemyKind::Drone, Hex::new(9, -4));
+        let distance = |run: &TestRun| {
+            let transform = run.app.world.get::<Transform>(enemy).unwrap();
+            transform.translation.truncate().length()
+        };
+        let start = distance(&run);
+        run.advance(1.);
+        assert!(distance(&run) < start);
+        assert!(run.advance_until(60., |run| run.leaks() == 1));
+    }
+
+    #[test]
+    fn a_flyer_goes_straight_over_the_maze() {
+        let mut run = TestRun::new();
+        // a wall the walking enemies would have to go around
+        let building = Building::new(
+            BuildingMesh::Quad,
+            BuildingSize::Small,
+            BuildingColor::White,
+        );
+        for hex in [Hex::new(1, 0), Hex::new(2, -1), Hex::new(3, -1)] {
+            run.place_turret(hex, building);
+        }
+        let flyer = run.spawn_enemy(EnemyKind::Flyer, Hex::new(6, -3));
+        let start = run
+            .app
+            .world
+            .get::<Transform>(flyer)
+            .unwrap()
+            .translation
+            .xy();
+        for _ in 0..10 {
+            run.advance(1.);
+            let position = run
+                .app
+                .world
+                .get::<Transform>(flyer)
+                .unwrap()
+                .translation
+                .xy();
+            // still on the line from where it came out to the crystal
+            assert!(start.perp_dot(position).abs() < start.length());
+        }
+        assert!(run.advance_until(60., |run| run.leaks() == 1));
+    }
+}
@@ -2,15 +2,25 @@ use std::time::Duration;
 
 use bevy::{ecs::system::EntityCommand, prelude::*};
 
-use crate::{entities::enemy::SpawnEnemyCmd, loading::TextureAssets, GameState};
+use crate::{
+    difficulty::NextWave,
+    entities::enemy::{EnemyKind, SpawnEnemyCmd},
+    loading::TextureAssets,
+    random::{RandomStreams, RngStream},
+    tick::{GameplaySet, TickEventApp},
+    versus::Versus,
+    GameState,
+};
 
 pub(super) struct PortalsPlugin;
 
 impl Plugin for PortalsPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_systems(
-            Update,
-            (update_all_portals).run_if(in_state(GameState::Playing)),
+        app.add_tick_event::<EventOpenedPortal>().add_systems(
+            FixedUpdate,
+            update_all_portals
+                .in_set(GameplaySet::Waves)
+                .run_if(in_state(GameState::Playing)),
         );
     }
 }
@@ -19,14 +29,20 @@ impl Plugin for PortalsPlugin {
 pub struct Portal {
     // track when to spawn a new enemy
     timer: Timer,
-    capacity: u32,
-    delay_ms: Duration,
-    spawn_pattern: SpawnPattern,
+    /// enemies left to spawn, the next one last
+    enemies: Vec<EnemyKind>,
 }
 
-pub enum SpawnPattern {
-    Slow,
-    Immediate,
+impl Portal {
+    /// enemies left to spawn, the next one last
+    pub fn enemies(&self) -> &[EnemyKind] {
+        &self.enemies
+    }
+
+    /// time since the last enemy came out
+    pub fn elapsed(&self) -> Duration {
+        self.timer.elapsed()
+    }
 }
 
 pub fn update_all_portals(
@@ -43,13 +59,15 @@ pub fn update_all_portals(
 }
 
 fn spawn_enemy(command: &mut Commands, mut portal: (Mut<Portal>, &GlobalTransform, Entity)) {
-    command.add(SpawnEnemyCmd {
-        position: portal.1.translation().xy(),
-    });
-    portal.0.capacity -= 1;
+    if let Some(kind) = portal.0.enemies.pop() {
+        command.add(SpawnEnemyCmd {
+            position: portal.1.translation().xy(),
+            kind,
+        });
+    }
     // despawn immediatly the portal if it was the last enemy to spawn
     // TODO: special closing animation?
-    if portal.0.capacity < 1 {
+    if portal.0.enemies.is_empty() {
         command.entity(portal.2).remove_parent().despawn();
     }
 }
@@ -60,29 +78,61 @@ pub struct SpawnPortalCmd {
 
 impl EntityCommand for SpawnPortalCmd {
     fn apply(self, id: Entity, world: &mut World) {
-        world.resource_scope(|world, texture_assets: Mut<TextureAssets>| {
-            println!("Spawning a new portal");
-            let spawn_delay_ms = Duration::from_millis(3000);
-            world
-                .entity_mut(id)
-                .insert((
-                    SpriteBundle {
-                        transform: Transform::from_scale(Vec3::new(0.5, 0.5, 1.)),
-                        texture: texture_assets.portal.clone_weak(),
-                        ..Default::default()
-                    },
-                    Portal {
-                        capacity: 2,
-                        delay_ms: spawn_delay_ms,
-                        timer: Timer::new(spawn_delay_ms, TimerMode::Repeating),
-                        spawn_pattern: SpawnPattern::Immediate,
-                    },
-                    Name::new("Portal"),
-                ))
-                .set_parent(self.parent_hex);
-        });
+        debug!("Spawning a new portal");
+        let mut enemies = match world.get_resource_mut::<Versus>() {
+            Some(mut versus) => std::mem::take(&mut versus.queued),
+            None => {
+                let next_wave = world.resource::<NextWave>().clone();
+                next_wave.draw(world.resource_mut::<RandomStreams>().get(RngStream::Waves))
+            }
+        };
+        enemies.reverse();
+        insert_portal(world, id, self.parent_hex, enemies, Duration::ZERO);
+        world.send_event(EventOpenedPortal);
+    }
+}
+
+/// Puts back a portal from a saved run, without starting a new wave
+pub struct RestorePortalCmd {
+    pub parent_hex: Entity,
+    /// enemies left to spawn, the next one last
+    pub enemies: Vec<EnemyKind>,
+    /// time since the last enemy came out
+    pub elapsed: Duration,
+}
+
+impl EntityCommand for RestorePortalCmd {
+    fn apply(self, id: Entity, world: &mut World) {
+        insert_portal(world, id, self.parent_hex, self.enemies, self.elapsed);
     }
 }
 
-#[derive(Event)]
-pub struct EventDespawnedPortal(pub Entity);
+fn insert_portal(
+    world: &mut World,
+    id: Entity,
+    parent_hex: Entity,
+    enemies: Vec<EnemyKind>,
+    elapsed: Duration,
+) {
+    world.resource_scope(|world, texture_assets: Mut<TextureAssets>| {
+        let spawn_delay_ms = Duration::from_millis(3000);
+        let mut timer = Timer::new(spawn_delay_ms, TimerMode::Repeating);
+        timer.set_elapsed(elapsed);
+        world
+            .entity_mut(id)
+            .insert((
+                SpriteBundle {
+                    transform: Transform::from_scale(Vec3::new(0.5, 0.5, 1.)),
+                    texture: texture_assets.portal.clone_weak(),
+                    ..Default::default()
+                },
+                Portal { enemies, timer },
+                Name::new("Portal"),
+            ))
+            .set_parent(parent_hex);
+    });
+}
+
+/// Sent when a portal opens, starting a new wave
+#[derive(Event, Debug)]
+pub struct EventOpenedPortal;
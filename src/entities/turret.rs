@@ -1,23 +1,22 @@
 use std::{f32::consts::FRAC_PI_2, time::Duration};
 
 use crate::{
-    buildings::{self, BuildingInventory},
+    balance::Balance,
+    buildings::{self, Building, BuildingInventory},
     entities::{bullet::SpawnBullet, enemy::Enemy},
     grid::{HexCell, HexGrid},
     primitives::{
+        destructible::{apply_damage, EventDestroyed},
         target::{SourceWithTargetAccessor, Target},
         view::{
-            auto_remove_target_when_out_of_range, scan_for_targets_in_range, EnterViewEvent, View,
+            auto_remove_target_when_out_of_range, scan_for_targets_in_range,
+            update_visible_targets, EnterViewEvent, ExitViewEvent, View,
         },
     },
+    tick::GameplaySet,
     GameState,
 };
-use bevy::{
-    ecs::system::{EntityCommand, SystemState},
-    math::Vec3,
-    prelude::*,
-    sprite::SpriteBundle,
-};
+use bevy::{ecs::system::EntityCommand, math::Vec3, prelude::*, sprite::SpriteBundle};
 use bevy_easings::{Ease, EaseFunction};
 
 pub(super) struct TurretPlugin;
@@ -25,82 +24,136 @@ pub(super) struct TurretPlugin;
 impl Plugin for TurretPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(buildings::BuildingsPlugin);
-        app.add_event::<EventSpawnedTower>();
         app.add_systems(
-            Update,
+            FixedUpdate,
             (
-                scan_for_targets_in_range::<Turret, Enemy>,
+                update_visible_targets::<Turret, Enemy>,
                 auto_remove_target_when_out_of_range::<Turret, Enemy>,
+                scan_for_targets_in_range::<Turret, Enemy>,
                 process_enemy_enter_range,
                 process_enemy_exit_range,
-                animate_targeting,
                 auto_fire,
             )
+                .chain()
+                .in_set(GameplaySet::Shoot)
+                .run_if(in_state(GameState::Playing)),
+        );
+        app.add_systems(
+            FixedUpdate,
+            count_kills
+                .after(apply_damage)
+                .in_set(GameplaySet::Damage)
                 .run_if(in_state(GameState::Playing)),
         );
+        app.add_systems(
+            Update,
+            animate_targeting.run_if(in_state(GameState::Playing)),
+        );
     }
 }
 
 #[derive(Component)]
 pub struct Turret;
 
-#[derive(Event)]
-pub struct EventSpawnedTower(pub Entity);
-
 #[derive(Component)]
 pub struct AutoGun {
     next_shot: Timer,
+    damage: f32,
 }
 
 impl AutoGun {
-    pub fn new(fire_rate: f32) -> Self {
+    /// `fire_rate` is the delay in seconds between two shots
+    pub fn new(fire_rate: f32, damage: f32) -> Self {
         let mut next_shot = Timer::from_seconds(fire_rate, TimerMode::Repeating);
         next_shot.pause();
 
-        Self { next_shot }
+        Self { next_shot, damage }
+    }
+
+    pub fn shots_per_second(&self) -> f32 {
+        1. / self.next_shot.duration().as_secs_f32()
     }
+
+    pub fn dps(&self) -> f32 {
+        self.damage * self.shots_per_second()
+    }
+}
+
+/// What a turret achieved since it was built
+#[derive(Component, Default, Debug)]
+pub struct TurretStats {
+    pub shots_fired: u32,
+    pub kills: u32,
 }
 
 pub struct SpawnTurretCmd {
     pub parent_hex: Entity,
+    /// the inventory item to build, the next one if `None`
+    pub item: Option<Entity>,
 }
 
 impl EntityCommand for SpawnTurretCmd {
     fn apply(self, id: Entity, world: &mut World) {
-        // TODO: attach building to the turret
-        let _building =
-            world.resource_scope(|world, mut building_inventory: Mut<BuildingInventory>| {
-                building_inventory.next(world)
-            });
-
-        let texture = world.resource_scope(|_, asset_server: Mut<AssetServer>| {
-            asset_server.load("textures/DifferentTurrets/Turret01.png")
-        });
-        let hex_grid = world.resource::<HexGrid>();
-        let hex_radius: f32 = hex_grid.layout.hex_size.length();
-        let spawned_turret = world
-            .entity_mut(id)
-            .insert((
-                SpriteBundle {
-                    transform: Transform::from_scale(Vec3::new(0.5, 0.5, 1.)),
-                    texture,
-                    ..Default::default()
-                },
-                Turret,
-                Name::new("Turret"),
-                AutoGun::new(1.),
-                View::new(2. * hex_radius),
-            ))
-            .set_parent(self.parent_hex)
-            .id();
-        // FIXME: Not sure this is useful since there is bevy::event::Changed<Turret> that could do the same thing?
-        let mut q_event: SystemState<EventWriter<EventSpawnedTower>> = SystemState::new(world);
-
-        let mut event_writer = q_event.get_mut(world);
-        event_writer.send(EventSpawnedTower(spawned_turret));
+        let building = world.resource_scope(
+            |world, mut building_inventory: Mut<BuildingInventory>| match self.item {
+                Some(item) => building_inventory.take(world, item),
+                None => building_inventory.next(world),
+            },
+        );
+        let Some(building) = building else {
+            // nothing could be built, e.g. not enough energy
+            world.despawn(id);
+            return;
+        };
+        insert_turret(world, id, self.parent_hex, building);
+    }
+}
+
+/// Puts back a turret from a saved run, without paying for it
+pub struct RestoreTurretCmd {
+    pub parent_hex: Entity,
+    pub building: Building,
+}
+
+impl EntityCommand for RestoreTurretCmd {
+    fn apply(self, id: Entity, world: &mut World) {
+        insert_turret(world, id, self.parent_hex, self.building);
     }
 }
 
+fn insert_turret(world: &mut World, id: Entity, parent_hex: Entity, building: Building) {
+    let texture = world.resource_scope(|_, asset_server: Mut<AssetServer>| {
+        asset_server.load("textures/DifferentTurrets/Turret01.png")
+    });
+    let hex_grid = world.resource::<HexGrid>();
+    let hex_radius: f32 = hex_grid.layout.hex_size.length();
+    let range_bonus = world
+        .get::<HexCell>(parent_hex)
+        .map_or(0., |cell| cell.terrain.range_bonus());
+    let balance = &world.resource::<Balance>().turrets;
+    let gun = AutoGun::new(balance.fire_rate, balance.damage);
+    let mut view = View::new((balance.range_in_hexes + range_bonus) * hex_radius);
+    if building.is_anti_air() {
+        view = view.with_anti_air();
+    }
+    world
+        .entity_mut(id)
+        .insert((
+            SpriteBundle {
+                transform: Transform::from_scale(Vec3::new(0.5, 0.5, 1.)),
+                texture,
+                ..Default::default()
+            },
+            Turret,
+            building,
+            Name::new("Turret"),
+            gun,
+            TurretStats::default(),
+            view,
+        ))
+        .set_parent(parent_hex);
+}
+
 pub fn animate_targeting(
     mut commands: Commands,
     accessor: SourceWithTargetAccessor<Turret, Enemy>,
@@ -131,45 +184,138 @@ pub fn process_enemy_enter_range(
     mut turrets_query: Query<&mut AutoGun, With<Turret>>,
 ) {
     for event in events.read() {
-        if let Ok(mut gun) = turrets_query.get_mut(event.entity) {
+        if let Ok(mut gun) = turrets_query.get_mut(event.source) {
             gun.next_shot.unpause();
         }
     }
 }
 
 pub fn process_enemy_exit_range(
-    mut events: EventReader<EnterViewEvent>,
-    mut turrets_query: Query<&mut AutoGun, With<Turret>>,
+    mut events: EventReader<ExitViewEvent>,
+    mut turrets_query: Query<(&mut AutoGun, &View), With<Turret>>,
 ) {
     for event in events.read() {
-        if let Ok(mut gun) = turrets_query.get_mut(event.entity) {
-            gun.next_shot.pause();
-            gun.next_shot.reset();
+        if let Ok((mut gun, view)) = turrets_query.get_mut(event.source) {
+            // keep shooting as long as there is something left to shoot at
+            if view.sees_nothing() {
+                gun.next_shot.pause();
+                gun.next_shot.reset();
+            }
         }
     }
 }
 
+/// A turret ready to shoot at its target
+type AimingTurret = (
+    Entity,
+    &'static Target,
+    &'static mut AutoGun,
+    &'static mut TurretStats,
+    &'static Parent,
+);
+
 pub fn auto_fire(
     mut commands: Commands,
     // make sure that the turret has a target and is in view
-    mut turrets_query: Query<
-        (&Transform, &Target, &mut AutoGun, &Parent),
-        (With<Turret>, With<View>),
-    >,
+    mut turrets_query: Query<AimingTurret, (With<Turret>, With<View>)>,
     hex_query: Query<&Transform, (Without<Turret>, With<HexCell>)>,
     time: Res<Time>,
+    balance: Res<Balance>,
 ) {
-    for (global_transform, target, mut gun, parent) in &mut turrets_query {
+    for (entity, target, mut gun, mut stats, parent) in &mut turrets_query {
         if gun.next_shot.tick(time.delta()).just_finished() {
             if let Ok(transform) = hex_query.get(parent.get()) {
                 let spaw_bullet = SpawnBullet {
                     position: transform.translation,
-                    velocity: 200.,
-                    damage: 1.,
+                    velocity: balance.bullets.velocity,
+                    damage: gun.damage,
                     target: target.entity,
+                    source: entity,
                 };
                 commands.add(spaw_bullet);
+                stats.shots_fired += 1;
             }
         }
     }
 }
+
+pub fn count_kills(
+    mut events: EventReader<EventDestroyed>,
+    mut turrets_query: Query<&mut TurretStats, With<Turret>>,
+) {
+    for event in events.read() {
+        let Some(turret) = event.destroyed_by else {
+            continue;
+        };
+        if let Ok(mut stats) = turrets_query.get_mut(turret) {
+            debug!("{:?} destroyed by turret {:?}", event.entity, turret);
+            stats.kills += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hexx::Hex;
+
+    use super::*;
+    use crate::{
+        buildings::{BuildingColor, BuildingMesh, BuildingSize},
+        entities::enemy::EnemyKind,
+        grid::{MapDefinition, Terrain},
+        testing::TestRun,
+    };
+
+    #[test]
+    fn a_turret_kills_an_enemy_in_range() {
+        let mut run = TestRun::new();
+        let turret = run
+            .place_turret(
+                Hex::new(3, -1),
+                Building::new(
+                    BuildingMesh::Triangle,
+                    BuildingSize::Small,
+                    BuildingColor::Pink,
+                ),
+            )
+            .unwrap();
+        let enemy = run.spawn_enemy(EnemyKind::Drone, Hex::new(5, -2));
+        assert!(run.advance_until(30., |run| !run.is_alive(enemy)));
+        assert_eq!(run.turret_stats(turret).kills, 1);
+        assert_eq!(run.leaks(), 0);
+    }
+
+    #[test]
+    fn the_terrain_decides_where_turrets_stand_and_how_far_they_see() {
+        let mut map = MapDefinition::default();
+        map.set_terrain([3, 0], Terrain::Water);
+        map.set_terrain([-3, 0], Terrain::EnergyNode);
+        let mut run = TestRun::on_map(map);
+        let building = Building::new(
+            BuildingMesh::Triangle,
+            BuildingSize::Small,
+            BuildingColor::Pink,
+        );
+        assert!(run.place_turret(Hex::new(3, 0), building).is_none());
+        let plain = run.place_turret(Hex::new(0, 3), building).unwrap();
+        let node = run.place_turret(Hex::new(-3, 0), building).unwrap();
+        let range = |turret| run.app.world.get::<View>(turret).unwrap().range();
+        assert!(range(node) > range(plain));
+    }
+
+    #[test]
+    fn only_anti_air_turrets_shoot_at_flyers() {
+        for (mesh, anti_air) in [(BuildingMesh::Quad, false), (BuildingMesh::Triangle, true)] {
+            let mut run = TestRun::new();
+            let building = Building::new(mesh, BuildingSize::Small, BuildingColor::Pink);
+            assert_eq!(building.is_anti_air(), anti_air);
+            let turret = run.place_turret(Hex::new(3, -1), building).unwrap();
+            let flyer = run.spawn_enemy(EnemyKind::Flyer, Hex::new(5, -2));
+            assert!(run.advance_until(60., |run| { !run.is_alive(flyer) || run.leaks() == 1 }));
+            let stats = run.turret_stats(turret);
+            assert_eq!(stats.kills == 1, anti_air);
+            assert_eq!(stats.shots_fired > 0, anti_air);
+            assert_eq!(run.leaks() == 1, !anti_air);
+        }
+    }
+}
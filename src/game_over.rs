@@ -1,24 +1,154 @@
 use bevy::prelude::*;
 
-use crate::{entities::crystal::CrystalTouched, overload::OverloadDepleted};
+use crate::{
+    challenge::{record_score, ChallengeResult},
+    difficulty::Wave,
+    economy::{pay_interest, Ledger},
+    entities::crystal::CrystalTouched,
+    overload::OverloadDepleted,
+    random::RunSeed,
+    tick::GameplaySet,
+    versus::Versus,
+    GameState,
+};
 
 pub struct GameOverPlugin;
 
 impl Plugin for GameOverPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, detect_end_of_game);
+        app.add_systems(OnEnter(GameState::Playing), reset_game_over)
+            .add_systems(
+                FixedUpdate,
+                detect_end_of_game
+                    .after(pay_interest)
+                    .in_set(GameplaySet::Outcome)
+                    .run_if(in_state(GameState::Playing)),
+            );
     }
 }
 
+pub struct GameOverUiPlugin;
+
+/// The results of the run once it is over, with its seed so that it can be played again.
+/// A versus match shows its winner instead
+impl Plugin for GameOverUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            show_results
+                .after(record_score)
+                .run_if(resource_added::<GameOver>())
+                .run_if(not(resource_exists::<Versus>()))
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameOverCause {
+    OverloadDepleted,
+    CrystalTouched,
+}
+
+/// How the run ended, once it did
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct GameOver {
+    pub cause: GameOverCause,
+    /// portals opened before the end
+    pub waves: u32,
+    /// enemies killed before the end
+    pub kills: u32,
+    pub seed: RunSeed,
+}
+
+fn reset_game_over(mut commands: Commands) {
+    commands.remove_resource::<GameOver>();
+}
+
 pub fn detect_end_of_game(
-    _command: Commands,
+    mut commands: Commands,
     mut crystal_touched: EventReader<CrystalTouched>,
     mut overload_depleted: EventReader<OverloadDepleted>,
+    seed: Res<RunSeed>,
+    wave: Res<Wave>,
+    ledger: Res<Ledger>,
+    game_over: Option<Res<GameOver>>,
 ) {
+    let mut cause = None;
     for _ in overload_depleted.read() {
-        info!("Game over: Overload depleted");
+        info!("Game over: Overload depleted ({})", *seed);
+        cause = Some(GameOverCause::OverloadDepleted);
     }
     for _ in crystal_touched.read() {
-        info!("Game over: Crystal touched");
+        info!("Game over: Crystal touched ({})", *seed);
+        cause = cause.or(Some(GameOverCause::CrystalTouched));
+    }
+    if let (Some(cause), None) = (cause, game_over) {
+        commands.insert_resource(GameOver {
+            cause,
+            waves: wave.0,
+            kills: ledger.kills(),
+            seed: *seed,
+        });
+    }
+}
+
+#[derive(Component)]
+struct ResultsScreen;
+
+fn show_results(
+    mut commands: Commands,
+    game_over: Res<GameOver>,
+    challenge: Res<ChallengeResult>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    // the board stays as it was when the run ended
+    time.pause();
+    let cause = match game_over.cause {
+        GameOverCause::OverloadDepleted => "The overload is depleted",
+        GameOverCause::CrystalTouched => "An enemy reached the crystal",
+    };
+    let mut results = format!("{}\nWaves: {}\n{}", cause, game_over.waves, game_over.seed);
+    if let Some(score) = challenge.0 {
+        results += &format!("\nDaily challenge score: {}", score);
     }
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(20.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            ResultsScreen,
+            Name::new("Results screen"),
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Game over",
+                TextStyle {
+                    font_size: 60.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                    ..default()
+                },
+            ));
+            parent.spawn(
+                TextBundle::from_section(
+                    results,
+                    TextStyle {
+                        font_size: 30.0,
+                        color: Color::rgb(0.9, 0.9, 0.9),
+                        ..default()
+                    },
+                )
+                .with_text_alignment(TextAlignment::Center),
+            );
+        });
 }
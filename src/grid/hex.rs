@@ -8,12 +8,14 @@ use bevy_eventlistener::{
     callbacks::{Listener, ListenerInput},
     event_listener::On,
 };
-use bevy_mod_picking::events::{Click, Out, Over, Pointer};
-use hexx::Hex;
+use bevy_mod_picking::events::{Click, Drop, Out, Over, Pointer};
+
+use super::Terrain;
 
 #[derive(Debug, Default, Component)]
 pub struct HexCell {
     pub dist: u32,
+    pub terrain: Terrain,
 }
 
 #[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
@@ -22,6 +24,9 @@ pub struct HexMaterial {
     pub color: Vec4,
     #[uniform(0)]
     pub is_selected: f32,
+    /// see [`Terrain::style`]
+    #[uniform(0)]
+    pub terrain: f32,
 }
 
 impl Material2d for HexMaterial {
@@ -32,8 +37,8 @@ impl Material2d for HexMaterial {
 
 pub struct SpawnHexCmd {
     pub position: Vec2,
-    pub hex: Hex,
     pub mesh: Handle<Mesh>,
+    pub terrain: Terrain,
 }
 
 impl EntityCommand for SpawnHexCmd {
@@ -42,6 +47,7 @@ impl EntityCommand for SpawnHexCmd {
         let asset = HexMaterial {
             color: color.into(),
             is_selected: 0.,
+            terrain: self.terrain.style(),
         };
         let material = world.resource_scope(
             |_world: &mut World, mut materials: Mut<Assets<HexMaterial>>| materials.add(asset),
@@ -53,16 +59,18 @@ impl EntityCommand for SpawnHexCmd {
                 transform: Transform::from_xyz(self.position.x, self.position.y, -1.0),
                 ..default()
             },
-            HexCell { dist: 0 },
+            HexCell {
+                dist: 0,
+                terrain: self.terrain,
+            },
             On::<Pointer<Over>>::run(select_hex),
             On::<Pointer<Out>>::run(deselect_hex),
             On::<Pointer<Click>>::send_event::<HexClicked>(),
+            On::<Pointer<Drop>>::send_event::<HexDropped>(),
         ));
     }
 }
 
-pub fn reset_dist_on_content_change() {}
-
 pub fn select_hex(
     event: Listener<Pointer<Over>>,
     mut hexes: Query<(&Handle<HexMaterial>, Option<&Children>), With<HexCell>>,
@@ -100,3 +108,19 @@ impl From<ListenerInput<Pointer<Click>>> for HexClicked {
         }
     }
 }
+
+/// Sent when something is dragged and dropped onto a hex
+#[derive(Event)]
+pub struct HexDropped {
+    pub dropped: Entity,
+    pub target: Entity,
+}
+
+impl From<ListenerInput<Pointer<Drop>>> for HexDropped {
+    fn from(value: ListenerInput<Pointer<Drop>>) -> Self {
+        HexDropped {
+            dropped: value.dropped,
+            target: value.target,
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use std::path::Path;
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use hexx::{Hex, HexBounds};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{distances_to_crystals, portal_distance, Terrain, MAP_RADIUS};
+
+/// where the editor exports and imports the map, relative to the working directory
+pub const MAP_FILE: &str = "map.ron";
+/// the smallest and the largest radius a map can have
+pub const MAP_RADIUS_RANGE: std::ops::RangeInclusive<u32> = 3..=15;
+
+/// The map the runs are played on, as authored in the editor
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MapDefinition {
+    /// hexes from the center to the border
+    pub radius: u32,
+    /// axial coordinates of the crystals the enemies walk to
+    pub crystals: Vec<[i32; 2]>,
+    /// where portals can be opened, anywhere when empty
+    pub portals: Vec<[i32; 2]>,
+    /// hexes nothing can cross nor be built on
+    pub blockers: Vec<[i32; 2]>,
+    /// rocks blocking the way until the player clears them
+    #[serde(default)]
+    pub obstacles: Vec<[i32; 2]>,
+    /// the hexes that aren't plain, maps made before the terrain are all plain
+    #[serde(default)]
+    pub terrain: Vec<([i32; 2], Terrain)>,
+    /// asset path of a `.balance_patch.ron` file tuning the balance for this map
+    #[serde(default)]
+    pub balance: Option<String>,
+}
+
+impl Default for MapDefinition {
+    /// The open map with a crystal in the center
+    fn default() -> Self {
+        Self {
+            radius: MAP_RADIUS,
+            crystals: vec![[0, 0]],
+            portals: Vec::new(),
+            blockers: Vec::new(),
+            obstacles: Vec::new(),
+            terrain: Vec::new(),
+            balance: None,
+        }
+    }
+}
+
+/// What a hex of a map holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapTile {
+    Crystal,
+    Portal,
+    Blocker,
+    Obstacle,
+}
+
+#[derive(Debug, Error)]
+pub enum MapError {
+    #[error("could not access the map file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse the map file: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("could not write the map file: {0}")]
+    Write(#[from] ron::Error),
+    #[error("the map has no crystal")]
+    NoCrystal,
+    #[error("({}, {}) is out of the map", .0[0], .0[1])]
+    OutOfBounds([i32; 2]),
+    #[error("the enemies of the portal at ({}, {}) can't reach a crystal", .0[0], .0[1])]
+    Unreachable([i32; 2]),
+}
+
+impl MapDefinition {
+    pub fn bounds(&self) -> HexBounds {
+        HexBounds::new(Hex::ZERO, self.radius)
+    }
+
+    pub fn crystal_hexes(&self) -> Vec<Hex> {
+        self.crystals.iter().map(|&[x, y]| Hex::new(x, y)).collect()
+    }
+
+    pub fn portal_hexes(&self) -> Vec<Hex> {
+        self.portals.iter().map(|&[x, y]| Hex::new(x, y)).collect()
+    }
+
+    /// The terrain of every hex that isn't plain
+    pub fn terrain_hexes(&self) -> HashMap<Hex, Terrain> {
+        self.terrain
+            .iter()
+            .map(|&([x, y], terrain)| (Hex::new(x, y), terrain))
+            .collect()
+    }
+
+    pub fn terrain_at(&self, hex: [i32; 2]) -> Terrain {
+        self.terrain
+            .iter()
+            .find_map(|&(h, terrain)| (h == hex).then_some(terrain))
+            .unwrap_or_default()
+    }
+
+    pub fn set_terrain(&mut self, hex: [i32; 2], terrain: Terrain) {
+        if !self.bounds().is_in_bounds(Hex::new(hex[0], hex[1])) {
+            return;
+        }
+        self.terrain.retain(|&(h, _)| h != hex);
+        if terrain != Terrain::Plain {
+            self.terrain.push((hex, terrain));
+        }
+    }
+
+    pub fn tile(&self, hex: [i32; 2]) -> Option<MapTile> {
+        if self.crystals.contains(&hex) {
+            Some(MapTile::Crystal)
+        } else if self.portals.contains(&hex) {
+            Some(MapTile::Portal)
+        } else if self.blockers.contains(&hex) {
+            Some(MapTile::Blocker)
+        } else if self.obstacles.contains(&hex) {
+            Some(MapTile::Obstacle)
+        } else {
+            None
+        }
+    }
+
+    /// Puts `tile` on `hex` in place of what was there, `None` clears the hex
+    pub fn set(&mut self, hex: [i32; 2], tile: Option<MapTile>) {
+        if !self.bounds().is_in_bounds(Hex::new(hex[0], hex[1])) {
+            return;
+        }
+        for hexes in self.tiles_mut() {
+            hexes.retain(|&h| h != hex);
+        }
+        match tile {
+            Some(MapTile::Crystal) => self.crystals.push(hex),
+            Some(MapTile::Portal) => self.portals.push(hex),
+            Some(MapTile::Blocker) => self.blockers.push(hex),
+            Some(MapTile::Obstacle) => self.obstacles.push(hex),
+            None => {}
+        }
+    }
+
+    /// Changes the size of the map, what falls out of it is dropped
+    pub fn resize(&mut self, radius: u32) {
+        self.radius = radius.clamp(*MAP_RADIUS_RANGE.start(), *MAP_RADIUS_RANGE.end());
+        let bounds = self.bounds();
+        for hexes in self.tiles_mut() {
+            hexes.retain(|&[x, y]| bounds.is_in_bounds(Hex::new(x, y)));
+        }
+        self.terrain
+            .retain(|&([x, y], _)| bounds.is_in_bounds(Hex::new(x, y)));
+    }
+
+    fn tiles_mut(&mut self) -> [&mut Vec<[i32; 2]>; 4] {
+        [
+            &mut self.crystals,
+            &mut self.portals,
+            &mut self.blockers,
+            &mut self.obstacles,
+        ]
+    }
+
+    /// Checks that the runs can be played on the map: there is a crystal, and the enemies of
+    /// every portal can reach one without any obstacle being cleared
+    pub fn validate(&self) -> Result<(), MapError> {
+        if self.crystals.is_empty() {
+            return Err(MapError::NoCrystal);
+        }
+        let bounds = self.bounds();
+        if let Some(&hex) = [
+            &self.crystals,
+            &self.portals,
+            &self.blockers,
+            &self.obstacles,
+        ]
+        .into_iter()
+        .flatten()
+        .find(|&&[x, y]| !bounds.is_in_bounds(Hex::new(x, y)))
+        {
+            return Err(MapError::OutOfBounds(hex));
+        }
+        let walls: HashSet<Hex> = self
+            .blockers
+            .iter()
+            .chain(&self.portals)
+            .chain(&self.obstacles)
+            .map(|&[x, y]| Hex::new(x, y))
+            .collect();
+        let distances = distances_to_crystals(
+            &bounds,
+            &walls,
+            &self.terrain_hexes(),
+            &self.crystal_hexes(),
+        );
+        match self
+            .portals
+            .iter()
+            .find(|&&[x, y]| portal_distance(&distances, Hex::new(x, y)).is_none())
+        {
+            Some(&portal) => Err(MapError::Unreachable(portal)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MapError> {
+        Ok(ron::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MapError> {
+        let content = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_portal_walled_off_from_the_crystals_is_reported() {
+        let mut map = MapDefinition::default();
+        map.set([5, 0], Some(MapTile::Portal));
+        assert!(map.validate().is_ok());
+        for hex in Hex::new(5, 0).all_neighbors() {
+            map.set([hex.x, hex.y], Some(MapTile::Blocker));
+        }
+        assert!(matches!(map.validate(), Err(MapError::Unreachable([5, 0]))));
+        // a second crystal in place of one of the walls
+        map.set([6, 0], Some(MapTile::Crystal));
+        assert!(map.validate().is_ok());
+    }
+
+    #[test]
+    fn shrinking_the_map_drops_what_falls_out_of_it() {
+        let mut map = MapDefinition::default();
+        map.set([8, 0], Some(MapTile::Portal));
+        map.set([2, 0], Some(MapTile::Blocker));
+        map.set([2, 0], Some(MapTile::Portal));
+        map.resize(5);
+        assert_eq!(map.portals, vec![[2, 0]]);
+        assert!(map.blockers.is_empty());
+        let exported = ron::to_string(&map).unwrap();
+        assert_eq!(ron::from_str::<MapDefinition>(&exported).unwrap(), map);
+    }
+}
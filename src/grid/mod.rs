@@ -1,32 +1,47 @@
 mod hex;
+mod map;
+mod terrain;
+
+use std::{cmp::Reverse, collections::BinaryHeap};
 
 use bevy::{
     app::{App, Plugin},
     asset::Assets,
-    ecs::system::{Commands, ResMut},
+    ecs::system::{Commands, ResMut, SystemParam},
     prelude::*,
     render::{
         mesh::{Indices, Mesh},
         render_resource::PrimitiveTopology,
     },
-    sprite::Material2dPlugin,
     utils::{HashMap, HashSet},
 };
 
 use bevy_mod_picking::prelude::PointerButton;
 use hexx::{Hex, HexBounds, HexLayout, PlaneMeshBuilder, Vec2};
 
-use crate::{entities::portal::SpawnPortalCmd, entities::turret::SpawnTurretCmd, GameState};
+use crate::{
+    actions::player_command::{execute_player_commands, EventPlayerCommand, PlayerCommand},
+    buildings::Building,
+    inventory::Inventory,
+    tick::{TickEventApp, TickSet},
+    versus::Versus,
+    GameState,
+};
 
-pub use self::hex::HexCell;
-use self::hex::{HexClicked, HexMaterial, SpawnHexCmd};
+pub(crate) use self::hex::HexClicked;
+pub use self::hex::{HexCell, HexMaterial};
+use self::hex::{HexDropped, SpawnHexCmd};
+pub use self::map::{MapDefinition, MapTile, MAP_FILE};
+pub use self::terrain::{Terrain, PLAIN_MOVEMENT_COST};
 
 pub struct GridPlugin;
 
 impl Plugin for GridPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(Material2dPlugin::<HexMaterial>::default())
+        app.init_resource::<MapDefinition>()
             .add_event::<HexClicked>()
+            .add_event::<HexDropped>()
+            .add_tick_event::<GridChanged>()
             .add_systems(
                 OnTransition {
                     from: GameState::Menu,
@@ -37,29 +52,33 @@ impl Plugin for GridPlugin {
             .add_systems(OnEnter(GameState::Playing), update_distances)
             .add_systems(
                 Update,
-                // All the systems to execute while the game is playing
+                // clicks and drops become player commands, carried out at the next tick
+                (on_hex_clicked, on_hex_dropped).run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                FixedUpdate,
+                // Execute this chain after each change of the grid content, before the enemies move
                 (
-                    // Execute this chain after each click on the grid
-                    ((
-                        on_hex_clicked,
-                        detect_despawned_grid_content,
-                        //FIXME: find a better solution
-                        clear_unconstructible_hexes, // remove all nonconstructibletags before recalculating
-                        apply_deferred.in_set(GridFlush), // make sure we flush the grid before updating distances
-                        update_distances,
-                        update_unconstructible_hexes,
-                        apply_deferred.in_set(GridUpdate), // make sure we flush the grid before drawing
-                        debug_display_non_constructible_hexes,
-                    )
-                        .chain())
-                    .run_if(on_event::<HexClicked>())
+                    detect_despawned_grid_content,
+                    //FIXME: find a better solution
+                    clear_unconstructible_hexes, // remove all nonconstructibletags before recalculating
+                    apply_deferred.in_set(GridFlush), // make sure we flush the grid before updating distances
+                    update_distances,
+                    update_unconstructible_hexes,
+                    apply_deferred.in_set(GridUpdate), // make sure we flush the grid before drawing
+                    debug_display_non_constructible_hexes,
                 )
-                .run_if(in_state(GameState::Playing)),
+                    .chain()
+                    .after(execute_player_commands)
+                    .in_set(TickSet::Commands)
+                    .run_if(on_event::<GridChanged>())
+                    .run_if(in_state(GameState::Playing)),
             );
     }
 }
 
 pub const HEX_SIZE: Vec2 = Vec2::new(60., 60.);
+/// the radius of the default map
 pub const MAP_RADIUS: u32 = 10;
 
 #[derive(Debug, Resource)]
@@ -67,29 +86,76 @@ pub struct HexGrid {
     entities: HashMap<Hex, Entity>,
     pub layout: HexLayout,
     pub bounds: HexBounds,
+    /// where the crystals stand, the enemies walk to the closest one
+    pub crystals: Vec<Hex>,
+    /// where portals can be opened, anywhere when empty
+    pub portals: Vec<Hex>,
 }
 
 impl HexGrid {
     pub fn hex_to_entity(&self, hex: &Hex) -> Option<&Entity> {
         self.entities.get(hex)
     }
+
+    pub fn entity_to_hex(&self, entity: Entity) -> Option<Hex> {
+        self.entities
+            .iter()
+            .find_map(|(&hex, &e)| (e == entity).then_some(hex))
+    }
+
+    /// How building on `hex` changes the way from each of the `portals` to the crystal,
+    /// see [`path_changes`]
+    pub fn path_changes(
+        &self,
+        walls: &HashSet<Hex>,
+        terrain: &HashMap<Hex, Terrain>,
+        portals: &[Hex],
+        hex: Hex,
+    ) -> Vec<PathChange> {
+        path_changes(&self.bounds, walls, terrain, &self.crystals, portals, hex)
+    }
+
+    /// The crystals and the portals of the map stand there, nothing can be built on it
+    pub fn is_reserved(&self, hex: &Hex) -> bool {
+        self.crystals.contains(hex) || self.portals.contains(hex)
+    }
 }
 
+/// Sent when the content of the grid changed, e.g. when a building is built or a saved run is restored
+#[derive(Event, Debug)]
+pub struct GridChanged;
+
 #[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
 struct GridFlush;
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, SystemSet)]
 struct GridUpdate;
 
-fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+/// A hex of the map nothing can cross nor be built on
+#[derive(Component, Debug)]
+pub struct Blocker;
+
+/// A rock in the way of the enemies, until the player clears it
+#[derive(Component, Debug)]
+pub struct Obstacle;
+
+/// Spawns the hexes of the [`MapDefinition`]
+pub(crate) fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    map: Res<MapDefinition>,
+    asset_server: Res<AssetServer>,
+    mut grid_changed: EventWriter<GridChanged>,
+) {
     let layout = HexLayout {
         hex_size: HEX_SIZE,
         ..Default::default()
     };
     let mesh = meshes.add(hexagonal_plane(&layout));
 
-    let bounds = HexBounds::new(Hex::ZERO, MAP_RADIUS);
-    let entities = bounds
+    let bounds = map.bounds();
+    let terrain = map.terrain_hexes();
+    let entities: HashMap<Hex, Entity> = bounds
         .all_coords()
         .map(|hex| {
             let position = layout.hex_to_world_pos(hex);
@@ -97,19 +163,71 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
                 .spawn_empty()
                 .add(SpawnHexCmd {
                     position,
-                    hex,
                     mesh: mesh.clone(),
+                    terrain: terrain.get(&hex).copied().unwrap_or_default(),
                 })
                 .id();
             (hex, entity)
         })
         .collect();
+    for &[x, y] in &map.blockers {
+        let Some(&hex_entity) = entities.get(&Hex::new(x, y)) else {
+            continue;
+        };
+        commands
+            .spawn((
+                SpriteBundle {
+                    transform: Transform::from_scale(Vec3::new(0.5, 0.5, 1.)),
+                    texture: asset_server.load("textures/RandomBuildings/B05.png"),
+                    ..default()
+                },
+                Blocker,
+                Name::new("Blocker"),
+            ))
+            .set_parent(hex_entity);
+    }
+    for &[x, y] in &map.obstacles {
+        let Some(&hex_entity) = entities.get(&Hex::new(x, y)) else {
+            continue;
+        };
+        // the look of a rock only depends on where it is
+        let texture = format!(
+            "textures/MiniAsteroids/0{}.png",
+            (x * 7 + y * 13).rem_euclid(3) + 1
+        );
+        commands
+            .spawn((
+                SpriteBundle {
+                    transform: Transform::from_scale(Vec3::new(0.8, 0.8, 1.)),
+                    texture: asset_server.load(texture),
+                    ..default()
+                },
+                Obstacle,
+                Name::new("Obstacle"),
+            ))
+            .set_parent(hex_entity);
+    }
     let grid = HexGrid {
         entities,
         layout,
         bounds,
+        crystals: map.crystal_hexes(),
+        portals: map.portal_hexes(),
     };
     commands.insert_resource(grid);
+    // the blockers and the obstacles may already make some hexes unconstructible
+    grid_changed.send(GridChanged);
+}
+
+/// Despawns the hexes and what stands on them
+pub(crate) fn despawn_grid(mut commands: Commands, grid: Option<Res<HexGrid>>) {
+    let Some(grid) = grid else {
+        return;
+    };
+    for &entity in grid.entities.values() {
+        commands.entity(entity).despawn_recursive();
+    }
+    commands.remove_resource::<HexGrid>();
 }
 
 #[derive(Debug, Default, Component)]
@@ -128,56 +246,151 @@ fn hexagonal_plane(hex_layout: &HexLayout) -> Mesh {
 
 // recalculating the distance to the center for all hexes
 // to be called when the grid is changed (e.g. when a tower is placed)
-fn update_distances(
+pub(crate) fn update_distances(
     grid: Res<HexGrid>,
     mut hexes: Query<(&mut HexCell, &Handle<HexMaterial>, Option<&Children>)>,
     mut materials: ResMut<Assets<HexMaterial>>,
     mut colored: Local<bool>,
 ) {
-    let center = Hex::ZERO;
-    let mut queue = vec![center];
-    let mut processed: HashSet<Hex> = HashSet::new();
-    let mut dist = 0;
-
-    processed.insert(center);
-    while !queue.is_empty() {
-        let mut next_queue = Vec::new();
-        for hex in queue {
-            if let Some(entity) = grid.entities.get(&hex) {
-                if let Ok((mut cell, hex_material, content)) = hexes.get_mut(*entity) {
-                    // if the hex has content, consider it as a wall and don't add any neighbors
-                    if content.is_some() {
-                        cell.dist = u32::MAX;
-                        continue;
-                    }
-                    cell.dist = dist;
-
-                    // adds next circle of neighbors to the queue
-                    for neighbor in hex.all_neighbors() {
-                        // filter out out-of-bounds and inner-bounds hexes
-                        if !grid.entities.contains_key(&neighbor) || processed.contains(&neighbor) {
-                            continue;
-                        }
-                        processed.insert(neighbor);
-                        next_queue.push(neighbor);
-                    }
-                    // FIXME: debug purposes only, find a better way to color the field
-                    if !*colored {
-                        let v = dist as f32 / MAP_RADIUS as f32;
-                        let material = materials.get_mut(hex_material).unwrap();
-                        material.color.x = v;
-                        material.color.y = v;
-                        material.color.z = v;
-                    }
-                }
-            }
+    // if the hex has content, consider it as a wall
+    let mut walls = HashSet::new();
+    let mut terrain = HashMap::new();
+    for (&hex, &entity) in &grid.entities {
+        let Ok((cell, _, content)) = hexes.get(entity) else {
+            continue;
+        };
+        if content.is_some() {
+            walls.insert(hex);
+        }
+        terrain.insert(hex, cell.terrain);
+    }
+    let distances = distances_to_crystals(&grid.bounds, &walls, &terrain, &grid.crystals);
+    for (hex, &entity) in &grid.entities {
+        let Ok((mut cell, hex_material, _)) = hexes.get_mut(entity) else {
+            continue;
+        };
+        let Some(&dist) = distances.get(hex) else {
+            cell.dist = u32::MAX;
+            continue;
+        };
+        cell.dist = dist;
+        // FIXME: debug purposes only, find a better way to color the field
+        if !*colored {
+            let v = dist as f32 / (grid.bounds.radius * PLAIN_MOVEMENT_COST) as f32;
+            let material = materials.get_mut(hex_material).unwrap();
+            material.color.x = v;
+            material.color.y = v;
+            material.color.z = v;
         }
-        queue = next_queue;
-        dist += 1;
     }
     *colored = true;
 }
 
+/// What it costs to go from each hex to the closest of the `crystals`, going around the walls:
+/// leaving a hex costs the [`Terrain::movement_cost`] of its `terrain`, plain when missing.
+/// Walls, impassable hexes and hexes cut off from the crystals are left out
+pub fn distances_to_crystals(
+    bounds: &HexBounds,
+    walls: &HashSet<Hex>,
+    terrain: &HashMap<Hex, Terrain>,
+    crystals: &[Hex],
+) -> HashMap<Hex, u32> {
+    let cost = |hex: &Hex| {
+        terrain
+            .get(hex)
+            .copied()
+            .unwrap_or_default()
+            .movement_cost()
+    };
+    let mut distances = HashMap::new();
+    // the closest hexes first, in a stable order
+    let mut queue = BinaryHeap::new();
+    for &crystal in crystals {
+        if !walls.contains(&crystal) && cost(&crystal).is_some() {
+            distances.insert(crystal, 0);
+            queue.push(Reverse((0, [crystal.x, crystal.y])));
+        }
+    }
+    while let Some(Reverse((dist, [x, y]))) = queue.pop() {
+        if distances.get(&Hex::new(x, y)).is_some_and(|&d| d < dist) {
+            continue;
+        }
+        for neighbor in Hex::new(x, y).all_neighbors() {
+            // filter out out-of-bounds, walls and impassable hexes
+            if !bounds.is_in_bounds(neighbor) || walls.contains(&neighbor) {
+                continue;
+            }
+            let Some(cost) = cost(&neighbor) else {
+                continue;
+            };
+            let neighbor_dist = dist + cost;
+            if distances
+                .get(&neighbor)
+                .is_some_and(|&d| d <= neighbor_dist)
+            {
+                continue;
+            }
+            distances.insert(neighbor, neighbor_dist);
+            queue.push(Reverse((neighbor_dist, [neighbor.x, neighbor.y])));
+        }
+    }
+    distances
+}
+
+/// What the way to the crystal costs to an enemy coming out of a portal on `portal`: the portal
+/// is a wall, the enemy starts next to it
+pub fn portal_distance(distances: &HashMap<Hex, u32>, portal: Hex) -> Option<u32> {
+    if let Some(&dist) = distances.get(&portal) {
+        return Some(dist);
+    }
+    portal
+        .all_neighbors()
+        .iter()
+        .filter_map(|neighbor| distances.get(neighbor))
+        .min()
+        .map(|dist| dist + PLAIN_MOVEMENT_COST)
+}
+
+/// How building on a hex changes the way of the enemies coming out of a portal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathChange {
+    pub portal: Hex,
+    /// what the way from the portal to the crystal costs, `None` when there is no way
+    pub before: Option<u32>,
+    pub after: Option<u32>,
+}
+
+impl PathChange {
+    /// How much longer the way of the enemies gets, `None` when there is no way afterwards
+    pub fn delta(&self) -> Option<i32> {
+        Some(self.after? as i32 - self.before? as i32)
+    }
+}
+
+/// How building on `hex` changes the way from each of the `portals` to the `crystals`,
+/// on a grid where `walls` are already built
+pub fn path_changes(
+    bounds: &HexBounds,
+    walls: &HashSet<Hex>,
+    terrain: &HashMap<Hex, Terrain>,
+    crystals: &[Hex],
+    portals: &[Hex],
+    hex: Hex,
+) -> Vec<PathChange> {
+    let before = distances_to_crystals(bounds, walls, terrain, crystals);
+    let mut walls = walls.clone();
+    walls.insert(hex);
+    let after = distances_to_crystals(bounds, &walls, terrain, crystals);
+    portals
+        .iter()
+        .map(|&portal| PathChange {
+            portal,
+            before: portal_distance(&before, portal),
+            after: portal_distance(&after, portal),
+        })
+        .collect()
+}
+
 fn detect_despawned_grid_content(
     mut command: Commands,
     hexes: Query<(Entity, &Children), With<HexCell>>,
@@ -185,7 +398,7 @@ fn detect_despawned_grid_content(
 ) {
     hexes.for_each(|(e, children)| {
         if !entities.contains(*children.first().unwrap()) {
-            println!(
+            debug!(
                 "Detected a hex still parenting a building that's now destroyed, cleaned children."
             );
             command.entity(e).clear_children();
@@ -193,10 +406,10 @@ fn detect_despawned_grid_content(
     });
 }
 
-fn clear_unconstructible_hexes(
-    mut command: Commands,
-    hexes: Query<Entity, (Without<Children>, With<NonConstructible>, With<HexCell>)>,
-) {
+/// Empty hexes marked as non constructible
+type EmptyNonConstructible = (Without<Children>, With<NonConstructible>, With<HexCell>);
+
+fn clear_unconstructible_hexes(mut command: Commands, hexes: Query<Entity, EmptyNonConstructible>) {
     hexes.for_each(|e| {
         command.entity(e).remove::<NonConstructible>();
     });
@@ -205,72 +418,99 @@ fn clear_unconstructible_hexes(
 fn update_unconstructible_hexes(
     mut commands: Commands,
     grid: Res<HexGrid>,
-    hexes: Query<Entity, Without<Children>>,
+    hexes: Query<&HexCell, Without<Children>>,
 ) {
-    // detect hexes that if constructed upon would prevent from having a path to the center and mark them as NonConstructible
+    // detect hexes that if constructed upon would prevent from having a path to the crystals and mark them as NonConstructible
+    let Some(&root) = grid.crystals.first() else {
+        return;
+    };
     tarjan(
-        Hex::ZERO,
+        root,
         None,
         1,
-        &mut HashMap::new(),
-        &mut HashMap::new(),
+        &mut TarjanLinks::default(),
         &mut commands,
         &grid,
         &hexes,
     );
 
+    /// The link numbers of the hexes visited so far
+    #[derive(Default)]
+    struct TarjanLinks {
+        /// lowest max depth reached per node
+        lowest: HashMap<Hex, usize>,
+        /// current depth per node
+        current: HashMap<Hex, usize>,
+    }
+
     /// Recursive depth-first search function for Tarjan's strongly connected components algorithm.
     /// This function is used to identify non-constructible entities in a grid of hexagonal cells.
     // TODO: move the bevy side out of this function and isolate it
-    pub fn tarjan(
-        hex: Hex,                               // Current hexagon being processed
-        parent: Option<Hex>,                    // Parent hexagon in the current path from root
-        depth: usize,                           // Depth of current recursion level
-        lowest_link: &mut HashMap<Hex, usize>,  // lowest max depth reached per node
-        current_link: &mut HashMap<Hex, usize>, // current depth per node
+    fn tarjan(
+        hex: Hex,            // Current hexagon being processed
+        parent: Option<Hex>, // Parent hexagon in the current path from root
+        depth: usize,        // Depth of current recursion level
+        links: &mut TarjanLinks,
         commands: &mut Commands,
         grid: &Res<HexGrid>,
-        hexes: &Query<Entity, Without<Children>>, // Query for accessing hexagon cells without content
+        hexes: &Query<&HexCell, Without<Children>>, // Query for accessing hexagon cells without content
     ) {
         // Mark the current hexagon as processed and update its lowest link number.
-        current_link.insert(hex, depth);
-        lowest_link.insert(hex, depth);
+        links.current.insert(hex, depth);
+        links.lowest.insert(hex, depth);
 
         // Get all neighboring hexagons that are not yet processed.
-        let all_neighbors = hex.all_neighbors();
-        let neighbors: Vec<&Hex> = all_neighbors
-            .iter()
+        // The crystals are a single node, reached through the first one: the enemies may go to any of them
+        let all_neighbors: Vec<Hex> = if grid.crystals.contains(&hex) {
+            grid.crystals
+                .iter()
+                .flat_map(|crystal| crystal.all_neighbors())
+                .collect()
+        } else {
+            hex.all_neighbors().to_vec()
+        };
+        let mut neighbors: Vec<Hex> = all_neighbors
+            .into_iter()
+            .map(|h| {
+                if grid.crystals.contains(&h) {
+                    grid.crystals[0]
+                } else {
+                    h
+                }
+            })
             .filter(|h|
                 // check that the neighbors exist in the grid and they can be queried (=> meaning that they have no content)
-                grid.hex_to_entity(h).and_then(|&e| hexes.get(e).ok()).is_some())
+                // and that the enemies can cross them
+                *h != hex && grid.hex_to_entity(h).and_then(|&e| hexes.get(e).ok()).is_some_and(|cell| cell.terrain.movement_cost().is_some()))
             .collect::<Vec<_>>();
+        neighbors.sort_by_key(|h| [h.x, h.y]);
+        neighbors.dedup();
 
         // Initialize the count of children for the current hexagon.
         let mut children: usize = 0;
 
         // Iterate through all neighboring hexagons and recursively call tarjan function if needed.
-        for neighbor in neighbors {
+        for neighbor in &neighbors {
             // If we discover a new node, increment the number of children and recurse.
-            if !current_link.contains_key(neighbor) {
+            if !links.current.contains_key(neighbor) {
                 children += 1;
 
                 tarjan(
                     *neighbor,
                     Some(hex),
                     depth + 1,
-                    lowest_link,
-                    current_link,
+                    links,
                     commands,
                     grid,
                     hexes,
                 );
-                let lowest_neighbour_link = lowest_link.get(neighbor).copied().unwrap();
-                let lowest_hex_link = lowest_link.get(&hex).copied().unwrap();
-                let current_hex_link = current_link.get(&hex).copied().unwrap();
+                let lowest_neighbour_link = links.lowest.get(neighbor).copied().unwrap();
+                let lowest_hex_link = links.lowest.get(&hex).copied().unwrap();
+                let current_hex_link = links.current.get(&hex).copied().unwrap();
 
                 // Update the lowest link number for the current hexagon if necessary.
                 if lowest_hex_link > lowest_neighbour_link {
-                    lowest_link.insert(hex, lowest_neighbour_link);
+                    links.lowest.insert(hex, lowest_neighbour_link);
                 }
                 // If the parent is set and the neighbor's lowest link number is greater than or equal to the current hexagon's link number, mark the current hexagon as non-constructible.
                 if lowest_neighbour_link >= current_hex_link && parent.is_some() {
@@ -280,10 +520,10 @@ fn update_unconstructible_hexes(
                 }
             } else if Some(*neighbor) != parent {
                 // If the neighbor is already processed, update the lowest link number for the current hexagon if necessary.
-                let lowest_neighbour_link = lowest_link.get(neighbor).copied().unwrap();
-                let lowest_hex_link = lowest_link.get(&hex).copied().unwrap();
+                let lowest_neighbour_link = links.lowest.get(neighbor).copied().unwrap();
+                let lowest_hex_link = links.lowest.get(&hex).copied().unwrap();
                 if lowest_hex_link > lowest_neighbour_link {
-                    lowest_link.insert(hex, lowest_neighbour_link);
+                    links.lowest.insert(hex, lowest_neighbour_link);
                 }
             }
         }
@@ -297,47 +537,120 @@ fn update_unconstructible_hexes(
     }
 }
 
+/// The constructible hexes, and the materials telling which one is selected
+#[derive(SystemParam)]
+pub struct SelectableHexes<'w, 's> {
+    hexes: Query<'w, 's, &'static Handle<HexMaterial>, Without<NonConstructible>>,
+    materials: ResMut<'w, Assets<HexMaterial>>,
+}
+
+impl SelectableHexes<'_, '_> {
+    /// Whether the hex is selected, `None` when it isn't constructible
+    fn is_selected(&self, hex: Entity) -> Option<bool> {
+        let material = self.hexes.get(hex).ok()?;
+        Some(self.materials.get(material)?.is_selected != 0.)
+    }
+
+    fn unselect(&mut self, hex: Entity) {
+        if let Ok(material) = self.hexes.get(hex) {
+            if let Some(material) = self.materials.get_mut(material) {
+                material.is_selected = 0.;
+            }
+        }
+    }
+}
+
 pub fn on_hex_clicked(
-    mut commands: Commands,
     mut clicks: EventReader<HexClicked>,
-    hexes: Query<(&Transform, &Handle<HexMaterial>), Without<NonConstructible>>,
-    mut materials: ResMut<Assets<HexMaterial>>,
     grid: Res<HexGrid>,
+    mut selectable: SelectableHexes,
+    q_obstacles: Query<&Parent, With<Obstacle>>,
+    q_inventory: Query<&Inventory<Building>>,
+    mut player_commands: EventWriter<EventPlayerCommand>,
+    versus: Option<Res<Versus>>,
 ) {
     for click in clicks.read() {
-        if let Ok((transform, material)) = hexes.get(click.target) {
+        // a left click on an obstacle, or on its hex, clears it
+        let clicked_hex = q_obstacles
+            .get(click.target)
+            .map_or(click.target, |parent| parent.get());
+        if click.event.button == PointerButton::Primary
+            && q_obstacles.iter().any(|parent| parent.get() == clicked_hex)
+        {
+            if let Some(hex) = grid.entity_to_hex(clicked_hex) {
+                player_commands.send(EventPlayerCommand(PlayerCommand::ClearObstacle {
+                    hex: [hex.x, hex.y],
+                }));
+            }
+            continue;
+        }
+        if let Some(selected) = selectable.is_selected(click.target) {
             // early return if we clicked on an unselected hex
-            if materials.get_mut(material).unwrap().is_selected == 0. {
+            if !selected {
                 return;
             }
-            let spawned_id = match click.event.button {
-                PointerButton::Secondary => Some(
-                    commands
-                        .spawn_empty()
-                        .add(SpawnPortalCmd {
-                            parent_hex: click.target,
-                        })
-                        .id(),
-                ),
-                PointerButton::Primary => Some(
-                    commands
-                        .spawn_empty()
-                        .add(SpawnTurretCmd {
-                            parent_hex: click.target,
-                        })
-                        .id(),
-                ),
+            let Some(hex) = grid.entity_to_hex(click.target) else {
+                continue;
+            };
+            let hex = [hex.x, hex.y];
+            let command = match click.event.button {
+                // in a versus match, the portals belong to the attacker and its gamepad
+                PointerButton::Secondary if versus.is_none() => {
+                    Some(PlayerCommand::OpenPortal { hex })
+                }
+                // the picked building, or the first one
+                PointerButton::Primary => q_inventory
+                    .get_single()
+                    .ok()
+                    .and_then(|inventory| {
+                        let item = inventory.next_item()?;
+                        inventory.items.iter().position(|&e| e == item)
+                    })
+                    .map(|slot| PlayerCommand::Build { hex, slot }),
                 _ => None,
             };
-            if let Some(_) = spawned_id {
-                // mark the hex as not selected since something spawned on it
-                materials.get_mut(material).unwrap().is_selected = 0.;
+            if let Some(command) = command {
+                player_commands.send(EventPlayerCommand(command));
+                // mark the hex as not selected since something will be spawned on it
+                selectable.unselect(click.target);
             }
         }
         // TODO: else, the hex is not constructible. Make it clear to the player!
     }
 }
 
+/// Builds the dropped inventory item on the hex, if the hex is free
+pub fn on_hex_dropped(
+    mut drops: EventReader<HexDropped>,
+    grid: Res<HexGrid>,
+    hexes: Query<&Handle<HexMaterial>, (Without<NonConstructible>, Without<Children>)>,
+    q_inventory: Query<&Inventory<Building>>,
+    mut materials: ResMut<Assets<HexMaterial>>,
+    mut player_commands: EventWriter<EventPlayerCommand>,
+) {
+    for drop in drops.read() {
+        let Ok(material) = hexes.get(drop.target) else {
+            // the item goes back to its slot at the end of the drag
+            continue;
+        };
+        let Some(hex) = grid.entity_to_hex(drop.target) else {
+            continue;
+        };
+        let Some(slot) = q_inventory
+            .get_single()
+            .ok()
+            .and_then(|inventory| inventory.items.iter().position(|&e| e == drop.dropped))
+        else {
+            continue;
+        };
+        player_commands.send(EventPlayerCommand(PlayerCommand::Build {
+            hex: [hex.x, hex.y],
+            slot,
+        }));
+        materials.get_mut(material).unwrap().is_selected = 0.;
+    }
+}
+
 pub fn debug_display_non_constructible_hexes(
     grid: Res<HexGrid>,
     hexes: Query<(&Handle<HexMaterial>, Option<&NonConstructible>)>,
@@ -356,12 +669,167 @@ pub fn debug_display_non_constructible_hexes(
                     material.color.x = 1.0;
                     material.color.y = 0.0;
                     material.color.z = 0.0;
-                    println!("Toggling unconstrible red ON {:?}", hex);
+                    debug!("Toggling unconstrible red ON {:?}", hex);
                 } else if let Some(old_color) = cached_hex_colors.get(entity) {
-                    println!("Toggling unconstrible red OFF {:?}", hex);
+                    debug!("Toggling unconstrible red OFF {:?}", hex);
                     material.color = *old_color;
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actions::player_command::PlayerCommand,
+        balance::Balance,
+        buildings::{Building, BuildingColor, BuildingMesh, BuildingSize},
+        economy::Energy,
+        testing::TestRun,
+    };
+
+    #[test]
+    fn building_across_the_way_of_a_portal_lengthens_it() {
+        let bounds = HexBounds::new(Hex::ZERO, MAP_RADIUS);
+        let portal = Hex::new(3, 0);
+        // the enemies go around the wall
+        let walls: HashSet<Hex> = [Hex::new(2, -1), Hex::new(1, 1)].into_iter().collect();
+
+        let crystals = [Hex::ZERO];
+        let terrain = HashMap::new();
+        let changes = path_changes(
+            &bounds,
+            &walls,
+            &terrain,
+            &crystals,
+            &[portal],
+            Hex::new(1, 0),
+        );
+        assert_eq!(changes[0].before, Some(3 * PLAIN_MOVEMENT_COST));
+        assert_eq!(changes[0].delta(), Some(2 * PLAIN_MOVEMENT_COST as i32));
+        let changes = path_changes(
+            &bounds,
+            &walls,
+            &terrain,
+            &crystals,
+            &[portal],
+            Hex::new(-3, 0),
+        );
+        assert_eq!(changes[0].delta(), Some(0));
+    }
+
+    #[test]
+    fn the_enemies_go_around_the_water_when_it_is_shorter() {
+        let bounds = HexBounds::new(Hex::ZERO, MAP_RADIUS);
+        let walls = HashSet::new();
+        let crystals = [Hex::ZERO];
+        // a single water hex is worth a detour of one hex
+        let terrain: HashMap<Hex, Terrain> = [(Hex::new(1, 0), Terrain::Water)].into();
+        let distances = distances_to_crystals(&bounds, &walls, &terrain, &crystals);
+        assert_eq!(distances[&Hex::new(1, 0)], 6);
+        assert_eq!(distances[&Hex::new(2, 0)], 6);
+        // a river across the way has to be waded through
+        let terrain: HashMap<Hex, Terrain> = bounds
+            .all_coords()
+            .filter(|hex| hex.x == 1)
+            .map(|hex| (hex, Terrain::Water))
+            .collect();
+        let distances = distances_to_crystals(&bounds, &walls, &terrain, &crystals);
+        assert_eq!(distances[&Hex::new(2, 0)], 8);
+        // and a chasm can't be crossed at all
+        let terrain: HashMap<Hex, Terrain> = terrain
+            .into_keys()
+            .map(|hex| (hex, Terrain::Void))
+            .collect();
+        let distances = distances_to_crystals(&bounds, &walls, &terrain, &crystals);
+        assert!(!distances.contains_key(&Hex::new(2, 0)));
+    }
+
+    #[test]
+    fn the_enemies_take_a_detour_over_boost_pads() {
+        let bounds = HexBounds::new(Hex::ZERO, MAP_RADIUS);
+        let walls = HashSet::new();
+        let crystals = [Hex::ZERO];
+        let terrain: HashMap<Hex, Terrain> = [Hex::new(1, -1), Hex::new(2, -1), Hex::new(3, -1)]
+            .into_iter()
+            .map(|hex| (hex, Terrain::BoostPad))
+            .collect();
+        let distances = distances_to_crystals(&bounds, &walls, &terrain, &crystals);
+        // one hex longer than the straight way, but faster
+        assert_eq!(distances[&Hex::new(3, 0)], 3 + PLAIN_MOVEMENT_COST);
+        assert!(distances[&Hex::new(3, -1)] < distances[&Hex::new(2, 0)]);
+    }
+
+    #[test]
+    fn a_wall_cutting_the_path_to_the_crystal_is_refused() {
+        let mut run = TestRun::new();
+        let building = Building::new(
+            BuildingMesh::Quad,
+            BuildingSize::Small,
+            BuildingColor::White,
+        );
+        let neighbors = Hex::ZERO.all_neighbors();
+        let (last, walls) = neighbors.split_last().unwrap();
+        for &hex in walls {
+            assert!(run.place_turret(hex, building).is_some());
+        }
+
+        assert!(!run.is_constructible(*last));
+        assert!(run.place_turret(*last, building).is_none());
+        run.command(PlayerCommand::Build {
+            hex: [last.x, last.y],
+            slot: 0,
+        });
+        assert!(run.turret_on(*last).is_none());
+    }
+
+    #[test]
+    fn a_crystal_can_be_walled_in_while_another_one_is_reachable() {
+        let mut map = MapDefinition::default();
+        map.set([5, 0], Some(MapTile::Crystal));
+        map.set([-3, 0], Some(MapTile::Blocker));
+        let mut run = TestRun::on_map(map);
+        let building = Building::new(
+            BuildingMesh::Quad,
+            BuildingSize::Small,
+            BuildingColor::White,
+        );
+        assert!(run.place_turret(Hex::new(5, 0), building).is_none());
+        assert!(run.place_turret(Hex::new(-3, 0), building).is_none());
+        for hex in Hex::ZERO.all_neighbors() {
+            assert!(run.place_turret(hex, building).is_some());
+        }
+    }
+
+    #[test]
+    fn clearing_an_obstacle_opens_the_way_it_was_blocking() {
+        let mut map = MapDefinition::default();
+        let neighbors = Hex::ZERO.all_neighbors();
+        let (last, obstacles) = neighbors.split_last().unwrap();
+        for hex in obstacles {
+            map.set([hex.x, hex.y], Some(MapTile::Obstacle));
+        }
+        let mut run = TestRun::on_map(map);
+        assert!(!run.is_constructible(*last));
+
+        let clear = PlayerCommand::ClearObstacle {
+            hex: [obstacles[0].x, obstacles[0].y],
+        };
+        run.world().resource_mut::<Energy>().restore(10);
+        run.command(clear);
+        assert!(!run.is_constructible(*last));
+        run.world().resource_mut::<Energy>().restore(100);
+        run.command(clear);
+        run.advance_ticks(1);
+        let cost = run
+            .world()
+            .resource::<Balance>()
+            .economy
+            .obstacle_clear_cost;
+        assert_eq!(run.world().resource::<Energy>().balance(), 100 - cost);
+        assert!(run.is_constructible(*last));
+        assert!(run.is_constructible(obstacles[0]));
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+/// how many more hexes a turret on an energy node sees
+pub const ENERGY_NODE_RANGE_BONUS: f32 = 1.;
+/// what crossing a plain hex costs to the enemies, leaving room for cheaper terrains
+pub const PLAIN_MOVEMENT_COST: u32 = 2;
+
+/// The ground of a hex, changing how the enemies cross it and what can be built on it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Terrain {
+    #[default]
+    Plain,
+    /// slows the enemies down
+    Rough,
+    /// speeds the enemies up
+    BoostPad,
+    /// the enemies wade through it, nothing can be built on it
+    Water,
+    /// a hole nothing crosses and nothing is built on
+    Void,
+    /// turrets built on it see further
+    EnergyNode,
+}
+
+impl Terrain {
+    /// What crossing the hex costs to the enemies, [`PLAIN_MOVEMENT_COST`] for a plain hex.
+    /// `None` when they can't
+    pub fn movement_cost(&self) -> Option<u32> {
+        match self {
+            Terrain::Plain | Terrain::EnergyNode => Some(PLAIN_MOVEMENT_COST),
+            Terrain::BoostPad => Some(1),
+            Terrain::Rough => Some(4),
+            Terrain::Water => Some(6),
+            Terrain::Void => None,
+        }
+    }
+
+    /// How fast the enemies move on the hex, relative to their velocity
+    pub fn speed_factor(&self) -> f32 {
+        match self {
+            Terrain::Plain | Terrain::EnergyNode | Terrain::Void => 1.,
+            Terrain::Rough => 0.5,
+            Terrain::BoostPad => 1.5,
+            Terrain::Water => 1. / 3.,
+        }
+    }
+
+    pub fn is_constructible(&self) -> bool {
+        !matches!(self, Terrain::Water | Terrain::Void)
+    }
+
+    /// Extra range of the turrets built on the hex, in hexes
+    pub fn range_bonus(&self) -> f32 {
+        match self {
+            Terrain::EnergyNode => ENERGY_NODE_RANGE_BONUS,
+            _ => 0.,
+        }
+    }
+
+    /// Picks how `hex.wgsl` draws the hex
+    pub fn style(&self) -> f32 {
+        match self {
+            Terrain::Plain => 0.,
+            Terrain::Rough => 1.,
+            Terrain::BoostPad => 2.,
+            Terrain::Water => 3.,
+            Terrain::Void => 4.,
+            Terrain::EnergyNode => 5.,
+        }
+    }
+}
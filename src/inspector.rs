@@ -0,0 +1,300 @@
+use std::f32::consts::FRAC_PI_6;
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+    window::PrimaryWindow,
+};
+use bevy_vector_shapes::prelude::*;
+use hexx::Hex;
+
+use crate::{
+    actions::{cursor::CursorScreenPos, game_control::GameControl},
+    entities::{
+        portal::Portal,
+        turret::{AutoGun, Turret, TurretStats},
+    },
+    grid::{HexCell, HexGrid, NonConstructible, PLAIN_MOVEMENT_COST},
+    primitives::{target::Target, view::View},
+    GameState,
+};
+
+pub struct InspectorPlugin;
+
+/// This plugin shows information about the turrets to the player:
+///   - hovering or clicking a turret shows its range, its current target and its stats
+///   - holding [`GameControl::ShowCoverage`] paints how many turrets cover each hex
+///   - hovering a hex that can be built on tells how much longer the way from each portal
+///     to the crystal would get
+impl Plugin for InspectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InspectedTurret>()
+            .add_systems(
+                OnEnter(GameState::Playing),
+                (spawn_inspector_panel, spawn_placement_tooltip),
+            )
+            .add_systems(
+                Update,
+                (
+                    update_inspected_turret,
+                    draw_inspected_turret,
+                    update_inspector_panel,
+                    draw_coverage_heatmap,
+                    update_placement_tooltip,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Playing).and_then(resource_exists::<HexGrid>())),
+            );
+    }
+}
+
+/// The turret currently under the cursor and the one that was last clicked
+#[derive(Resource, Default, Debug)]
+pub struct InspectedTurret {
+    pub hovered: Option<Entity>,
+    pub selected: Option<Entity>,
+}
+
+impl InspectedTurret {
+    /// The hovered turret takes precedence over the selected one
+    pub fn get(&self) -> Option<Entity> {
+        self.hovered.or(self.selected)
+    }
+}
+
+#[derive(Component)]
+struct InspectorPanel;
+
+#[derive(Component)]
+struct PlacementTooltip;
+
+const RANGE_COLOR: Color = Color::LIME_GREEN;
+const TARGET_COLOR: Color = Color::ORANGE_RED;
+const UNCOVERED_COLOR: Color = Color::rgba(1.0, 0.0, 0.3, 0.35);
+const COVERED_COLOR: Color = Color::rgba(0.0, 1.0, 0.9, 0.0);
+const COVERAGE_ALPHA_PER_TURRET: f32 = 0.2;
+const COVERAGE_MAX_ALPHA: f32 = 0.7;
+/// where the tooltip is drawn, from the cursor
+const TOOLTIP_OFFSET: Vec2 = Vec2::new(16.0, 16.0);
+
+fn spawn_inspector_panel(mut commands: Commands) {
+    let style = TextStyle {
+        font_size: 20.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+        ..default()
+    };
+    commands.spawn((
+        TextBundle::from_sections(
+            ["Turret\n", "", "", "", ""].map(|text| TextSection::new(text, style.clone())),
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            right: Val::Px(10.0),
+            ..default()
+        })
+        .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        InspectorPanel,
+        Name::new("Inspector panel"),
+    ));
+}
+
+fn spawn_placement_tooltip(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 18.0,
+                color: Color::rgb(0.9, 0.9, 0.9),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            ..default()
+        })
+        .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        Visibility::Hidden,
+        PlacementTooltip,
+        Name::new("Placement tooltip"),
+    ));
+}
+
+fn update_inspected_turret(
+    cursor: Res<CursorScreenPos>,
+    mouse_input: Res<Input<MouseButton>>,
+    grid: Res<HexGrid>,
+    hexes: Query<&Children, With<HexCell>>,
+    turrets: Query<(), With<Turret>>,
+    mut inspected: ResMut<InspectedTurret>,
+) {
+    let hex = grid.layout.world_pos_to_hex(cursor.0);
+    let hovered = grid
+        .hex_to_entity(&hex)
+        .and_then(|&entity| hexes.get(entity).ok())
+        .and_then(|content| content.iter().find(|&&e| turrets.contains(e)).copied());
+
+    if inspected.hovered != hovered {
+        inspected.hovered = hovered;
+    }
+    if mouse_input.just_pressed(MouseButton::Left) {
+        // clicking the selected turret again, or anything else, clears the selection
+        inspected.selected = if hovered == inspected.selected {
+            None
+        } else {
+            hovered
+        };
+    }
+    if inspected.selected.is_some_and(|e| !turrets.contains(e)) {
+        inspected.selected = None;
+    }
+}
+
+fn draw_inspected_turret(
+    mut gizmos: Gizmos,
+    inspected: Res<InspectedTurret>,
+    turrets: Query<(&GlobalTransform, &View, Option<&Target>), With<Turret>>,
+    targets: Query<&GlobalTransform, Without<Turret>>,
+) {
+    let Some((transform, view, target)) = inspected.get().and_then(|e| turrets.get(e).ok()) else {
+        return;
+    };
+    let position = transform.translation().xy();
+    gizmos.circle_2d(position, view.range(), RANGE_COLOR);
+    if let Some(target_transform) = target.and_then(|t| targets.get(t.entity).ok()) {
+        gizmos.line_2d(position, target_transform.translation().xy(), TARGET_COLOR);
+    }
+}
+
+fn update_inspector_panel(
+    inspected: Res<InspectedTurret>,
+    turrets: Query<(&AutoGun, &TurretStats, &View), With<Turret>>,
+    mut panel: Query<(&mut Text, &mut Visibility), With<InspectorPanel>>,
+) {
+    let Ok((mut text, mut visibility)) = panel.get_single_mut() else {
+        return;
+    };
+    let Some((gun, stats, view)) = inspected.get().and_then(|e| turrets.get(e).ok()) else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    *visibility = Visibility::Inherited;
+    text.sections[1].value = format!("DPS: {:.1}\n", gun.dps());
+    text.sections[2].value = format!("Fire rate: {:.1}/s\n", gun.shots_per_second());
+    text.sections[3].value = if view.has_anti_air() {
+        format!("Range: {:.0}, anti-air\n", view.range())
+    } else {
+        format!("Range: {:.0}\n", view.range())
+    };
+    text.sections[4].value = format!("Kills: {}", stats.kills);
+}
+
+fn draw_coverage_heatmap(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut painter: ShapePainter,
+    grid: Res<HexGrid>,
+    turrets: Query<(&GlobalTransform, &View), With<Turret>>,
+) {
+    if !GameControl::ShowCoverage.pressed(&keyboard_input) {
+        return;
+    }
+    let views: Vec<(Vec2, f32)> = turrets
+        .iter()
+        .map(|(transform, view)| (transform.translation().xy(), view.range()))
+        .collect();
+
+    painter.hollow = false;
+    // the layout is flat-topped while the polygons are drawn pointy-topped
+    painter.set_rotation(Quat::from_rotation_z(FRAC_PI_6));
+    for hex in grid.bounds.all_coords() {
+        let position = grid.layout.hex_to_world_pos(hex);
+        let coverage = views
+            .iter()
+            .filter(|(turret_position, range)| turret_position.distance(position) <= *range)
+            .count();
+
+        painter.color = if coverage == 0 {
+            UNCOVERED_COLOR
+        } else {
+            COVERED_COLOR
+                .with_a((COVERAGE_ALPHA_PER_TURRET * coverage as f32).min(COVERAGE_MAX_ALPHA))
+        };
+        // draw between the hexes and the enemies
+        painter.set_translation(position.extend(-0.5));
+        painter.ngon(6.0, grid.layout.hex_size.x * 0.95);
+    }
+}
+
+fn update_placement_tooltip(
+    cursor: Res<CursorScreenPos>,
+    q_window: Query<&Window, With<PrimaryWindow>>,
+    grid: Res<HexGrid>,
+    hexes: Query<(&HexCell, Option<&Children>, Option<&NonConstructible>)>,
+    portals: Query<&Parent, With<Portal>>,
+    mut tooltip: Query<(&mut Text, &mut Style, &mut Visibility), With<PlacementTooltip>>,
+) {
+    let Ok((mut text, mut style, mut visibility)) = tooltip.get_single_mut() else {
+        return;
+    };
+    let hex = grid.layout.world_pos_to_hex(cursor.0);
+    let constructible = grid
+        .hex_to_entity(&hex)
+        .and_then(|&entity| hexes.get(entity).ok())
+        .is_some_and(|(cell, content, non_constructible)| {
+            content.is_none() && non_constructible.is_none() && cell.terrain.is_constructible()
+        });
+    let window_cursor = q_window
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position());
+    let Some(window_cursor) = window_cursor.filter(|_| constructible && !grid.is_reserved(&hex))
+    else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    let mut portal_hexes: Vec<Hex> = portals
+        .iter()
+        .filter_map(|parent| grid.entity_to_hex(parent.get()))
+        .collect();
+    if portal_hexes.is_empty() {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    portal_hexes.sort_by_key(|hex| [hex.x, hex.y]);
+
+    let mut walls = HashSet::new();
+    let mut terrain = HashMap::new();
+    for hex in grid.bounds.all_coords() {
+        let Some((cell, content, _)) = grid
+            .hex_to_entity(&hex)
+            .and_then(|&entity| hexes.get(entity).ok())
+        else {
+            continue;
+        };
+        if content.is_some() {
+            walls.insert(hex);
+        }
+        terrain.insert(hex, cell.terrain);
+    }
+    let lines: Vec<String> = grid
+        .path_changes(&walls, &terrain, &portal_hexes, hex)
+        .iter()
+        .map(|change| match change.delta() {
+            // in plain hexes
+            Some(delta) => format!(
+                "Path from ({}, {}): {:+}",
+                change.portal.x,
+                change.portal.y,
+                delta as f32 / PLAIN_MOVEMENT_COST as f32
+            ),
+            None => format!(
+                "Path from ({}, {}): blocked",
+                change.portal.x, change.portal.y
+            ),
+        })
+        .collect();
+    text.sections[0].value = lines.join("\n");
+    style.left = Val::Px(window_cursor.x + TOOLTIP_OFFSET.x);
+    style.top = Val::Px(window_cursor.y + TOOLTIP_OFFSET.y);
+    *visibility = Visibility::Inherited;
+}
@@ -1,9 +1,22 @@
 use bevy::ecs::system::EntityCommand;
 use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use bevy::utils::HashSet;
+use bevy_easings::{Ease, EaseFunction, EasingComponent, EasingType};
+use bevy_eventlistener::{callbacks::Listener, event_listener::On};
+use bevy_mod_picking::prelude::{Click, Drag, DragEnd, DragStart, Pickable, Pointer};
 use std::collections::VecDeque;
 use std::marker::PhantomData;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::window::WindowSize;
 
 /// This plugin handles the creation of Items in the inventory
+/// Visible items can be clicked to pick them, or dragged: dropping them is up to the drop target
+/// Item visuals are children of the inventory entity and slide between the slots of its layout
+/// Every change of content is reported with an [`InventoryChanged`] event
 pub struct InventoryPlugin<IT: Component + ItemSpriteBuilder> {
     _item_type: PhantomData<IT>,
 }
@@ -18,15 +31,18 @@ impl<IT: Component + ItemSpriteBuilder> Default for InventoryPlugin<IT> {
 
 impl<IT: Component + ItemSpriteBuilder> Plugin for InventoryPlugin<IT> {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            PostUpdate,
-            (
-                item_create_sprite::<IT>,
-                apply_deferred,
-                redraw_inventory_on_change::<IT>,
+        app.add_event::<InventoryChanged>()
+            .add_systems(
+                PostUpdate,
+                (
+                    item_create_sprite::<IT>,
+                    apply_deferred,
+                    redraw_inventory_on_change::<IT>,
+                    report_inventory_changes::<IT>,
+                )
+                    .chain(),
             )
-                .chain(),
-        );
+            .add_systems(Update, anchor_to_screen);
     }
 }
 
@@ -38,16 +54,125 @@ pub trait ItemSpriteBuilder {
 #[derive(Component)]
 struct MarkerItemSpriteBuilt;
 
+/// the picked item is drawn shifted to the right
+const PICKED_OFFSET: Vec3 = Vec3::new(16.0, 0.0, 0.0);
+/// a dragged item is drawn above everything else
+const DRAGGED_Z: f32 = 10.0;
+const SLOT_EASING_DURATION: Duration = Duration::from_millis(200);
+/// upcoming items are drawn smaller than the ones in a slot
+const PREVIEW_SCALE: f32 = 0.5;
+
+/// the scale given by [`ItemSpriteBuilder::build_sprite`], before any preview shrinking
+#[derive(Component)]
+struct ItemBaseScale(Vec3);
+
+/// Sent when items enter or leave an inventory. Moving an item inside it, or holding it, doesn't count.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct InventoryChanged {
+    pub inventory: Entity,
+    pub added: Vec<Entity>,
+    pub removed: Vec<Entity>,
+}
+
 #[derive(Component)]
 pub struct Inventory<IT: Component + ItemSpriteBuilder> {
     /// entities contained here have a MarkerItem component, it handles logic
     /// their rendering is created via item_create_visual
     pub items: VecDeque<Entity>,
-    pub positions: Vec<Vec3>,
+    /// maximum number of items in the queue, see [`Inventory::push`]
+    pub capacity: usize,
+    /// number of items shown
+    pub slots: usize,
+    /// number of upcoming items shown after the slots, they can't be picked
+    pub preview: usize,
+    pub layout: InventoryLayout,
+    /// an item stashed by the player, to be swapped back later
+    pub hold: Option<Entity>,
+    /// relative to the inventory entity
+    pub hold_position: Vec3,
+    /// an item the player clicked on, to be used instead of the first one
+    pub picked: Option<Entity>,
 
+    /// content at the time of the last [`InventoryChanged`]
+    reported: HashSet<Entity>,
     _item_type: PhantomData<IT>,
 }
 
+impl<IT: Component + ItemSpriteBuilder> Inventory<IT> {
+    pub fn is_full(&self) -> bool {
+        self.items.len() >= self.capacity
+    }
+
+    /// Adds `item` at the end of the queue, returns false if the inventory is full
+    pub fn push(&mut self, item: Entity) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.items.push_back(item);
+        true
+    }
+
+    /// The next `count` items after the visible slots
+    pub fn upcoming(&self, count: usize) -> impl Iterator<Item = &Entity> {
+        self.items.iter().skip(self.slots).take(count)
+    }
+
+    /// Every item the inventory owns
+    fn content(&self) -> impl Iterator<Item = &Entity> {
+        self.items.iter().chain(self.hold.iter())
+    }
+
+    /// Swaps the first item with the held one.
+    /// Returns true if nothing was held: the first item is now held and the queue is one item shorter.
+    pub fn hold_first(&mut self) -> bool {
+        let Some(first) = self.items.pop_front() else {
+            return false;
+        };
+        if self.picked == Some(first) {
+            self.picked = None;
+        }
+        match self.hold.replace(first) {
+            Some(held) => {
+                self.items.push_front(held);
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// The item to use next: the picked one, or the first of the queue
+    pub fn next_item(&self) -> Option<Entity> {
+        self.picked.or(self.items.front().copied())
+    }
+
+    /// Only the items shown in a slot can be picked or taken
+    pub fn is_visible(&self, item: Entity) -> bool {
+        self.items.iter().take(self.slots).any(|&e| e == item)
+    }
+
+    /// Picks `item`, or unpicks it if it was already picked
+    pub fn toggle_pick(&mut self, item: Entity) {
+        if self.picked == Some(item) {
+            self.picked = None;
+        } else if self.is_visible(item) {
+            self.picked = Some(item);
+        }
+    }
+
+    /// Removes `item` from its slot, the following items move up.
+    /// Returns false if the item is not in a visible slot.
+    pub fn take(&mut self, item: Entity) -> bool {
+        if !self.is_visible(item) {
+            return false;
+        }
+        self.items.retain(|&e| e != item);
+        if self.picked == Some(item) {
+            self.picked = None;
+        }
+        true
+    }
+}
+
 pub struct SpawnInventory<IT: Component + ItemSpriteBuilder> {
     items: Vec<Entity>,
     configuration: InventoryConfiguration,
@@ -69,10 +194,82 @@ where
 }
 
 /// Configuration for the inventory
-///   positions: Vec<Vec3> - positions of the items in the inventory
-/// TODO: should be relative to the inventory entity/transform
+///   capacity: usize - maximum number of items in the queue
+///   slots: usize - number of items shown
+///   preview: usize - number of upcoming items shown after the slots
+///   layout: InventoryLayout - how the slots are laid out around the inventory entity
+///   hold_position: Vec3 - position of the held item, relative to the inventory entity
 pub struct InventoryConfiguration {
-    pub positions: Vec<Vec3>,
+    pub capacity: usize,
+    pub slots: usize,
+    pub preview: usize,
+    pub layout: InventoryLayout,
+    pub hold_position: Vec3,
+}
+
+/// Where each slot is, relative to the inventory entity. The first slot is always at the origin.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InventoryLayout {
+    /// a column, going up for a positive spacing
+    Vertical { spacing: f32 },
+    /// a row, going right for a positive spacing
+    Horizontal { spacing: f32 },
+    /// rows of `columns` slots, filled left to right then going up
+    Grid { columns: usize, spacing: Vec2 },
+    /// slots spread evenly along an arc around a center `radius` away from the first slot
+    Arc {
+        radius: f32,
+        start_angle: f32,
+        end_angle: f32,
+    },
+}
+
+impl InventoryLayout {
+    pub fn slot_position(&self, slot: usize, slots: usize) -> Vec3 {
+        match *self {
+            InventoryLayout::Vertical { spacing } => Vec3::Y * spacing * slot as f32,
+            InventoryLayout::Horizontal { spacing } => Vec3::X * spacing * slot as f32,
+            InventoryLayout::Grid { columns, spacing } => {
+                let columns = columns.max(1);
+                let cell = Vec2::new((slot % columns) as f32, (slot / columns) as f32);
+                (cell * spacing).extend(0.0)
+            }
+            InventoryLayout::Arc {
+                radius,
+                start_angle,
+                end_angle,
+            } => {
+                let progress = slot as f32 / (slots.max(2) - 1) as f32;
+                let angle = start_angle + (end_angle - start_angle) * progress;
+                let center = -Vec2::from_angle(start_angle) * radius;
+                (center + Vec2::from_angle(angle) * radius).extend(0.0)
+            }
+        }
+    }
+}
+
+/// Keeps an entity at a fixed place on the screen, whatever the window size
+#[derive(Component, Debug, Clone)]
+pub struct ScreenAnchor {
+    pub anchor: Anchor,
+    /// from the anchor point, towards the center of the screen for positive values
+    /// (up or right along a centered axis)
+    pub offset: Vec2,
+}
+
+fn anchor_to_screen(
+    window_size: Res<WindowSize>,
+    mut anchored: Query<(Ref<ScreenAnchor>, &mut Transform)>,
+) {
+    for (screen_anchor, mut transform) in &mut anchored {
+        if !window_size.is_changed() && !screen_anchor.is_changed() {
+            continue;
+        }
+        let anchor = screen_anchor.anchor.as_vec();
+        let direction = Vec2::select(anchor.cmpeq(Vec2::ZERO), Vec2::ONE, -anchor.signum());
+        let position = anchor * window_size.size + direction * screen_anchor.offset;
+        transform.translation = position.extend(transform.translation.z);
+    }
 }
 
 impl<IT> EntityCommand for SpawnInventory<IT>
@@ -82,42 +279,356 @@ where
     fn apply(self, id: Entity, world: &mut World) {
         world.entity_mut(id).insert((Inventory::<IT> {
             items: self.items.into_iter().collect(),
-            positions: self.configuration.positions,
+            capacity: self.configuration.capacity,
+            slots: self.configuration.slots,
+            preview: self.configuration.preview,
+            layout: self.configuration.layout,
+            hold: None,
+            hold_position: self.configuration.hold_position,
+            picked: None,
+            reported: HashSet::new(),
             _item_type: self._item_type,
         },));
     }
 }
 
+/// The content of an inventory, without its entities
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedInventory<IT> {
+    pub items: Vec<IT>,
+    pub hold: Option<IT>,
+}
+
+impl<IT: Component + ItemSpriteBuilder + Clone> Inventory<IT> {
+    pub fn save(&self, world: &World) -> SavedInventory<IT> {
+        let item = |entity: &Entity| world.get::<IT>(*entity).cloned();
+        SavedInventory {
+            items: self.items.iter().filter_map(item).collect(),
+            hold: self.hold.as_ref().and_then(item),
+        }
+    }
+}
+
+/// Replaces the content of the inventory with saved items
+pub struct RestoreInventory<IT> {
+    pub saved: SavedInventory<IT>,
+}
+
+impl<IT: Component + ItemSpriteBuilder> EntityCommand for RestoreInventory<IT> {
+    fn apply(self, id: Entity, world: &mut World) {
+        let Some(inventory) = world.get::<Inventory<IT>>(id) else {
+            return;
+        };
+        let old_content: Vec<Entity> = inventory.content().copied().collect();
+        for item in old_content {
+            world.entity_mut(item).despawn_recursive();
+        }
+        let items = self
+            .saved
+            .items
+            .into_iter()
+            .map(|item| world.spawn(item).id())
+            .collect();
+        let hold = self.saved.hold.map(|item| world.spawn(item).id());
+        let mut inventory = world.get_mut::<Inventory<IT>>(id).unwrap();
+        inventory.items = items;
+        inventory.hold = hold;
+        inventory.picked = None;
+    }
+}
+
 fn item_create_sprite<IT: Component + ItemSpriteBuilder>(
     mut commands: Commands,
-    inventory: Query<&Inventory<IT>, Changed<Inventory<IT>>>,
+    inventory: Query<(Entity, &Inventory<IT>), Changed<Inventory<IT>>>,
     items_without_visual: Query<(Entity, &IT), Without<MarkerItemSpriteBuilt>>,
 ) {
-    for inventory in inventory.iter() {
-        for item in inventory.items.iter().take(inventory.positions.len()) {
+    for (inventory_entity, inventory) in inventory.iter() {
+        let shown_items = (inventory.items.iter().take(inventory.slots))
+            .chain(inventory.upcoming(inventory.preview))
+            .chain(inventory.hold.iter());
+        for item in shown_items {
             if let Ok((entity, item)) = items_without_visual.get(*item) {
                 let mut c = commands.entity(entity);
-                c.add(item.build_sprite()).insert(MarkerItemSpriteBuilt);
+                c.set_parent(inventory_entity);
+                c.add(item.build_sprite()).insert((
+                    MarkerItemSpriteBuilt,
+                    On::<Pointer<Click>>::run(pick_item::<IT>),
+                    On::<Pointer<DragStart>>::run(start_item_drag::<IT>),
+                    On::<Pointer<Drag>>::run(drag_item::<IT>),
+                    On::<Pointer<DragEnd>>::run(end_item_drag::<IT>),
+                ));
             }
         }
     }
 }
 
+fn pick_item<IT: Component + ItemSpriteBuilder>(
+    event: Listener<Pointer<Click>>,
+    mut inventories: Query<&mut Inventory<IT>>,
+) {
+    for mut inventory in &mut inventories {
+        if inventory.is_visible(event.target) {
+            inventory.toggle_pick(event.target);
+        }
+    }
+}
+
+fn start_item_drag<IT: Component + ItemSpriteBuilder>(
+    mut commands: Commands,
+    event: Listener<Pointer<DragStart>>,
+    mut inventories: Query<&mut Inventory<IT>>,
+) {
+    for mut inventory in &mut inventories {
+        if inventory.is_visible(event.target) {
+            inventory.picked = Some(event.target);
+            // let the pointer reach what is under the dragged item, to drop it there
+            commands
+                .entity(event.target)
+                .insert(Pickable::IGNORE)
+                .remove::<EasingComponent<Transform>>();
+        }
+    }
+}
+
+/// The dragged item follows the pointer, as a preview of what is about to be dropped
+fn drag_item<IT: Component + ItemSpriteBuilder>(
+    event: Listener<Pointer<Drag>>,
+    inventories: Query<&Inventory<IT>>,
+    mut transforms: Query<&mut Transform>,
+) {
+    if !inventories.iter().any(|i| i.is_visible(event.target)) {
+        return;
+    }
+    if let Ok(mut transform) = transforms.get_mut(event.target) {
+        transform.translation += Vec3::new(event.delta.x, -event.delta.y, 0.0);
+        transform.translation.z = DRAGGED_Z;
+    }
+}
+
+fn end_item_drag<IT: Component + ItemSpriteBuilder>(
+    mut commands: Commands,
+    event: Listener<Pointer<DragEnd>>,
+    mut inventories: Query<&mut Inventory<IT>>,
+) {
+    if let Some(mut entity) = commands.get_entity(event.target) {
+        entity.remove::<Pickable>();
+    }
+    // if the item was not used, put it back in its slot
+    for mut inventory in &mut inventories {
+        if inventory.is_visible(event.target) {
+            inventory.set_changed();
+        }
+    }
+}
+
+/// The visual of an item, and whether it was just built
+type ItemVisual = (
+    &'static mut Transform,
+    Ref<'static, MarkerItemSpriteBuilt>,
+    Option<&'static ItemBaseScale>,
+);
+
 fn redraw_inventory_on_change<IT: Component + ItemSpriteBuilder>(
+    mut commands: Commands,
     inventory: Query<&Inventory<IT>, Changed<Inventory<IT>>>,
-    mut items_with_visual: Query<&mut Transform, (With<MarkerItemSpriteBuilt>, With<IT>)>,
+    mut items_with_visual: Query<ItemVisual, With<IT>>,
 ) {
     for inventory in inventory.iter() {
-        for (i, &item) in inventory
+        let shown = inventory.slots + inventory.preview;
+        let slots = inventory
             .items
             .iter()
-            .take(inventory.positions.len())
+            .take(shown)
             .enumerate()
-        {
-            if let Ok(mut transform) = items_with_visual.get_mut(item) {
-                //TODO: should be relative to the inventory entity/transform
-                transform.translation = inventory.positions[i];
+            .map(|(i, &item)| {
+                let mut position = inventory.layout.slot_position(i, inventory.slots);
+                if inventory.picked == Some(item) {
+                    position += PICKED_OFFSET;
+                }
+                let scale = if i < inventory.slots {
+                    1.0
+                } else {
+                    PREVIEW_SCALE
+                };
+                (item, position, scale)
+            });
+        let hold = inventory
+            .hold
+            .map(|held| (held, inventory.hold_position, 1.0));
+
+        for (item, position, scale) in slots.chain(hold) {
+            let Ok((mut transform, marker, base_scale)) = items_with_visual.get_mut(item) else {
+                continue;
+            };
+            let base_scale = base_scale.map_or(transform.scale, |base| base.0);
+            if marker.is_added() {
+                commands.entity(item).insert(ItemBaseScale(base_scale));
+                // new items slide in from the place after the last one shown
+                transform.translation = inventory.layout.slot_position(shown, inventory.slots);
             }
+            let target = transform
+                .with_translation(position)
+                .with_scale(base_scale * scale);
+            if *transform == target {
+                continue;
+            }
+            commands.entity(item).insert(transform.ease_to(
+                target,
+                EaseFunction::QuadraticOut,
+                EasingType::Once {
+                    duration: SLOT_EASING_DURATION,
+                },
+            ));
+        }
+    }
+}
+
+fn report_inventory_changes<IT: Component + ItemSpriteBuilder>(
+    mut inventories: Query<(Entity, &mut Inventory<IT>), Changed<Inventory<IT>>>,
+    mut events: EventWriter<InventoryChanged>,
+) {
+    for (entity, mut inventory) in &mut inventories {
+        let content: HashSet<Entity> = inventory.content().copied().collect();
+        let added: Vec<Entity> = content.difference(&inventory.reported).copied().collect();
+        let removed: Vec<Entity> = inventory.reported.difference(&content).copied().collect();
+        if added.is_empty() && removed.is_empty() {
+            continue;
+        }
+        events.send(InventoryChanged {
+            inventory: entity,
+            added,
+            removed,
+        });
+        // not a change of the content itself
+        inventory.bypass_change_detection().reported = content;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Component, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    struct TestItem(u32);
+
+    impl ItemSpriteBuilder for TestItem {
+        type C = fn(EntityWorldMut);
+        fn build_sprite(&self) -> Self::C {
+            |_| {}
+        }
+    }
+
+    fn inventory(items: &[Entity], slots: usize) -> Inventory<TestItem> {
+        Inventory {
+            items: items.iter().copied().collect(),
+            capacity: 4,
+            slots,
+            preview: 1,
+            layout: InventoryLayout::Vertical { spacing: 1.0 },
+            hold: None,
+            hold_position: Vec3::ZERO,
+            picked: None,
+            reported: HashSet::new(),
+            _item_type: PhantomData,
+        }
+    }
+
+    #[test]
+    fn hold_stashes_then_swaps_the_first_item() {
+        let [a, b, c] = [0, 1, 2].map(Entity::from_raw);
+        let mut inventory = inventory(&[a, b], 2);
+
+        assert!(inventory.hold_first());
+        assert_eq!(inventory.hold, Some(a));
+        assert_eq!(inventory.items, [b]);
+
+        inventory.items.push_back(c);
+        assert!(!inventory.hold_first());
+        assert_eq!(inventory.hold, Some(b));
+        assert_eq!(inventory.items, [a, c]);
+    }
+
+    #[test]
+    fn only_visible_items_can_be_picked_and_taken() {
+        let [a, b, c] = [0, 1, 2].map(Entity::from_raw);
+        let mut inventory = inventory(&[a, b, c], 2);
+
+        inventory.toggle_pick(c);
+        assert_eq!(inventory.next_item(), Some(a));
+        inventory.toggle_pick(b);
+        assert_eq!(inventory.next_item(), Some(b));
+
+        assert!(!inventory.take(c));
+        assert!(inventory.take(b));
+        assert_eq!(inventory.items, [a, c]);
+        assert_eq!(inventory.picked, None);
+    }
+
+    #[test]
+    fn capacity_limits_the_queue_and_upcoming_peeks_past_the_slots() {
+        let [a, b, c, d, e] = [0, 1, 2, 3, 4].map(Entity::from_raw);
+        let mut inventory = inventory(&[a, b, c], 2);
+        assert!(inventory.push(d));
+        assert!(!inventory.push(e));
+        assert_eq!(inventory.upcoming(5).copied().collect::<Vec<_>>(), [c, d]);
+    }
+
+    #[test]
+    fn changes_are_reported_and_content_can_be_restored() {
+        let mut app = App::new();
+        app.add_event::<InventoryChanged>()
+            .add_systems(Update, report_inventory_changes::<TestItem>);
+        let items = [1, 2].map(|i| app.world.spawn(TestItem(i)).id());
+        let owner = app.world.spawn(inventory(&items, 2)).id();
+        app.update();
+
+        let saved = app
+            .world
+            .get::<Inventory<TestItem>>(owner)
+            .unwrap()
+            .save(&app.world);
+        assert_eq!(saved.items, [TestItem(1), TestItem(2)]);
+        RestoreInventory {
+            saved: SavedInventory {
+                items: vec![TestItem(3)],
+                hold: Some(TestItem(1)),
+            },
+        }
+        .apply(owner, &mut app.world);
+        app.update();
+
+        let inventory = app.world.get::<Inventory<TestItem>>(owner).unwrap();
+        assert_eq!(inventory.save(&app.world).items, [TestItem(3)]);
+        assert!(items
+            .iter()
+            .all(|&item| app.world.get_entity(item).is_none()));
+        let events = app.world.resource::<Events<InventoryChanged>>();
+        let last = events.iter_current_update_events().last().unwrap();
+        assert_eq!(last.added.len(), 2);
+        assert_eq!(last.removed.len(), 2);
+    }
+
+    #[test]
+    fn layouts_start_at_the_inventory_origin() {
+        let layouts = [
+            InventoryLayout::Vertical { spacing: 10.0 },
+            InventoryLayout::Horizontal { spacing: 10.0 },
+            InventoryLayout::Grid {
+                columns: 2,
+                spacing: Vec2::splat(10.0),
+            },
+            InventoryLayout::Arc {
+                radius: 10.0,
+                start_angle: 0.0,
+                end_angle: std::f32::consts::PI,
+            },
+        ];
+        for layout in &layouts {
+            assert!(layout.slot_position(0, 3).abs_diff_eq(Vec3::ZERO, 1e-4));
         }
+        assert_eq!(layouts[2].slot_position(3, 4), Vec3::new(10.0, 10.0, 0.0));
+        assert!(layouts[3]
+            .slot_position(2, 3)
+            .abs_diff_eq(Vec3::new(-20.0, 0.0, 0.0), 1e-4));
     }
 }
@@ -1,62 +1,96 @@
 mod actions;
 mod audio;
+pub mod autoplayer;
+mod balance;
 mod buildings;
+mod challenge;
+mod difficulty;
+mod economy;
+mod editor;
 mod entities;
 mod game_over;
 mod grid;
-mod inventory;
+mod inspector;
+pub mod inventory;
 mod loading;
+mod loot;
 mod menu;
+pub mod network;
 mod overload;
 mod primitives;
 mod random;
+mod replay;
+mod save;
+pub mod simulation;
+#[cfg(test)]
+mod testing;
+mod tick;
+pub mod versus;
 mod window;
 
 use actions::cursor::CursorPlugin;
-use bevy::prelude::*;
+use autoplayer::AttractModePlugin;
+use bevy::{prelude::*, sprite::Material2dPlugin};
 use bevy_mod_picking::DefaultPickingPlugins;
 use bevy_vector_shapes::Shape2dPlugin;
+use editor::EditorPlugin;
 
-use actions::ActionsPlugin;
 use audio::InternalAudioPlugin;
-use entities::EntityPlugin;
-use game_over::GameOverPlugin;
-use grid::GridPlugin;
+use challenge::HighScoresPlugin;
+use game_over::GameOverUiPlugin;
+use grid::HexMaterial;
+use inspector::InspectorPlugin;
 use loading::LoadingPlugin;
 use menu::MenuPlugin;
-use overload::OverloadPlugin;
-use primitives::PrimitivesPlugin;
+use network::NetworkUiPlugin;
+use overload::OverloadUiPlugin;
+use replay::ReplayPlugin;
+use save::SavePlugin;
+use simulation::SimulationPlugin;
+use versus::VersusUiPlugin;
 use window::GameWindowPlugin;
 
+pub use difficulty::Difficulty;
+pub use grid::MapDefinition;
+
 #[derive(States, Default, Clone, Eq, PartialEq, Debug, Hash)]
 pub enum GameState {
     #[default]
     Loading,
     Menu,
     Playing,
+    /// the designers author the map
+    Editor,
 }
 
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
-        app.add_state::<GameState>().add_plugins((
+        app.add_plugins((
+            // the gameplay, everything else draws it or takes the input of the player
+            SimulationPlugin,
             (
                 LoadingPlugin,
                 GameWindowPlugin,
                 Shape2dPlugin::default(),
                 DefaultPickingPlugins,
                 InternalAudioPlugin,
+                Material2dPlugin::<HexMaterial>::default(),
+                OverloadUiPlugin,
             ),
             MenuPlugin,
-            ActionsPlugin,
-            EntityPlugin,
-            GridPlugin,
             // TODO: remove and replace usage with bevy_mod_picking::PickingPlugin
             CursorPlugin,
-            PrimitivesPlugin,
-            OverloadPlugin,
-            GameOverPlugin,
+            InspectorPlugin,
+            SavePlugin,
+            ReplayPlugin,
+            AttractModePlugin,
+            VersusUiPlugin,
+            NetworkUiPlugin,
+            GameOverUiPlugin,
+            HighScoresPlugin,
+            EditorPlugin,
         ));
 
         #[cfg(debug_assertions)]
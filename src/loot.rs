@@ -0,0 +1,296 @@
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    buildings::{Building, BuildingColor, BuildingMesh, BuildingSize},
+    random::RandomDeterministic,
+};
+
+/// Describes which buildings can be drawn and how often.
+/// Each entry gives a weight to a group of buildings, shared evenly between the buildings of the
+/// group that are not banned. A building matching several entries adds up their weights.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LootTable {
+    pub entries: Vec<LootEntry>,
+    pub pity: Vec<PityRule>,
+    /// buildings matching one of these are never drawn
+    pub banned: Vec<BuildingPattern>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LootEntry {
+    pub pattern: BuildingPattern,
+    pub weight: WeightCurve,
+}
+
+/// Matches the buildings having one of the listed values for each attribute,
+/// an empty list matches any value
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct BuildingPattern {
+    pub mesh: Vec<BuildingMesh>,
+    pub size: Vec<BuildingSize>,
+    pub color: Vec<BuildingColor>,
+}
+
+/// Weight depending on the wave: `(wave, weight)` points, linearly interpolated in between
+/// and constant before the first point and after the last one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WeightCurve(pub Vec<(u32, f32)>);
+
+/// Guarantees that a building matching `pattern` is drawn at least once every `every` draws
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PityRule {
+    pub pattern: BuildingPattern,
+    pub every: u32,
+}
+
+/// How many draws happened since each pity rule was last satisfied
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LootPity(Vec<u32>);
+
+impl BuildingPattern {
+    pub fn matches(&self, building: &Building) -> bool {
+        (self.mesh.is_empty() || self.mesh.contains(&building.mesh()))
+            && (self.size.is_empty() || self.size.contains(&building.size()))
+            && (self.color.is_empty() || self.color.contains(&building.color()))
+    }
+}
+
+impl WeightCurve {
+    pub fn constant(weight: f32) -> Self {
+        Self(vec![(0, weight)])
+    }
+
+    pub fn at(&self, wave: u32) -> f32 {
+        let points = &self.0;
+        let Some(&(first_wave, first_weight)) = points.first() else {
+            return 0.;
+        };
+        if wave <= first_wave {
+            return first_weight.max(0.);
+        }
+        let weight = points.windows(2).find(|pair| wave <= pair[1].0).map_or(
+            points[points.len() - 1].1,
+            |pair| {
+                let ((from_wave, from), (to_wave, to)) = (pair[0], pair[1]);
+                let progress = (wave - from_wave) as f32 / (to_wave - from_wave) as f32;
+                from + (to - from) * progress
+            },
+        );
+        weight.max(0.)
+    }
+}
+
+impl LootTable {
+    fn is_banned(&self, building: &Building) -> bool {
+        self.banned.iter().any(|pattern| pattern.matches(building))
+    }
+
+    /// The weight of every building that can be drawn at `wave`, without the pity rules
+    pub fn distribution(&self, wave: u32) -> Vec<(Building, f32)> {
+        let allowed: Vec<Building> = Building::all()
+            .filter(|building| !self.is_banned(building))
+            .collect();
+        let mut weights = vec![0.; allowed.len()];
+        for entry in &self.entries {
+            let matching: Vec<usize> = (0..allowed.len())
+                .filter(|&i| entry.pattern.matches(&allowed[i]))
+                .collect();
+            for &i in &matching {
+                weights[i] += entry.weight.at(wave) / matching.len() as f32;
+            }
+        }
+        allowed
+            .into_iter()
+            .zip(weights)
+            .filter(|(_, weight)| *weight > 0.)
+            .collect()
+    }
+
+    /// Draws a building. A table that allows nothing at `wave`, e.g. a hot-reloaded one banning
+    /// everything, draws from [`LootTable::default`] instead
+    pub fn draw(&self, wave: u32, rng: &mut RandomDeterministic, pity: &mut LootPity) -> Building {
+        pity.0.resize(self.pity.len(), 0);
+        let mut candidates = self.distribution(wave);
+        // the rules running out of draws restrict the candidates, as long as something remains
+        for (rule, &draws) in self.pity.iter().zip(&pity.0) {
+            if draws + 1 >= rule.every {
+                let forced: Vec<_> = candidates
+                    .iter()
+                    .filter(|(building, _)| rule.pattern.matches(building))
+                    .copied()
+                    .collect();
+                if !forced.is_empty() {
+                    candidates = forced;
+                }
+            }
+        }
+
+        let Ok(&(building, _)) = candidates.choose_weighted(&mut rng.random, |(_, weight)| *weight)
+        else {
+            warn!(
+                "The loot table allows no building at wave {}, drawing from the default one",
+                wave
+            );
+            return LootTable::default().draw(wave, rng, &mut LootPity::default());
+        };
+        for (rule, draws) in self.pity.iter().zip(pity.0.iter_mut()) {
+            *draws = if rule.pattern.matches(&building) {
+                0
+            } else {
+                *draws + 1
+            };
+        }
+        building
+    }
+}
+
+impl Default for LootTable {
+    /// Sizes and colors drawn independently, rare colors guaranteed every 8 draws
+    fn default() -> Self {
+        let sizes = [
+            (BuildingSize::Big, 1.),
+            (BuildingSize::Medium, 2.),
+            (BuildingSize::Small, 1.),
+        ];
+        let colors = [
+            (BuildingColor::Black, 5.),
+            (BuildingColor::White, 5.),
+            (BuildingColor::Pink, 1.),
+            (BuildingColor::Blue, 1.),
+        ];
+        let entries = sizes
+            .iter()
+            .flat_map(|&(size, size_weight)| {
+                colors.iter().map(move |&(color, color_weight)| LootEntry {
+                    pattern: BuildingPattern {
+                        size: vec![size],
+                        color: vec![color],
+                        ..default()
+                    },
+                    weight: WeightCurve::constant(size_weight * color_weight),
+                })
+            })
+            .collect();
+        Self {
+            entries,
+            pity: vec![PityRule {
+                pattern: BuildingPattern {
+                    color: vec![BuildingColor::Pink, BuildingColor::Blue],
+                    ..default()
+                },
+                every: 8,
+            }],
+            banned: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn weight_curves_interpolate_between_points() {
+        let curve = WeightCurve(vec![(2, 1.), (4, 3.), (6, -1.)]);
+        assert_eq!(curve.at(0), 1.);
+        assert_eq!(curve.at(3), 2.);
+        assert_eq!(curve.at(5), 1.);
+        assert_eq!(curve.at(10), 0.);
+    }
+
+    #[test]
+    fn draws_follow_the_table() {
+        let mut table = LootTable {
+            pity: vec![],
+            banned: vec![BuildingPattern {
+                mesh: vec![BuildingMesh::Triangle],
+                size: vec![BuildingSize::Small],
+                ..default()
+            }],
+            ..default()
+        };
+        table.entries.push(LootEntry {
+            pattern: BuildingPattern {
+                mesh: vec![BuildingMesh::Quad],
+                color: vec![BuildingColor::Blue],
+                ..default()
+            },
+            weight: WeightCurve(vec![(0, 0.), (10, 20.)]),
+        });
+        let wave = 5;
+        let distribution = table.distribution(wave);
+        let total: f32 = distribution.iter().map(|(_, weight)| weight).sum();
+
+        let draws = 100_000;
+        let mut rng = RandomDeterministic::new_from_seed(42);
+        let mut pity = LootPity::default();
+        let mut counts: HashMap<Building, u32> = HashMap::new();
+        for _ in 0..draws {
+            *counts
+                .entry(table.draw(wave, &mut rng, &mut pity))
+                .or_default() += 1;
+        }
+
+        assert!(counts.keys().all(|building| !table.is_banned(building)));
+        for (building, weight) in distribution {
+            let expected = weight / total;
+            let observed = counts.get(&building).copied().unwrap_or(0) as f32 / draws as f32;
+            // about 4 standard deviations for the most likely building
+            assert!(
+                (observed - expected).abs() < 0.005,
+                "{:?}: expected {}, observed {}",
+                building,
+                expected,
+                observed
+            );
+        }
+    }
+
+    #[test]
+    fn a_table_allowing_nothing_draws_from_the_default_one() {
+        let mut rng = RandomDeterministic::new_from_seed(5);
+        let mut pity = LootPity::default();
+        let banning_everything = LootTable {
+            banned: vec![BuildingPattern::default()],
+            ..default()
+        };
+        let weightless = LootTable {
+            entries: vec![LootEntry {
+                pattern: BuildingPattern::default(),
+                weight: WeightCurve::constant(0.),
+            }],
+            ..default()
+        };
+        for table in [banning_everything, weightless] {
+            let building = table.draw(3, &mut rng, &mut pity);
+            assert!(LootTable::default()
+                .distribution(3)
+                .iter()
+                .any(|(allowed, _)| *allowed == building));
+        }
+    }
+
+    #[test]
+    fn pity_guarantees_a_rare_color() {
+        let table = LootTable::default();
+        let rule = &table.pity[0];
+        let mut rng = RandomDeterministic::new_from_seed(3);
+        let mut pity = LootPity::default();
+        let mut since_rare = 0;
+        for _ in 0..10_000 {
+            let building = table.draw(0, &mut rng, &mut pity);
+            since_rare = if rule.pattern.matches(&building) {
+                0
+            } else {
+                since_rare + 1
+            };
+            assert!(since_rare < rule.every);
+        }
+    }
+}
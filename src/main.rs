@@ -3,11 +3,34 @@
 use bevy::prelude::*;
 use bevy::DefaultPlugins;
 use bevy_easings::EasingsPlugin;
+use bevy_game::network::{connect, NetworkRole};
+use bevy_game::versus::VersusVariant;
 use bevy_game::GamePlugin; // ToDo: Replace bevy_game with your new crate name.
 
+/// `--host ADDRESS` waits for another player, `--join ADDRESS` connects to one.
+/// The host plays in co-op, or a versus match with `--versus` or `--turns`
+fn network_role() -> Option<NetworkRole> {
+    let mut role = None;
+    let mut versus = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--host" => role = args.next().map(|address| NetworkRole::Host(address, None)),
+            "--join" => role = args.next().map(NetworkRole::Join),
+            "--versus" => versus = Some(VersusVariant::RealTime),
+            "--turns" => versus = Some(VersusVariant::Turns),
+            _ => {}
+        }
+    }
+    match role {
+        Some(NetworkRole::Host(address, _)) => Some(NetworkRole::Host(address, versus)),
+        role => role,
+    }
+}
+
 fn main() {
-    App::new()
-        .insert_resource(Msaa::Off)
+    let mut app = App::new();
+    app.insert_resource(Msaa::Off)
         .insert_resource(ClearColor(Color::rgb(1., 1., 1.)))
         .add_plugins((
             DefaultPlugins
@@ -31,6 +54,12 @@ fn main() {
                 .set(ImagePlugin::default_nearest()),
             EasingsPlugin,
         ))
-        .add_plugins(GamePlugin)
-        .run();
+        .add_plugins(GamePlugin);
+    if let Some(role) = network_role() {
+        if let Err(e) = connect(&mut app, &role) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    app.run();
 }
@@ -1,22 +1,77 @@
-use crate::GameState;
-use bevy::prelude::*;
+use crate::{
+    challenge::ActiveChallenge,
+    difficulty::{Difficulty, DifficultySettings},
+    grid::MapDefinition,
+    network::NetworkSession,
+    random::{RunSeed, SeedSource},
+    replay::{Replay, ReplayPlayer, REPLAY_FILE},
+    save::{PendingRun, SavedRun, SAVE_FILE},
+    versus::{Versus, VersusVariant},
+    GameState,
+};
+use bevy::{ecs::system::SystemParam, prelude::*};
+use std::path::Path;
 
 pub struct MenuPlugin;
 
-/// This plugin is responsible for the game menu (containing only one button...)
+/// This plugin is responsible for the game menu: Play, Continue when a run was saved,
+/// Replay when a replay was recorded, and the two variants of a versus match
 /// The menu is only drawn during the State `GameState::Menu` and is removed when that state is exited
+/// The difficulty is picked with the arrows, Tab toggles the adaptive difficulty
+/// The seed of the run is random, typed with the digit keys, or the daily one with D.
+/// A random seed is rolled again each time the menu opens
+/// In a networked game the host chose the run when connecting, the menu only starts it
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<ButtonColors>()
-            .add_systems(OnEnter(GameState::Menu), setup_menu)
+            .init_resource::<PickedSeed>()
+            .add_systems(
+                OnEnter(GameState::Menu),
+                (
+                    // the host chose the seed of a networked game when connecting
+                    next_seed.run_if(not(resource_exists::<NetworkSession>())),
+                    setup_menu,
+                ),
+            )
             .add_systems(
                 Update,
-                (click_play_button, enter_button).run_if(in_state(GameState::Menu)),
+                (
+                    click_play_button,
+                    enter_button.run_if(not(resource_exists::<NetworkSession>())),
+                    // the host chose the run of a networked game when connecting
+                    change_difficulty.run_if(not(resource_exists::<NetworkSession>())),
+                    update_difficulty_text,
+                    change_seed.run_if(not(resource_exists::<NetworkSession>())),
+                    update_seed_text,
+                )
+                    .run_if(in_state(GameState::Menu)),
             )
             .add_systems(OnExit(GameState::Menu), cleanup_menu);
     }
 }
 
+#[derive(Component, Clone, Copy)]
+enum MenuButton {
+    Play,
+    Continue,
+    Replay,
+    Versus(VersusVariant),
+    Editor,
+}
+
+#[derive(Component)]
+struct DifficultyText;
+
+#[derive(Component)]
+struct SeedText;
+
+#[derive(Component)]
+struct MenuRoot;
+
+/// The seed picked in the menu, while a saved run or a replay plays with their own
+#[derive(Resource, Default)]
+struct PickedSeed(Option<RunSeed>);
+
 #[derive(Resource)]
 struct ButtonColors {
     normal: Color,
@@ -32,43 +87,209 @@ impl Default for ButtonColors {
     }
 }
 
-fn setup_menu(mut commands: Commands, button_colors: Res<ButtonColors>) {
+fn setup_menu(
+    mut commands: Commands,
+    button_colors: Res<ButtonColors>,
+    session: Option<Res<NetworkSession>>,
+) {
+    let has_save = Path::new(SAVE_FILE).exists();
+    let has_replay = Path::new(REPLAY_FILE).exists();
     commands
-        .spawn(ButtonBundle {
-            style: Style {
-                width: Val::Px(120.0),
-                height: Val::Px(50.0),
-                margin: UiRect::all(Val::Auto),
-                justify_content: JustifyContent::Center,
-                align_items: AlignItems::Center,
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(10.0),
+                    ..default()
+                },
                 ..default()
             },
-            background_color: button_colors.normal.into(),
-            ..Default::default()
-        })
+            MenuRoot,
+        ))
         .with_children(|parent| {
-            parent.spawn(TextBundle::from_section(
-                "Play",
-                TextStyle {
-                    font_size: 40.0,
-                    color: Color::rgb(0.9, 0.9, 0.9),
-                    ..default()
-                },
-            ));
+            let play = match session.as_ref().and_then(|session| session.versus) {
+                None => "Play",
+                Some(VersusVariant::RealTime) => "Play versus",
+                Some(VersusVariant::Turns) => "Play versus (turns)",
+            };
+            let buttons = [
+                (play, MenuButton::Play),
+                ("Continue", MenuButton::Continue),
+                ("Replay", MenuButton::Replay),
+                ("Versus", MenuButton::Versus(VersusVariant::RealTime)),
+                ("Versus (turns)", MenuButton::Versus(VersusVariant::Turns)),
+                ("Map editor", MenuButton::Editor),
+            ];
+            for (label, button) in buttons {
+                let available = match button {
+                    MenuButton::Play => true,
+                    // the run was agreed on when connecting
+                    _ if session.is_some() => false,
+                    MenuButton::Continue => has_save,
+                    MenuButton::Replay => has_replay,
+                    MenuButton::Versus(_) | MenuButton::Editor => true,
+                };
+                if !available {
+                    continue;
+                }
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                width: Val::Px(300.0),
+                                height: Val::Px(50.0),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                ..default()
+                            },
+                            background_color: button_colors.normal.into(),
+                            ..Default::default()
+                        },
+                        button,
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(
+                            label,
+                            TextStyle {
+                                font_size: 40.0,
+                                color: Color::rgb(0.9, 0.9, 0.9),
+                                ..default()
+                            },
+                        ));
+                    });
+            }
         });
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 24.0,
+                color: Color::rgb(0.9, 0.9, 0.9),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(40.0),
+            width: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        })
+        .with_text_alignment(TextAlignment::Center),
+        DifficultyText,
+    ));
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 20.0,
+                color: Color::rgb(0.6, 0.6, 0.6),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(10.0),
+            width: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        })
+        .with_text_alignment(TextAlignment::Center),
+        SeedText,
+    ));
 }
 
+/// What a run starts with
+#[derive(SystemParam)]
+struct RunSetup<'w> {
+    seed: ResMut<'w, RunSeed>,
+    picked_seed: ResMut<'w, PickedSeed>,
+    settings: ResMut<'w, DifficultySettings>,
+    map: ResMut<'w, MapDefinition>,
+}
+
+impl RunSetup<'_> {
+    /// Starts like a saved run or a replay did, the seed picked in the menu comes back afterwards
+    fn resume(
+        &mut self,
+        seed: RunSeed,
+        difficulty: Difficulty,
+        adaptive: bool,
+        map: &MapDefinition,
+    ) {
+        self.picked_seed.0 = Some(*self.seed);
+        *self.seed = seed;
+        self.settings.preset = difficulty;
+        self.settings.adaptive = adaptive;
+        *self.map = map.clone();
+    }
+}
+
+type ButtonInteraction = (
+    &'static Interaction,
+    &'static mut BackgroundColor,
+    &'static MenuButton,
+);
+
 fn click_play_button(
+    mut commands: Commands,
     button_colors: Res<ButtonColors>,
     mut state: ResMut<NextState<GameState>>,
-    mut interaction_query: Query<
-        (&Interaction, &mut BackgroundColor),
-        (Changed<Interaction>, With<Button>),
-    >,
+    mut setup: RunSetup,
+    session: Option<Res<NetworkSession>>,
+    mut interaction_query: Query<ButtonInteraction, (Changed<Interaction>, With<Button>)>,
 ) {
-    for (interaction, mut color) in &mut interaction_query {
+    for (interaction, mut color, button) in &mut interaction_query {
         match *interaction {
             Interaction::Pressed => {
+                match button {
+                    MenuButton::Play => {
+                        if let Some(variant) = session.as_ref().and_then(|session| session.versus) {
+                            commands.insert_resource(Versus::new(variant));
+                        }
+                    }
+                    MenuButton::Continue => match SavedRun::load(Path::new(SAVE_FILE)) {
+                        Ok(Some(saved)) => {
+                            // the balance and the inventory depend on these from the start
+                            setup.resume(saved.seed, saved.difficulty, saved.adaptive, &saved.map);
+                            commands.insert_resource(PendingRun(saved));
+                        }
+                        Ok(None) => {
+                            warn!("No saved run in {}", SAVE_FILE);
+                            continue;
+                        }
+                        Err(e) => {
+                            error!("Could not load the saved run: {}", e);
+                            continue;
+                        }
+                    },
+                    MenuButton::Replay => match Replay::load(Path::new(REPLAY_FILE)) {
+                        Ok(replay) => {
+                            setup.resume(
+                                replay.seed,
+                                replay.difficulty,
+                                replay.adaptive,
+                                &replay.map,
+                            );
+                            commands.insert_resource(ReplayPlayer::new(replay));
+                        }
+                        Err(e) => {
+                            error!("Could not load the replay: {}", e);
+                            continue;
+                        }
+                    },
+                    MenuButton::Versus(variant) => {
+                        commands.insert_resource(Versus::new(*variant));
+                    }
+                    MenuButton::Editor => {
+                        state.set(GameState::Editor);
+                        continue;
+                    }
+                }
                 state.set(GameState::Playing);
             }
             Interaction::Hovered => {
@@ -87,6 +308,113 @@ pub fn enter_button(mut state: ResMut<NextState<GameState>>, keyboard_input: Res
     }
 }
 
-fn cleanup_menu(mut commands: Commands, button: Query<Entity, With<Button>>) {
-    commands.entity(button.single()).despawn_recursive();
+fn change_difficulty(
+    mut settings: ResMut<DifficultySettings>,
+    keyboard_input: Res<Input<KeyCode>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Right) {
+        settings.preset = settings.preset.next();
+    }
+    if keyboard_input.just_pressed(KeyCode::Left) {
+        settings.preset = settings.preset.previous();
+    }
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        settings.adaptive = !settings.adaptive;
+    }
+}
+
+fn update_difficulty_text(
+    settings: Res<DifficultySettings>,
+    mut q_text: Query<(&mut Text, Ref<DifficultyText>)>,
+) {
+    let Ok((mut text, marker)) = q_text.get_single_mut() else {
+        return;
+    };
+    // the text is spawned empty each time the menu is entered
+    if !settings.is_changed() && !marker.is_added() {
+        return;
+    }
+    text.sections[0].value = format!(
+        "< Difficulty: {:?} >    Adaptive [Tab]: {}",
+        settings.preset,
+        if settings.adaptive { "on" } else { "off" }
+    );
+}
+
+/// Gives back the seed picked before a saved run or a replay, a random seed is only played once
+fn next_seed(mut seed: ResMut<RunSeed>, mut picked_seed: ResMut<PickedSeed>) {
+    if let Some(picked) = picked_seed.0.take() {
+        *seed = picked;
+    }
+    if seed.source == SeedSource::Random {
+        *seed = RunSeed::random();
+    }
+}
+
+/// Digits append to the typed seed, Backspace removes the last one,
+/// N rolls a new random seed and D picks the daily one
+fn change_seed(
+    mut seed: ResMut<RunSeed>,
+    mut characters: EventReader<ReceivedCharacter>,
+    keyboard_input: Res<Input<KeyCode>>,
+) {
+    for character in characters.read() {
+        let Some(digit) = character.char.to_digit(10) else {
+            continue;
+        };
+        let typed = match seed.source {
+            SeedSource::Typed => seed.seed,
+            SeedSource::Random | SeedSource::Daily(_) => 0,
+        };
+        if let Some(typed) = typed
+            .checked_mul(10)
+            .and_then(|typed| typed.checked_add(digit as u64))
+        {
+            *seed = RunSeed::typed(typed);
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Back) && seed.source == SeedSource::Typed {
+        seed.seed /= 10;
+    }
+    if keyboard_input.just_pressed(KeyCode::N) {
+        *seed = RunSeed::random();
+    }
+    if keyboard_input.just_pressed(KeyCode::D) {
+        *seed = RunSeed::daily();
+    }
+}
+
+fn update_seed_text(
+    seed: Res<RunSeed>,
+    challenge: Res<ActiveChallenge>,
+    mut q_text: Query<(&mut Text, Ref<SeedText>)>,
+) {
+    let Ok((mut text, marker)) = q_text.get_single_mut() else {
+        return;
+    };
+    if !seed.is_changed() && !challenge.is_changed() && !marker.is_added() {
+        return;
+    }
+    let mut value = format!(
+        "{}    type a seed, [N] new random seed, [D] daily challenge",
+        *seed
+    );
+    if let Some(challenge) = &challenge.0 {
+        let modifiers: Vec<String> = challenge.modifiers.iter().map(|m| m.to_string()).collect();
+        value += &format!(
+            "\nChallenge of {}: {}",
+            challenge.date(),
+            modifiers.join(", ")
+        );
+    }
+    text.sections[0].value = value;
+}
+
+/// Everything [`setup_menu`] spawns
+type MenuNode = Or<(With<MenuRoot>, With<DifficultyText>, With<SeedText>)>;
+
+fn cleanup_menu(mut commands: Commands, nodes: Query<Entity, MenuNode>) {
+    for node in &nodes {
+        commands.entity(node).despawn_recursive();
+    }
 }
@@ -0,0 +1,675 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    actions::player_command::{
+        collect_player_commands, execute_player_commands, PlayerCommand, TickCommands,
+    },
+    buildings::Building,
+    difficulty::{Difficulty, DifficultySettings, Wave},
+    economy::Energy,
+    entities::{
+        enemy::{Enemy, EnemyKind},
+        turret::Turret,
+    },
+    grid::{HexGrid, MapDefinition},
+    overload::Overload,
+    primitives::destructible::Destructible,
+    random::RunSeed,
+    replay::GAME_VERSION,
+    tick::{count_ticks, record_simulated_translations, Interpolated, Tick, TickSet},
+    versus::{enforce_phase, Side, Versus, VersusVariant},
+    GameState,
+};
+
+pub use self::transport::{LoopbackTransport, TcpTransport, Transport};
+
+mod transport;
+
+pub struct NetworkPlugin;
+
+/// This plugin plays a run with another player over a [`Transport`], in lockstep:
+/// the commands of a tick are sent to the other peer [`NetworkSession::input_delay`] ticks
+/// before they are carried out, and a tick only runs once the commands of both peers for it
+/// are known, so that both simulations see the same commands at the same ticks.
+/// Every [`HASH_EVERY`] ticks, both peers send a hash of their world and compare them.
+/// The run stops when they differ or when the other player leaves.
+/// In a versus match the host defends and the other peer attacks, otherwise both defend together
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<EventDesync>()
+            .configure_sets(FixedUpdate, TickSet::Commands.run_if(lockstep_ready))
+            .configure_sets(FixedUpdate, TickSet::Gameplay.run_if(lockstep_ready))
+            .configure_sets(FixedUpdate, TickSet::Finish.run_if(lockstep_ready))
+            .add_systems(
+                FixedUpdate,
+                receive_messages
+                    .in_set(TickSet::Prepare)
+                    .run_if(resource_exists::<NetworkSession>())
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                FixedUpdate,
+                exchange_commands
+                    .after(collect_player_commands)
+                    .before(enforce_phase)
+                    .before(execute_player_commands)
+                    .in_set(TickSet::Commands)
+                    .run_if(resource_exists::<NetworkSession>())
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                FixedUpdate,
+                hash_world
+                    .after(record_simulated_translations)
+                    .before(count_ticks)
+                    .in_set(TickSet::Finish)
+                    .run_if(resource_exists::<NetworkSession>())
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+pub struct NetworkUiPlugin;
+
+/// Tells the player why a networked run stopped
+impl Plugin for NetworkUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            show_connection_lost
+                .run_if(resource_exists::<NetworkSession>())
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+/// how many ticks apart the peers compare their worlds
+pub const HASH_EVERY: u64 = 60;
+/// how many ticks the commands of the player wait, so that they reach the other peer in time
+pub const DEFAULT_INPUT_DELAY: u64 = 6;
+/// how long the peer that joins waits for the host to tell it the run
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the peers tell each other
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum NetMessage {
+    /// sent by the host once connected: the run both peers play
+    Hello {
+        version: String,
+        seed: RunSeed,
+        difficulty: Difficulty,
+        adaptive: bool,
+        map: MapDefinition,
+        /// the variant of the versus match, none in co-op
+        versus: Option<VersusVariant>,
+    },
+    /// the commands of the sender for a tick, possibly none
+    Commands {
+        tick: u64,
+        commands: Vec<PlayerCommand>,
+    },
+    /// the state of the world of the sender at the end of a tick
+    Hash { tick: u64, hash: u64 },
+}
+
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error("network error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not read or write a message: {0}")]
+    Encoding(#[from] serde_json::Error),
+    #[error("the other player left")]
+    Disconnected,
+    #[error("the host plays version {found} of the game, this is version {expected}")]
+    Version { found: String, expected: String },
+    #[error("the host didn't say which run to play")]
+    Handshake,
+}
+
+/// Sent when the world of the other peer was found different at the end of `tick`
+#[derive(Event, Debug, Clone, Copy)]
+pub struct EventDesync {
+    pub tick: u64,
+}
+
+/// Who connects to whom
+#[derive(Debug, Clone)]
+pub enum NetworkRole {
+    /// waits for the other player on this address, and chooses the versus match to play if any
+    Host(String, Option<VersusVariant>),
+    /// connects to the host at this address
+    Join(String),
+}
+
+/// The connection to the other player, and the commands and hashes in flight
+#[derive(Resource)]
+pub struct NetworkSession {
+    transport: Box<dyn Transport>,
+    /// 0 for the host, 1 for the other peer. The commands of the host are carried out first
+    pub player: u8,
+    pub input_delay: u64,
+    /// the variant of the versus match the host chose, none in co-op
+    pub versus: Option<VersusVariant>,
+    /// the tick the session started playing at
+    first_tick: Option<u64>,
+    /// commands of this peer, by the tick they are carried out at
+    local: BTreeMap<u64, Vec<PlayerCommand>>,
+    /// commands of the other peer, by the tick they are carried out at
+    remote: BTreeMap<u64, Vec<PlayerCommand>>,
+    local_hashes: BTreeMap<u64, u64>,
+    remote_hashes: BTreeMap<u64, u64>,
+    /// the first tick the worlds were found different at
+    pub desync: Option<u64>,
+    pub disconnected: bool,
+}
+
+impl NetworkSession {
+    pub fn new(transport: impl Transport + 'static, player: u8) -> Self {
+        Self {
+            transport: Box::new(transport),
+            player,
+            input_delay: DEFAULT_INPUT_DELAY,
+            versus: None,
+            first_tick: None,
+            local: BTreeMap::new(),
+            remote: BTreeMap::new(),
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            desync: None,
+            disconnected: false,
+        }
+    }
+
+    /// The commands of both peers for `tick` are known.
+    /// Nothing was sent for the first ticks, no command is carried out at them
+    pub fn is_ready(&self, tick: u64) -> bool {
+        self.first_tick
+            .is_none_or(|first_tick| tick < first_tick + self.input_delay)
+            || self.remote.contains_key(&tick)
+    }
+
+    /// What this peer plays in a versus match, nothing restricts it in co-op
+    pub fn side(&self, versus: bool) -> Option<Side> {
+        versus.then_some(if self.player == 0 {
+            Side::Defender
+        } else {
+            Side::Attacker
+        })
+    }
+
+    fn send(&mut self, message: &NetMessage) {
+        if self.disconnected {
+            return;
+        }
+        if let Err(e) = self.transport.send(message) {
+            error!("Could not reach the other player: {}", e);
+            self.disconnected = true;
+        }
+    }
+
+    /// Compares the hashes known on both sides, the first difference is a desync
+    fn compare_hashes(&mut self) -> Option<u64> {
+        let compared: Vec<u64> = self
+            .remote_hashes
+            .keys()
+            .filter(|tick| self.local_hashes.contains_key(tick))
+            .copied()
+            .collect();
+        let mut found = None;
+        for tick in compared {
+            let local = self.local_hashes.remove(&tick);
+            let remote = self.remote_hashes.remove(&tick);
+            if local != remote && self.desync.is_none() {
+                error!("The worlds of the two players differ at tick {}", tick);
+                self.desync = Some(tick);
+                found = Some(tick);
+            }
+        }
+        found
+    }
+}
+
+/// Connects to the other player before the game starts. The host tells the peer that joins
+/// the seed, the difficulty, the map and the mode of the run, both players then start the same run
+pub fn connect(app: &mut App, role: &NetworkRole) -> Result<(), NetworkError> {
+    let session = match role {
+        NetworkRole::Host(address, versus) => {
+            info!("Waiting for the other player on {}", address);
+            let mut session = NetworkSession::new(TcpTransport::listen(address.as_str())?, 0);
+            let settings = app.world.resource::<DifficultySettings>();
+            let hello = NetMessage::Hello {
+                version: GAME_VERSION.to_string(),
+                seed: *app.world.resource::<RunSeed>(),
+                difficulty: settings.preset,
+                adaptive: settings.adaptive,
+                map: app.world.resource::<MapDefinition>().clone(),
+                versus: *versus,
+            };
+            session.transport.send(&hello)?;
+            session.versus = *versus;
+            session
+        }
+        NetworkRole::Join(address) => {
+            let mut transport = TcpTransport::connect(address.as_str())?;
+            let started = Instant::now();
+            let hello = loop {
+                if let Some(hello) = transport.receive()?.into_iter().next() {
+                    break hello;
+                }
+                if started.elapsed() > HANDSHAKE_TIMEOUT {
+                    return Err(NetworkError::Handshake);
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            };
+            let NetMessage::Hello {
+                version,
+                seed,
+                difficulty,
+                adaptive,
+                map,
+                versus,
+            } = hello
+            else {
+                return Err(NetworkError::Handshake);
+            };
+            if version != GAME_VERSION {
+                return Err(NetworkError::Version {
+                    found: version,
+                    expected: GAME_VERSION.to_string(),
+                });
+            }
+            app.insert_resource(seed)
+                .insert_resource(DifficultySettings {
+                    preset: difficulty,
+                    adaptive,
+                })
+                .insert_resource(map);
+            let mut session = NetworkSession::new(transport, 1);
+            session.versus = versus;
+            session
+        }
+    };
+    info!("Connected to the other player");
+    app.insert_resource(session);
+    Ok(())
+}
+
+/// The run stops for good once the other player is gone or the worlds differ
+fn lockstep_ready(session: Option<Res<NetworkSession>>, tick: Res<Tick>) -> bool {
+    session.is_none_or(|session| {
+        !session.disconnected && session.desync.is_none() && session.is_ready(tick.0)
+    })
+}
+
+fn receive_messages(mut session: ResMut<NetworkSession>, mut desyncs: EventWriter<EventDesync>) {
+    if session.disconnected {
+        return;
+    }
+    let messages = match session.transport.receive() {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("Lost the other player: {}", e);
+            session.disconnected = true;
+            return;
+        }
+    };
+    for message in messages {
+        match message {
+            NetMessage::Commands { tick, commands } => {
+                session.remote.insert(tick, commands);
+            }
+            NetMessage::Hash { tick, hash } => {
+                session.remote_hashes.insert(tick, hash);
+            }
+            NetMessage::Hello { .. } => warn!("The other player said hello again"),
+        }
+    }
+    if let Some(tick) = session.compare_hashes() {
+        desyncs.send(EventDesync { tick });
+    }
+}
+
+/// Sends the commands of this tick to the other peer, and carries out the ones of both peers
+/// that were scheduled for this tick
+pub(crate) fn exchange_commands(
+    tick: Res<Tick>,
+    mut session: ResMut<NetworkSession>,
+    mut tick_commands: ResMut<TickCommands>,
+    versus: Option<Res<Versus>>,
+) {
+    session.first_tick.get_or_insert(tick.0);
+    let mut local = std::mem::take(&mut tick_commands.0);
+    if let Some(side) = session.side(versus.is_some()) {
+        local.retain(|command| side.controls(command));
+    }
+    let at = tick.0 + session.input_delay;
+    session.send(&NetMessage::Commands {
+        tick: at,
+        commands: local.clone(),
+    });
+    session.local.insert(at, local);
+
+    let mine = session.local.remove(&tick.0).unwrap_or_default();
+    // the commands of the other peer stay until the next tick: they tell the rest of this tick
+    // that it can run
+    session.remote = session.remote.split_off(&tick.0);
+    let theirs = session.remote.get(&tick.0).cloned().unwrap_or_default();
+    tick_commands.0 = if session.player == 0 {
+        mine.into_iter().chain(theirs).collect()
+    } else {
+        theirs.into_iter().chain(mine).collect()
+    };
+}
+
+/// What the peers compare to detect a desync
+#[derive(SystemParam)]
+struct HashedWorld<'w, 's> {
+    q_enemies: Query<
+        'w,
+        's,
+        (
+            &'static EnemyKind,
+            &'static Interpolated,
+            &'static Destructible,
+        ),
+        With<Enemy>,
+    >,
+    q_turrets: Query<'w, 's, (&'static Building, &'static Parent), With<Turret>>,
+    grid: Res<'w, HexGrid>,
+    energy: Res<'w, Energy>,
+    q_overload: Query<'w, 's, &'static Overload>,
+    wave: Res<'w, Wave>,
+}
+
+impl HashedWorld<'_, '_> {
+    fn hash(&self) -> u64 {
+        // entities are hashed in an order that doesn't depend on how they were stored
+        let mut enemies: Vec<_> = self
+            .q_enemies
+            .iter()
+            .map(|(kind, interpolated, destructible)| {
+                let position = interpolated.simulated();
+                (
+                    *kind,
+                    [position.x, position.y, destructible.health].map(f32::to_bits),
+                )
+            })
+            .collect();
+        enemies.sort_by_key(|&(kind, bits)| (kind as u8, bits));
+        let mut turrets: Vec<_> = self
+            .q_turrets
+            .iter()
+            .filter_map(|(building, parent)| {
+                let hex = self.grid.entity_to_hex(parent.get())?;
+                Some(([hex.x, hex.y], *building))
+            })
+            .collect();
+        turrets.sort_by_key(|&(hex, _)| hex);
+
+        let overload = self
+            .q_overload
+            .get_single()
+            .map_or(0, |overload| overload.0.to_bits());
+        world_hash(
+            &enemies,
+            &turrets,
+            self.energy.balance(),
+            overload,
+            self.wave.0,
+        )
+    }
+}
+
+fn hash_world(
+    tick: Res<Tick>,
+    mut session: ResMut<NetworkSession>,
+    world: HashedWorld,
+    mut desyncs: EventWriter<EventDesync>,
+) {
+    if !tick.0.is_multiple_of(HASH_EVERY) {
+        return;
+    }
+    let hash = world.hash();
+    session.local_hashes.insert(tick.0, hash);
+    session.send(&NetMessage::Hash { tick: tick.0, hash });
+    if let Some(tick) = session.compare_hashes() {
+        desyncs.send(EventDesync { tick });
+    }
+}
+
+/// FNV-1a over little-endian bytes: unlike the hashers of the standard library, it gives the same
+/// hash on every platform and with every version of Rust
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+}
+
+/// The hash the peers compare. In this order: the number of enemies then the kind, position
+/// and health of each one, the number of turrets then the hex, mesh, size and color of each one,
+/// the energy, the overload and the wave
+fn world_hash(
+    enemies: &[(EnemyKind, [u32; 3])],
+    turrets: &[([i32; 2], Building)],
+    energy: u32,
+    overload: u32,
+    wave: u32,
+) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write_u32(enemies.len() as u32);
+    for &(kind, bits) in enemies {
+        hasher.write(&[kind as u8]);
+        bits.iter().for_each(|&bits| hasher.write_u32(bits));
+    }
+    hasher.write_u32(turrets.len() as u32);
+    for &([x, y], building) in turrets {
+        hasher.write(&x.to_le_bytes());
+        hasher.write(&y.to_le_bytes());
+        hasher.write(&[
+            building.mesh() as u8,
+            building.size() as u8,
+            building.color() as u8,
+        ]);
+    }
+    hasher.write_u32(energy);
+    hasher.write_u32(overload);
+    hasher.write_u32(wave);
+    hasher.0
+}
+
+#[derive(Component)]
+struct ConnectionLostScreen;
+
+fn show_connection_lost(
+    mut commands: Commands,
+    session: Res<NetworkSession>,
+    mut desyncs: EventReader<EventDesync>,
+    mut time: ResMut<Time<Virtual>>,
+    q_screen: Query<(), With<ConnectionLostScreen>>,
+) {
+    let title = if let Some(desync) = desyncs.read().last() {
+        format!(
+            "The game went out of sync with the other player at tick {}",
+            desync.tick
+        )
+    } else if session.disconnected {
+        "The other player left".to_string()
+    } else {
+        return;
+    };
+    if !q_screen.is_empty() {
+        return;
+    }
+    // the board stays as it was when the run stopped
+    time.pause();
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            ConnectionLostScreen,
+            Name::new("Connection lost screen"),
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                title,
+                TextStyle {
+                    font_size: 40.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                    ..default()
+                },
+            ));
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        autoplayer::{AutoPlayer, PlacementStrategy},
+        buildings::{BuildingColor, BuildingMesh, BuildingSize},
+        simulation::headless_run,
+        tick::TICKS_PER_SECOND,
+    };
+
+    #[test]
+    fn world_hashes_are_the_same_everywhere() {
+        // the reference values of FNV-1a
+        let mut hasher = StableHasher::new();
+        assert_eq!(hasher.0, 0xcbf2_9ce4_8422_2325);
+        hasher.write(b"a");
+        assert_eq!(hasher.0, 0xaf63_dc4c_8601_ec8c);
+
+        let enemies = [(EnemyKind::Tank, [1., -2., 3.].map(f32::to_bits))];
+        let turrets = [(
+            [1, -1],
+            Building::new(
+                BuildingMesh::Quad,
+                BuildingSize::Small,
+                BuildingColor::Black,
+            ),
+        )];
+        let hash = world_hash(&enemies, &turrets, 40, 0.5f32.to_bits(), 2);
+        assert_eq!(
+            hash,
+            world_hash(&enemies, &turrets, 40, 0.5f32.to_bits(), 2)
+        );
+        assert_ne!(hash, world_hash(&enemies, &[], 40, 0.5f32.to_bits(), 2));
+        assert_ne!(
+            hash,
+            world_hash(&enemies, &turrets, 40, 0.5f32.to_bits(), 3)
+        );
+    }
+
+    /// Two peers of the same run, the host played by the autoplayer
+    fn peers() -> (App, App) {
+        let (host_transport, peer_transport) = LoopbackTransport::pair();
+        let mut host = headless_run(7, Difficulty::default(), MapDefinition::default());
+        host.insert_resource(NetworkSession::new(host_transport, 0))
+            .insert_resource(AutoPlayer::new(PlacementStrategy::GreedyMaze));
+        let mut peer = headless_run(7, Difficulty::default(), MapDefinition::default());
+        peer.insert_resource(NetworkSession::new(peer_transport, 1));
+        (host, peer)
+    }
+
+    /// Updates both peers in turn until they both reach `seconds`
+    fn play(host: &mut App, peer: &mut App, seconds: f64) {
+        let last_tick = (seconds * TICKS_PER_SECOND) as u64;
+        let tick = |app: &App| app.world.resource::<Tick>().0;
+        // a stalled peer can't go on forever
+        for _ in 0..last_tick * 4 {
+            if tick(host) >= last_tick && tick(peer) >= last_tick {
+                return;
+            }
+            if tick(host) < last_tick {
+                host.update();
+            }
+            if tick(peer) < last_tick {
+                peer.update();
+            }
+        }
+        panic!("the peers stalled");
+    }
+
+    fn turrets(app: &mut App) -> usize {
+        app.world
+            .query_filtered::<(), With<Turret>>()
+            .iter(&app.world)
+            .count()
+    }
+
+    #[test]
+    fn both_peers_play_the_same_run() {
+        let (mut host, mut peer) = peers();
+        play(&mut host, &mut peer, 30.);
+        // the commands of the autoplayer reached the other peer
+        assert!(turrets(&mut peer) > 0);
+        assert_eq!(turrets(&mut host), turrets(&mut peer));
+        assert_eq!(
+            host.world.resource::<Energy>().balance(),
+            peer.world.resource::<Energy>().balance()
+        );
+        assert_eq!(host.world.resource::<NetworkSession>().desync, None);
+        assert_eq!(peer.world.resource::<NetworkSession>().desync, None);
+    }
+
+    #[test]
+    fn the_run_stops_when_the_other_player_leaves() {
+        let (mut host, peer) = peers();
+        drop(peer);
+        for _ in 0..10 {
+            host.update();
+        }
+        assert!(host.world.resource::<NetworkSession>().disconnected);
+        let tick = host.world.resource::<Tick>().0;
+        host.update();
+        assert_eq!(host.world.resource::<Tick>().0, tick);
+    }
+
+    #[test]
+    fn a_desync_is_detected() {
+        let (mut host, mut peer) = peers();
+        play(&mut host, &mut peer, 5.);
+        let balance = peer.world.resource::<Energy>().balance();
+        peer.world.resource_mut::<Energy>().restore(balance + 1);
+        for _ in 0..3 * HASH_EVERY {
+            host.update();
+            peer.update();
+        }
+        let desync = host.world.resource::<NetworkSession>().desync;
+        assert!(desync.is_some_and(|tick| tick >= 5 * TICKS_PER_SECOND as u64));
+        assert_eq!(peer.world.resource::<NetworkSession>().desync, desync);
+        // neither run goes on past the desync
+        let tick = host.world.resource::<Tick>().0;
+        host.update();
+        assert_eq!(host.world.resource::<Tick>().0, tick);
+    }
+}
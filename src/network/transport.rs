@@ -0,0 +1,125 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{channel, Receiver, Sender, TryRecvError},
+        Mutex,
+    },
+};
+
+use super::{NetMessage, NetworkError};
+
+/// Carries messages to the other peer, in order
+pub trait Transport: Send + Sync {
+    fn send(&mut self, message: &NetMessage) -> Result<(), NetworkError>;
+
+    /// The messages received since the last call, oldest first
+    fn receive(&mut self) -> Result<Vec<NetMessage>, NetworkError>;
+}
+
+/// Drains `incoming` without waiting
+fn drain<T>(
+    incoming: &mut Mutex<Receiver<Result<T, NetworkError>>>,
+) -> Result<Vec<T>, NetworkError> {
+    let incoming = incoming.get_mut().map_err(|_| NetworkError::Disconnected)?;
+    let mut messages = Vec::new();
+    loop {
+        match incoming.try_recv() {
+            Ok(message) => messages.push(message?),
+            Err(TryRecvError::Empty) => return Ok(messages),
+            // what was received before the other side left is still played
+            Err(TryRecvError::Disconnected) if !messages.is_empty() => return Ok(messages),
+            Err(TryRecvError::Disconnected) => return Err(NetworkError::Disconnected),
+        }
+    }
+}
+
+/// Both peers in the same process, for the tests
+pub struct LoopbackTransport {
+    outgoing: Sender<Result<NetMessage, NetworkError>>,
+    incoming: Mutex<Receiver<Result<NetMessage, NetworkError>>>,
+}
+
+impl LoopbackTransport {
+    /// Two transports, each receiving what the other sends
+    pub fn pair() -> (Self, Self) {
+        let (to_first, from_second) = channel();
+        let (to_second, from_first) = channel();
+        (
+            Self {
+                outgoing: to_second,
+                incoming: Mutex::new(from_second),
+            },
+            Self {
+                outgoing: to_first,
+                incoming: Mutex::new(from_first),
+            },
+        )
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&mut self, message: &NetMessage) -> Result<(), NetworkError> {
+        self.outgoing
+            .send(Ok(message.clone()))
+            .map_err(|_| NetworkError::Disconnected)
+    }
+
+    fn receive(&mut self) -> Result<Vec<NetMessage>, NetworkError> {
+        drain(&mut self.incoming)
+    }
+}
+
+/// One JSON message per line over a TCP connection.
+/// A thread reads the connection so that receiving never blocks the game
+pub struct TcpTransport {
+    stream: TcpStream,
+    incoming: Mutex<Receiver<Result<NetMessage, NetworkError>>>,
+}
+
+impl TcpTransport {
+    /// Waits for a peer to connect on `address`
+    pub fn listen(address: impl ToSocketAddrs) -> Result<Self, NetworkError> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        Self::new(stream)
+    }
+
+    pub fn connect(address: impl ToSocketAddrs) -> Result<Self, NetworkError> {
+        Self::new(TcpStream::connect(address)?)
+    }
+
+    fn new(stream: TcpStream) -> Result<Self, NetworkError> {
+        // the commands of a tick are small and late ones stall both peers
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (sender, incoming) = channel();
+        std::thread::spawn(move || {
+            for line in reader.lines() {
+                let message = line
+                    .map_err(NetworkError::from)
+                    .and_then(|line| Ok(serde_json::from_str(&line)?));
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(Self {
+            stream,
+            incoming: Mutex::new(incoming),
+        })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, message: &NetMessage) -> Result<(), NetworkError> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.stream.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Vec<NetMessage>, NetworkError> {
+        drain(&mut self.incoming)
+    }
+}
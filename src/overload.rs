@@ -1,37 +1,48 @@
 use bevy::prelude::*;
 use bevy_vector_shapes::prelude::*;
 
+use crate::balance::Balance;
+use crate::tick::{GameplaySet, TickEventApp};
 use crate::window::WindowSize;
-use crate::{entities::enemy::EventSpawnedEnemy, entities::turret::EventSpawnedTower, GameState};
+use crate::{entities::enemy::EventSpawnedEnemy, GameState};
 
 pub struct OverloadPlugin;
 
 impl Plugin for OverloadPlugin {
     fn build(&self, app: &mut App) {
-        app.add_event::<OverloadDepleted>();
+        app.add_tick_event::<OverloadDepleted>();
 
-        app.add_systems(Update, draw_ui);
-        app.add_systems(Update, update_overload);
-        app.add_systems(Update, react_to_spawned_enemy);
-        app.add_systems(Update, spend_overload_on_tower_spawnned);
+        app.add_systems(
+            FixedUpdate,
+            (update_overload, react_to_spawned_enemy)
+                .chain()
+                .in_set(GameplaySet::Overload),
+        );
 
         app.add_systems(OnEnter(GameState::Playing), setup);
     }
 }
 
-fn setup(mut commands: Commands) {
-    commands.spawn(Overload(0.5f32));
+/// Draws the overload bar, apart from [`OverloadPlugin`] so that the simulation runs without a renderer
+pub struct OverloadUiPlugin;
+
+impl Plugin for OverloadUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, draw_ui);
+    }
 }
 
-/// Basically the HP bar, but it decreases naturally over time
-///   and increases when enemies are killed
-///   and decreases when towers are built
-///   always between 0 and 1
+fn setup(mut commands: Commands, balance: Res<Balance>) {
+    commands.spawn(Overload(balance.overload.start));
+}
+
+/// The pressure meter of the run, always between 0 and 1.
+/// It drains over time and each enemy entering the board refills it:
+///   the run is lost when the player lets the pressure drop too low.
+/// It is not a currency, buildings are paid with [`crate::economy::Energy`].
 #[derive(Component, Reflect, Debug)]
 pub struct Overload(pub f32);
 
-const OVERLOAD_DEPLETED_THRESHOLD: f32 = 0.001;
-
 #[derive(Event)]
 pub struct OverloadDepleted;
 
@@ -96,15 +107,16 @@ fn draw_ui(mut painter: ShapePainter, q_overload: Query<&Overload>, window_size:
 
 fn update_overload(
     time: Res<Time>,
+    balance: Res<Balance>,
     mut q_overload: Query<&mut Overload>,
     mut event_writer: EventWriter<OverloadDepleted>,
 ) {
     let Ok(mut overload) = q_overload.get_single_mut() else {
         return;
     };
-    //dbg!(&overload);
-    overload.0 = (overload.0 - 0.03 * time.delta_seconds()).clamp(0.0, 1.0);
-    if overload.0 < OVERLOAD_DEPLETED_THRESHOLD {
+    overload.0 =
+        (overload.0 - balance.overload.decay_per_second * time.delta_seconds()).clamp(0.0, 1.0);
+    if overload.0 < balance.overload.depleted_threshold {
         event_writer.send(OverloadDepleted);
     }
 }
@@ -112,23 +124,12 @@ fn update_overload(
 fn react_to_spawned_enemy(
     mut event: EventReader<EventSpawnedEnemy>,
     mut q_overload: Query<&mut Overload>,
+    balance: Res<Balance>,
 ) {
     let Ok(mut overload) = q_overload.get_single_mut() else {
         return;
     };
     for _e in event.read() {
-        overload.0 = (overload.0 + 0.1).clamp(0.0, 1.0);
-    }
-}
-
-fn spend_overload_on_tower_spawnned(
-    mut event: EventReader<EventSpawnedTower>,
-    mut q_overload: Query<&mut Overload>,
-) {
-    let Ok(mut overload) = q_overload.get_single_mut() else {
-        return;
-    };
-    for _e in event.read() {
-        overload.0 = (overload.0 - 0.1).clamp(0.0, 1.0);
+        overload.0 = (overload.0 + balance.overload.gain_per_spawned_enemy).clamp(0.0, 1.0);
     }
 }
@@ -1,6 +1,9 @@
 use bevy::prelude::*;
 
-use crate::GameState;
+use crate::{
+    tick::{GameplaySet, TickEventApp},
+    GameState,
+};
 
 use super::target::Target;
 
@@ -8,9 +11,12 @@ pub struct DestructiblePlugin;
 
 impl Plugin for DestructiblePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (apply_damage, destroy_if_no_health).run_if(in_state(GameState::Playing)),
+        app.add_tick_event::<EventDestroyed>().add_systems(
+            FixedUpdate,
+            (apply_damage, destroy_if_no_health)
+                .chain()
+                .in_set(GameplaySet::Damage)
+                .run_if(in_state(GameState::Playing)),
         );
     }
 }
@@ -22,18 +28,38 @@ pub struct Destructible {
 }
 
 #[derive(Component)]
-pub struct Damage(f32);
+pub struct Damage {
+    amount: f32,
+    /// the entity responsible for the damage, e.g. the turret that shot the bullet
+    source: Option<Entity>,
+}
 
 impl Damage {
     pub fn new(damage: f32) -> Self {
-        Self(damage)
+        Self {
+            amount: damage,
+            source: None,
+        }
+    }
+
+    pub fn with_source(mut self, source: Entity) -> Self {
+        self.source = Some(source);
+        self
     }
 }
 
+/// Sent once when a destructible loses its last health point
+#[derive(Event, Debug)]
+pub struct EventDestroyed {
+    pub entity: Entity,
+    pub destroyed_by: Option<Entity>,
+}
+
 pub fn apply_damage(
     mut commands: Commands,
     damager_query: Query<(&Damage, &Transform, &Target, Entity)>,
     mut enemies_query: Query<(&mut Destructible, &Transform)>,
+    mut destroyed_events: EventWriter<EventDestroyed>,
 ) {
     for (damage, dmg_transform, target, dmg_entity) in damager_query.iter() {
         if let Ok((mut destructible, enemy_transform)) = enemies_query.get_mut(target.entity) {
@@ -41,7 +67,14 @@ pub fn apply_damage(
                 .translation
                 .distance(dmg_transform.translation);
             if distance < destructible.hitbox {
-                destructible.health -= damage.0;
+                let was_alive = destructible.health > 0.0;
+                destructible.health -= damage.amount;
+                if was_alive && destructible.health <= 0.0 {
+                    destroyed_events.send(EventDestroyed {
+                        entity: target.entity,
+                        destroyed_by: damage.source,
+                    });
+                }
                 commands.entity(dmg_entity).despawn();
             }
         }
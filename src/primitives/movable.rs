@@ -8,6 +8,7 @@ use crate::primitives::target::{Target, TargetQuery};
 #[derive(Component)]
 pub struct AutoMovable {
     pub velocity: f32,
+    /// walks from hex to hex down the distances to the crystals, flies straight when false
     pub follow_grid: bool,
 }
 
@@ -5,7 +5,7 @@ use bevy::{
     prelude::*,
 };
 
-use crate::GameState;
+use crate::{tick::GameplaySet, GameState};
 
 pub struct TargetPlugin;
 
@@ -13,8 +13,10 @@ impl Plugin for TargetPlugin {
     fn build(&self, app: &mut App) {
         //app.add_systems(Update, systems);
         app.add_systems(
-            Update,
-            detect_target_removed.run_if(in_state(GameState::Playing)),
+            FixedUpdate,
+            detect_target_removed
+                .in_set(GameplaySet::Move)
+                .run_if(in_state(GameState::Playing)),
         );
     }
 }
@@ -104,16 +106,6 @@ where
     pub targets_query: Query<'w, 's, TargetQuery<S, T>>,
 }
 
-#[derive(SystemParam)]
-pub struct SourceWithoutTargetAccessor<'w, 's, S, T>
-where
-    S: Component,
-    T: Component,
-{
-    pub srcs_query: Query<'w, 's, SrcWithoutTargetQuery<S, T>>,
-    pub targets_query: Query<'w, 's, TargetQuery<S, T>>,
-}
-
 // TODO: change genetic const parameter to configuration through a resource
 pub fn face_target<S, T, const PI_2_OFFSET: usize>(mut params: SourceWithTargetAccessor<S, T>)
 where
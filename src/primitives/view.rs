@@ -1,8 +1,11 @@
 use bevy::{
     ecs::{query::WorldQuery, system::SystemParam},
     prelude::*,
+    utils::HashSet,
 };
 
+use crate::tick::TickEventApp;
+
 use super::target::{
     OnTargetDespawned, SrcTargetQuery, SrcWithoutTargetQuery, Target, TargetQuery,
 };
@@ -11,31 +14,75 @@ pub struct ViewPlugin;
 
 impl Plugin for ViewPlugin {
     fn build(&self, app: &mut App) {
-        app.add_event::<EnterViewEvent>()
-            .add_event::<ExitViewEvent>();
-        //.add_systems(Update, debug_range);
+        app.add_tick_event::<EnterViewEvent>()
+            .add_tick_event::<ExitViewEvent>();
     }
 }
 
+/// Gives its entity the ability to see targets within `range`.
+/// The set of visible targets is maintained by [`update_visible_targets`].
 #[derive(Component, Debug)]
 pub struct View {
     range: f32,
+    /// whether the [`Airborne`] targets are seen too
+    anti_air: bool,
+    visible: HashSet<Entity>,
 }
 
+/// A target flying high, only the views with anti-air see it
+#[derive(Component, Debug)]
+pub struct Airborne;
+
 impl View {
     pub fn new(range: f32) -> Self {
-        Self { range }
+        Self {
+            range,
+            anti_air: false,
+            visible: HashSet::new(),
+        }
+    }
+
+    pub fn with_anti_air(mut self) -> Self {
+        self.anti_air = true;
+        self
+    }
+
+    pub fn has_anti_air(&self) -> bool {
+        self.anti_air
+    }
+
+    pub fn range(&self) -> f32 {
+        self.range
+    }
+
+    /// Returns true if the given target is currently in view
+    pub fn can_see(&self, target: Entity) -> bool {
+        self.visible.contains(&target)
+    }
+
+    /// Iterates over all the targets currently in view
+    pub fn visible_targets(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.visible.iter().copied()
+    }
+
+    pub fn sees_nothing(&self) -> bool {
+        self.visible.is_empty()
     }
 }
 
-#[derive(Event, Debug)]
+/// Sent when `target` enters the view of `source`
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct EnterViewEvent {
-    pub entity: Entity,
+    pub source: Entity,
+    pub target: Entity,
 }
 
-#[derive(Event, Debug)]
+/// Sent when `target` leaves the view of `source`, either because it moved
+/// out of range or because it was despawned
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ExitViewEvent {
-    pub entity: Entity,
+    pub source: Entity,
+    pub target: Entity,
 }
 
 #[derive(WorldQuery)]
@@ -70,80 +117,219 @@ where
     pub view: &'static View,
 }
 
-#[derive(SystemParam)]
-pub struct SourceViewTargetAccessor<'w, 's, S, T>
-where
+/// Where a target is, and whether only anti-air views see it
+type TargetPosition = (Entity, &'static GlobalTransform, Has<Airborne>);
+
+/// Keeps the set of visible `T` of every `S` up to date and sends an
+/// [`EnterViewEvent`] or [`ExitViewEvent`] for each (source, target) pair that changed.
+pub fn update_visible_targets<S, T>(
+    mut sources: Query<(Entity, &GlobalTransform, &mut View), With<S>>,
+    targets: Query<TargetPosition, (With<T>, Without<S>)>,
+    mut enter_view_events: EventWriter<EnterViewEvent>,
+    mut exit_view_events: EventWriter<ExitViewEvent>,
+) where
     S: Component,
     T: Component,
 {
-    pub srcs_query: Query<'w, 's, SrcViewTargetQuery<S, T>>,
-    pub targets_query: Query<'w, 's, TargetQuery<S, T>>,
+    for (source, source_transform, mut view) in &mut sources {
+        let position = source_transform.translation().xy();
+        let in_range: HashSet<Entity> = targets
+            .iter()
+            .filter(|(_, target_transform, airborne)| {
+                (view.anti_air || !airborne)
+                    && position.distance(target_transform.translation().xy()) <= view.range
+            })
+            .map(|(target, _, _)| target)
+            .collect();
+
+        if in_range == view.visible {
+            continue;
+        }
+        // despawned targets are not returned by the query anymore, so they exit the view as well
+        for &target in view.visible.difference(&in_range) {
+            exit_view_events.send(ExitViewEvent { source, target });
+        }
+        for &target in in_range.difference(&view.visible) {
+            enter_view_events.send(EnterViewEvent { source, target });
+        }
+        view.visible = in_range;
+    }
 }
 
 pub fn scan_for_targets_in_range<S, T>(
     mut commands: Commands,
     accessor: SourceViewWithoutTargetAccessor<S, T>,
-    mut enter_view_events: EventWriter<EnterViewEvent>,
 ) where
     S: Component,
     T: Component,
 {
     for src in &accessor.srcs_query {
-        let mut nearest_target = None;
-        let mut nearest_distance = src.view.range;
-
-        for target in &accessor.targets_query {
-            let distance = src
-                .subquery
-                .global_transform
-                .compute_transform()
-                .translation
-                .distance(target.transform.translation);
-            if distance < nearest_distance {
-                nearest_target = Some(target);
-                nearest_distance = distance;
-            }
-        }
-        if let Some(target) = nearest_target {
+        let position = src.subquery.global_transform.translation();
+        let nearest_target = src
+            .view
+            .visible_targets()
+            .filter_map(|entity| accessor.targets_query.get(entity).ok())
+            .map(|target| {
+                let distance = position.distance(target.global_transform.translation());
+                (target.entity, distance)
+            })
+            .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2));
+
+        if let Some((target, _)) = nearest_target {
             // TODO: change OnTargetDespawned to an event
             commands
                 .entity(src.subquery.entity)
-                .insert((Target::new(target.entity, OnTargetDespawned::DoNothing),));
-            enter_view_events.send(EnterViewEvent {
-                entity: src.subquery.entity,
-            });
+                .insert((Target::new(target, OnTargetDespawned::DoNothing),));
         }
     }
 }
 
 pub fn auto_remove_target_when_out_of_range<S, T>(
     mut commands: Commands,
-    accessor: SourceViewTargetAccessor<S, T>,
-    mut exit_view_events: EventWriter<ExitViewEvent>,
+    srcs_query: Query<SrcViewTargetQuery<S, T>>,
 ) where
     S: Component,
     T: Component,
 {
-    for src in &accessor.srcs_query {
-        if let Ok(target) = accessor.targets_query.get(src.subquery.target.entity) {
-            let distance = src
-                .subquery
-                .global_transform
-                .compute_transform()
-                .translation
-                .distance(target.transform.translation);
-            if distance > src.view.range {
-                commands.entity(src.subquery.entity).remove::<Target>();
-                exit_view_events.send(ExitViewEvent {
-                    entity: src.subquery.entity,
-                });
-            }
+    for src in &srcs_query {
+        if !src.view.can_see(src.subquery.target.entity) {
+            commands.entity(src.subquery.entity).remove::<Target>();
         }
     }
 }
 
-pub fn _debug_range(mut gizmos: Gizmos, views: Query<(&View, &Transform)>) {
-    for (view, transform) in &views {
-        gizmos.circle_2d(transform.translation.xy(), view.range, Color::LIME_GREEN);
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::event::ManualEventReader;
+
+    use super::*;
+
+    #[derive(Component)]
+    struct Watcher;
+
+    #[derive(Component)]
+    struct Intruder;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins(ViewPlugin)
+            .add_systems(Update, update_visible_targets::<Watcher, Intruder>);
+        app
+    }
+
+    fn spawn_at<C: Component>(app: &mut App, component: C, position: Vec2) -> Entity {
+        // no transform propagation here, so set the global transform directly
+        let transform = Transform::from_translation(position.extend(0.));
+        app.world
+            .spawn((component, transform, GlobalTransform::from(transform)))
+            .id()
+    }
+
+    fn move_to(app: &mut App, entity: Entity, position: Vec2) {
+        let transform = Transform::from_translation(position.extend(0.));
+        app.world
+            .entity_mut(entity)
+            .insert((transform, GlobalTransform::from(transform)));
+    }
+
+    fn drain<E: Event + Copy>(app: &App, reader: &mut ManualEventReader<E>) -> Vec<E> {
+        reader
+            .read(app.world.resource::<Events<E>>())
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn only_targets_in_range_are_visible() {
+        let mut app = app();
+        let source = spawn_at(&mut app, Watcher, Vec2::ZERO);
+        app.world.entity_mut(source).insert(View::new(10.));
+        let near = spawn_at(&mut app, Intruder, Vec2::new(5., 0.));
+        let far = spawn_at(&mut app, Intruder, Vec2::new(50., 0.));
+
+        app.update();
+
+        let view = app.world.get::<View>(source).unwrap();
+        assert!(view.can_see(near));
+        assert!(!view.can_see(far));
+        assert_eq!(view.visible_targets().collect::<Vec<_>>(), vec![near]);
+    }
+
+    #[test]
+    fn only_anti_air_sees_airborne_targets() {
+        let mut app = app();
+        let ground = spawn_at(&mut app, Watcher, Vec2::ZERO);
+        app.world.entity_mut(ground).insert(View::new(10.));
+        let anti_air = spawn_at(&mut app, Watcher, Vec2::ZERO);
+        app.world
+            .entity_mut(anti_air)
+            .insert(View::new(10.).with_anti_air());
+        let flying = spawn_at(&mut app, Intruder, Vec2::new(5., 0.));
+        app.world.entity_mut(flying).insert(Airborne);
+        let walking = spawn_at(&mut app, Intruder, Vec2::new(0., 5.));
+
+        app.update();
+
+        let view = app.world.get::<View>(ground).unwrap();
+        assert!(!view.can_see(flying));
+        assert!(view.can_see(walking));
+        let view = app.world.get::<View>(anti_air).unwrap();
+        assert!(view.can_see(flying));
+        assert!(view.can_see(walking));
+    }
+
+    #[test]
+    fn enter_and_exit_events_are_sent_per_pair() {
+        let mut app = app();
+        let mut enter_reader = ManualEventReader::<EnterViewEvent>::default();
+        let mut exit_reader = ManualEventReader::<ExitViewEvent>::default();
+        let source = spawn_at(&mut app, Watcher, Vec2::ZERO);
+        app.world.entity_mut(source).insert(View::new(10.));
+        let target = spawn_at(&mut app, Intruder, Vec2::new(20., 0.));
+
+        app.update();
+        assert!(drain(&app, &mut enter_reader).is_empty());
+
+        move_to(&mut app, target, Vec2::new(5., 0.));
+        app.update();
+        assert_eq!(
+            drain(&app, &mut enter_reader),
+            vec![EnterViewEvent { source, target }]
+        );
+
+        // staying in range doesn't send the event again
+        app.update();
+        assert!(drain(&app, &mut enter_reader).is_empty());
+        assert!(drain(&app, &mut exit_reader).is_empty());
+
+        move_to(&mut app, target, Vec2::new(-20., 0.));
+        app.update();
+        assert_eq!(
+            drain(&app, &mut exit_reader),
+            vec![ExitViewEvent { source, target }]
+        );
+        assert!(app.world.get::<View>(source).unwrap().sees_nothing());
+    }
+
+    #[test]
+    fn despawned_target_exits_view() {
+        let mut app = app();
+        let mut exit_reader = ManualEventReader::<ExitViewEvent>::default();
+        let source = spawn_at(&mut app, Watcher, Vec2::ZERO);
+        app.world.entity_mut(source).insert(View::new(10.));
+        let target = spawn_at(&mut app, Intruder, Vec2::new(1., 1.));
+        let survivor = spawn_at(&mut app, Intruder, Vec2::new(-1., 1.));
+
+        app.update();
+        app.world.despawn(target);
+        app.update();
+
+        assert_eq!(
+            drain(&app, &mut exit_reader),
+            vec![ExitViewEvent { source, target }]
+        );
+        let view = app.world.get::<View>(source).unwrap();
+        assert!(!view.can_see(target));
+        assert!(view.can_see(survivor));
     }
 }
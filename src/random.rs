@@ -1,8 +1,32 @@
-use bevy::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::{prelude::*, utils::HashMap};
 use rand::prelude::*;
 use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::GameState;
+
+pub struct RandomPlugin;
+
+/// This plugin holds the seed of the run, picked in the menu.
+/// Every source of randomness in the gameplay comes from a named stream derived from it,
+/// so that two runs with the same seed play out the same way.
+impl Plugin for RandomPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(RunSeed::random())
+            .init_resource::<RandomStreams>()
+            .add_systems(
+                OnEnter(GameState::Playing),
+                (reset_streams, spawn_seed_display),
+            )
+            .add_systems(
+                Update,
+                update_seed_display.run_if(in_state(GameState::Playing)),
+            );
+    }
+}
 
-#[allow(dead_code)]
 #[derive(Component)]
 pub struct RandomDeterministic {
     pub random: ChaCha20Rng,
@@ -11,8 +35,7 @@ pub struct RandomDeterministic {
 
 impl Default for RandomDeterministic {
     fn default() -> Self {
-        let seed = 0; //thread_rng().gen::<u64>();
-        Self::new_from_seed(seed)
+        Self::new_from_seed(0)
     }
 }
 
@@ -24,9 +47,233 @@ impl RandomDeterministic {
         }
     }
     pub fn _reset(&mut self) {
+        let stream = self.random.get_stream();
         *self = Self::new_from_seed(self.seed);
+        self.random.set_stream(stream);
     }
     pub fn _get_seed(&self) -> u64 {
         self.seed
     }
+
+    /// Where the generator is in its sequence, to resume it later with [`Self::from_state`]
+    pub fn state(&self) -> RandomState {
+        RandomState {
+            seed: self.seed,
+            stream: self.random.get_stream(),
+            word_pos: self.random.get_word_pos(),
+        }
+    }
+
+    pub fn from_state(state: RandomState) -> RandomDeterministic {
+        let mut random = Self::new_from_seed(state.seed);
+        random.random.set_stream(state.stream);
+        random.random.set_word_pos(state.word_pos);
+        random
+    }
+}
+
+impl std::fmt::Debug for RandomDeterministic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RandomDeterministic")
+            .field(&self.state())
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomState {
+    seed: u64,
+    #[serde(default)]
+    stream: u64,
+    word_pos: u128,
+}
+
+/// The independent sequences of random numbers of a run.
+/// Drawing more from one of them never changes what the others give.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RngStream {
+    /// the buildings drawn from the loot table into the inventory
+    Loot,
+    /// how enemies break ties between equally short paths
+    EnemyPathing,
+    /// the modifiers of a daily challenge
+    Challenge,
+    /// where the autoplayer builds with the random strategy
+    AutoPlayer,
+    /// the order the enemies of a wave come out in
+    Waves,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeedSource {
+    Random,
+    Typed,
+    /// the seed of the daily challenge, with the days since the unix epoch
+    Daily(u64),
+}
+
+/// The seed of the current run
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunSeed {
+    pub seed: u64,
+    pub source: SeedSource,
+}
+
+impl RunSeed {
+    /// A fresh seed, the only place where randomness doesn't come from the run seed
+    pub fn random() -> Self {
+        Self {
+            seed: thread_rng().gen(),
+            source: SeedSource::Random,
+        }
+    }
+
+    pub fn typed(seed: u64) -> Self {
+        Self {
+            seed,
+            source: SeedSource::Typed,
+        }
+    }
+
+    /// The same seed for everybody playing on the same (UTC) day
+    pub fn daily() -> Self {
+        let days = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() / (24 * 60 * 60));
+        Self::for_day(days)
+    }
+
+    /// Seed of the `days`-th day since the unix epoch
+    pub fn for_day(days: u64) -> Self {
+        // mix the bits so that consecutive days don't get close seeds
+        let mut seed = days.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        seed = (seed ^ (seed >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        seed = (seed ^ (seed >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self {
+            seed: seed ^ (seed >> 31),
+            source: SeedSource::Daily(days),
+        }
+    }
+
+    /// A generator for `stream`, starting at the beginning of its sequence
+    pub fn stream(&self, stream: RngStream) -> RandomDeterministic {
+        let mut random = RandomDeterministic::new_from_seed(self.seed);
+        random.random.set_stream(stream as u64);
+        random
+    }
+}
+
+impl std::fmt::Display for RunSeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.source {
+            SeedSource::Daily(_) => write!(f, "Daily seed: {}", self.seed),
+            SeedSource::Random | SeedSource::Typed => write!(f, "Seed: {}", self.seed),
+        }
+    }
+}
+
+/// The streams that aren't owned by an entity, created on first use
+#[derive(Resource, Debug, Default)]
+pub struct RandomStreams {
+    seed: Option<RunSeed>,
+    streams: HashMap<RngStream, RandomDeterministic>,
+}
+
+impl RandomStreams {
+    pub fn new(seed: RunSeed) -> Self {
+        Self {
+            seed: Some(seed),
+            streams: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, stream: RngStream) -> &mut RandomDeterministic {
+        let seed = self.seed.unwrap_or(RunSeed::typed(0));
+        self.streams
+            .entry(stream)
+            .or_insert_with(|| seed.stream(stream))
+    }
+
+    /// Replaces `stream`, e.g. with one resumed from a saved run
+    pub fn insert(&mut self, stream: RngStream, random: RandomDeterministic) {
+        self.streams.insert(stream, random);
+    }
+}
+
+fn reset_streams(mut commands: Commands, seed: Res<RunSeed>) {
+    commands.insert_resource(RandomStreams::new(*seed));
+}
+
+#[derive(Component)]
+struct SeedDisplay;
+
+fn spawn_seed_display(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 20.0,
+                color: Color::rgb(0.6, 0.6, 0.6),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            // the inspector panel is in the top right corner
+            bottom: Val::Px(10.0),
+            right: Val::Px(10.0),
+            ..default()
+        }),
+        SeedDisplay,
+        Name::new("Seed display"),
+    ));
+}
+
+fn update_seed_display(seed: Res<RunSeed>, mut q_display: Query<&mut Text, With<SeedDisplay>>) {
+    for mut text in &mut q_display {
+        let value = seed.to_string();
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_values(mut random: RandomDeterministic) -> Vec<u32> {
+        (0..4).map(|_| random.random.gen()).collect()
+    }
+
+    #[test]
+    fn streams_are_reproducible_and_independent() {
+        let seed = RunSeed::typed(1234);
+        assert_eq!(
+            first_values(seed.stream(RngStream::Loot)),
+            first_values(RunSeed::typed(1234).stream(RngStream::Loot))
+        );
+        assert_ne!(
+            first_values(seed.stream(RngStream::Loot)),
+            first_values(seed.stream(RngStream::EnemyPathing))
+        );
+        assert_ne!(
+            first_values(seed.stream(RngStream::Loot)),
+            first_values(RunSeed::typed(1235).stream(RngStream::Loot))
+        );
+    }
+
+    #[test]
+    fn state_resumes_the_same_stream() {
+        let mut random = RunSeed::typed(99).stream(RngStream::EnemyPathing);
+        random.random.gen::<u64>();
+        let resumed = RandomDeterministic::from_state(random.state());
+        assert_eq!(first_values(random), first_values(resumed));
+    }
+
+    #[test]
+    fn daily_seeds_change_every_day() {
+        assert_eq!(RunSeed::for_day(19000), RunSeed::for_day(19000));
+        assert_ne!(RunSeed::for_day(19000).seed, RunSeed::for_day(19001).seed);
+    }
 }
@@ -0,0 +1,306 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    actions::{
+        game_control::GameControl,
+        player_command::{
+            collect_player_commands, execute_player_commands, PlayerCommand, TickCommands,
+        },
+    },
+    difficulty::{Difficulty, DifficultySettings},
+    grid::MapDefinition,
+    network::exchange_commands,
+    random::RunSeed,
+    tick::{Tick, TickSet},
+    GameState,
+};
+
+pub struct ReplayPlugin;
+
+/// This plugin records the commands of the player during a run, with the tick they were carried out at.
+/// F6 writes them to [`REPLAY_FILE`] along with the seed and the difficulty of the run.
+/// The menu offers to watch the recorded run: its commands are played back at the same ticks,
+/// and the input of the player is ignored until the last one.
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Playing), start_recording)
+            .add_systems(
+                FixedUpdate,
+                (
+                    play_back_commands.run_if(resource_exists::<ReplayPlayer>()),
+                    record_commands,
+                )
+                    .chain()
+                    .after(collect_player_commands)
+                    .after(exchange_commands)
+                    .before(execute_player_commands)
+                    .in_set(TickSet::Commands)
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(Update, save_replay.run_if(in_state(GameState::Playing)));
+    }
+}
+
+/// where the replay is written, relative to the working directory
+pub const REPLAY_FILE: &str = "replay.ron";
+
+/// The version of the game, a replay only plays out the same way with the version that recorded it
+pub const GAME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A run told as the commands of the player
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Replay {
+    /// the [`GAME_VERSION`] that recorded it
+    pub version: String,
+    pub seed: RunSeed,
+    pub difficulty: Difficulty,
+    pub adaptive: bool,
+    /// replays recorded before the editor were played on the default map
+    #[serde(default)]
+    pub map: MapDefinition,
+    /// in the order they were carried out
+    pub commands: Vec<RecordedCommand>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RecordedCommand {
+    /// ticks since the start of the run
+    pub tick: u64,
+    pub command: PlayerCommand,
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("could not access the replay file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse the replay file: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("could not write the replay file: {0}")]
+    Write(#[from] ron::Error),
+    #[error("the replay was recorded by version {found} of the game, this is version {expected}")]
+    Version { found: String, expected: String },
+}
+
+impl Replay {
+    /// An empty replay of a run starting with these settings
+    pub fn new(seed: RunSeed, settings: &DifficultySettings, map: MapDefinition) -> Self {
+        Self {
+            version: GAME_VERSION.to_string(),
+            seed,
+            difficulty: settings.preset,
+            adaptive: settings.adaptive,
+            map,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Reads the replay at `path`, refusing the ones recorded by another version of the game
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let replay: Self = ron::from_str(&std::fs::read_to_string(path)?)?;
+        if replay.version != GAME_VERSION {
+            return Err(ReplayError::Version {
+                found: replay.version,
+                expected: GAME_VERSION.to_string(),
+            });
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        let content = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+/// The run in progress, as it is played
+#[derive(Resource, Debug)]
+pub struct ReplayRecorder(pub Replay);
+
+/// A replay being watched
+#[derive(Resource, Debug)]
+pub struct ReplayPlayer {
+    replay: Replay,
+    /// index of the next command to play
+    next: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> Self {
+        Self { replay, next: 0 }
+    }
+
+    /// The commands recorded at `tick`, commands of earlier ticks are skipped
+    pub fn commands_at(&mut self, tick: u64) -> Vec<PlayerCommand> {
+        let remaining = &self.replay.commands[self.next..];
+        let skipped = remaining.iter().take_while(|c| c.tick < tick).count();
+        let commands: Vec<PlayerCommand> = remaining[skipped..]
+            .iter()
+            .take_while(|c| c.tick == tick)
+            .map(|c| c.command)
+            .collect();
+        self.next += skipped + commands.len();
+        commands
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.replay.commands.len()
+    }
+}
+
+fn start_recording(
+    mut commands: Commands,
+    seed: Res<RunSeed>,
+    settings: Res<DifficultySettings>,
+    map: Res<MapDefinition>,
+) {
+    commands.insert_resource(ReplayRecorder(Replay::new(*seed, &settings, map.clone())));
+}
+
+fn play_back_commands(
+    mut commands: Commands,
+    tick: Res<Tick>,
+    mut player: ResMut<ReplayPlayer>,
+    mut tick_commands: ResMut<TickCommands>,
+) {
+    // what the player does is ignored while the replay plays
+    tick_commands.0 = player.commands_at(tick.0);
+    if player.is_finished() {
+        info!("Replay finished at tick {}", tick.0);
+        commands.remove_resource::<ReplayPlayer>();
+    }
+}
+
+fn record_commands(
+    tick: Res<Tick>,
+    tick_commands: Res<TickCommands>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+) {
+    let Some(mut recorder) = recorder else {
+        return;
+    };
+    recorder
+        .0
+        .commands
+        .extend(tick_commands.0.iter().map(|&command| RecordedCommand {
+            tick: tick.0,
+            command,
+        }));
+}
+
+fn save_replay(keyboard_input: Res<Input<KeyCode>>, recorder: Option<Res<ReplayRecorder>>) {
+    if !GameControl::SaveReplay.just_pressed(&keyboard_input) {
+        return;
+    }
+    let Some(recorder) = recorder else {
+        return;
+    };
+    match recorder.0.save(Path::new(REPLAY_FILE)) {
+        Ok(()) => info!("Replay saved to {}", REPLAY_FILE),
+        Err(e) => error!("Could not save the replay: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{save::SavedRun, testing::TestRun};
+
+    fn replay() -> Replay {
+        let mut replay = Replay::new(
+            RunSeed::typed(40),
+            &DifficultySettings::default(),
+            MapDefinition::default(),
+        );
+        replay.commands = vec![
+            RecordedCommand {
+                tick: 3,
+                command: PlayerCommand::OpenPortal { hex: [4, -2] },
+            },
+            RecordedCommand {
+                tick: 3,
+                command: PlayerCommand::Build {
+                    hex: [1, 0],
+                    slot: 2,
+                },
+            },
+            RecordedCommand {
+                tick: 10,
+                command: PlayerCommand::Hold,
+            },
+            RecordedCommand {
+                tick: 12,
+                command: PlayerCommand::Reroll,
+            },
+        ];
+        replay
+    }
+
+    #[test]
+    fn commands_are_played_at_their_tick() {
+        let mut player = ReplayPlayer::new(replay());
+        assert!(player.commands_at(0).is_empty());
+        assert_eq!(
+            player.commands_at(3),
+            vec![
+                PlayerCommand::OpenPortal { hex: [4, -2] },
+                PlayerCommand::Build {
+                    hex: [1, 0],
+                    slot: 2
+                }
+            ]
+        );
+        assert!(player.commands_at(4).is_empty());
+        // a missed tick doesn't hold back the following ones
+        assert_eq!(player.commands_at(12), vec![PlayerCommand::Reroll]);
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn a_recorded_run_plays_back_the_same() {
+        let mut run = TestRun::recorded(None);
+        run.command(PlayerCommand::Build {
+            hex: [1, 0],
+            slot: 0,
+        });
+        run.advance(1.);
+        run.command(PlayerCommand::OpenPortal { hex: [4, -4] });
+        run.command(PlayerCommand::Hold);
+        run.advance(5.);
+        run.command(PlayerCommand::Reroll);
+        run.command(PlayerCommand::Build {
+            hex: [-1, 1],
+            slot: 1,
+        });
+        run.advance(20.);
+        let replay = run.world().resource::<ReplayRecorder>().0.clone();
+        assert_eq!(replay.commands.len(), 5);
+
+        let mut played = TestRun::recorded(Some(replay));
+        played.advance_ticks(run.tick() - played.tick());
+        assert!(played.world().get_resource::<ReplayPlayer>().is_none());
+        let end = SavedRun::capture(run.world()).unwrap();
+        assert_eq!(end.turrets.len(), 2);
+        // the enemies came out of the portal
+        assert!(!end.enemies.is_empty());
+        assert_eq!(SavedRun::capture(played.world()).unwrap(), end);
+    }
+
+    #[test]
+    fn replays_only_load_in_the_version_that_recorded_them() {
+        let path = std::env::temp_dir().join(format!("replay_{}.ron", std::process::id()));
+        replay().save(&path).unwrap();
+        assert_eq!(Replay::load(&path).unwrap(), replay());
+
+        let mut old = replay();
+        old.version = "0.0.0-old".to_string();
+        old.save(&path).unwrap();
+        let loaded = Replay::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(ReplayError::Version { .. })));
+    }
+}
@@ -0,0 +1,415 @@
+use std::{path::Path, time::Duration};
+
+use bevy::{
+    ecs::system::{Command, EntityCommand},
+    prelude::*,
+};
+use hexx::Hex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    actions::game_control::GameControl,
+    buildings::{Building, SavedBuildingInventory},
+    difficulty::{Difficulty, DifficultySettings, NextWave, Wave},
+    economy::{Energy, Ledger},
+    entities::{
+        bullet::Bullet,
+        enemy::{Enemy, EnemyKind, RestoreEnemyCmd},
+        portal::{Portal, RestorePortalCmd},
+        turret::{RestoreTurretCmd, Turret},
+    },
+    grid::{GridChanged, HexGrid, MapDefinition, Obstacle},
+    overload::Overload,
+    primitives::destructible::Destructible,
+    random::{RandomDeterministic, RandomState, RandomStreams, RngStream, RunSeed},
+    tick::Interpolated,
+    GameState,
+};
+
+pub struct SavePlugin;
+
+/// This plugin saves the run in progress to [`SAVE_FILE`] when F5 is pressed.
+/// The menu offers to continue it: the saved run is then restored on the first frame of the game.
+/// In-flight bullets, the energy ledger besides the kill count and what the adaptive difficulty
+/// learnt aren't saved.
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<EventSaveRun>().add_systems(
+            Update,
+            (
+                send_save_action,
+                save_run.run_if(on_event::<EventSaveRun>()),
+                restore_pending_run.run_if(resource_exists::<PendingRun>()),
+            )
+                .chain()
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+/// where the run is saved, relative to the working directory
+pub const SAVE_FILE: &str = "savegame.ron";
+
+/// Write the run in progress to [`SAVE_FILE`]
+#[derive(Event, Debug)]
+pub struct EventSaveRun;
+
+/// A saved run, restored as soon as the game starts
+#[derive(Resource, Debug)]
+pub struct PendingRun(pub SavedRun);
+
+/// Everything needed to resume a run where it was left
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedRun {
+    pub seed: RunSeed,
+    pub difficulty: Difficulty,
+    pub adaptive: bool,
+    /// the map is set up before the run is restored, runs saved before the editor were played on
+    /// the default map
+    #[serde(default)]
+    pub map: MapDefinition,
+    pub wave: u32,
+    pub next_wave: Vec<EnemyKind>,
+    pub overload: f32,
+    pub energy: u32,
+    pub turrets: Vec<SavedTurret>,
+    pub portals: Vec<SavedPortal>,
+    /// axial coordinates of the obstacles of the map that weren't cleared
+    #[serde(default)]
+    pub obstacles: Vec<[i32; 2]>,
+    pub enemies: Vec<SavedEnemy>,
+    pub inventory: SavedBuildingInventory,
+    pub enemy_pathing: RandomState,
+    /// runs saved before the waves were shuffled start the stream over
+    #[serde(default)]
+    pub waves: Option<RandomState>,
+    /// enemies killed before the save, they count in the score of the daily challenge
+    #[serde(default)]
+    pub kills: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedTurret {
+    /// axial coordinates of the hex the turret stands on
+    pub hex: [i32; 2],
+    pub building: Building,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedPortal {
+    /// axial coordinates of the hex the portal stands on
+    pub hex: [i32; 2],
+    /// enemies left to spawn, the next one last
+    pub enemies: Vec<EnemyKind>,
+    /// seconds since the last enemy came out
+    pub elapsed: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedEnemy {
+    pub kind: EnemyKind,
+    pub position: [f32; 2],
+    pub health: f32,
+}
+
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[error("could not access the save file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse the save file: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("could not write the save file: {0}")]
+    Write(#[from] ron::Error),
+}
+
+impl SavedRun {
+    /// A snapshot of the run in progress, `None` outside of a run
+    pub fn capture(world: &mut World) -> Option<Self> {
+        let inventory = SavedBuildingInventory::save(world)?;
+        let overload = world.query::<&Overload>().get_single(world).ok()?.0;
+        let layout = world.get_resource::<HexGrid>()?.layout.clone();
+        let hex_of = |world: &World, parent: &Parent| {
+            world.get::<Transform>(parent.get()).map(|transform| {
+                let hex = layout.world_pos_to_hex(transform.translation.xy());
+                [hex.x, hex.y]
+            })
+        };
+
+        let mut turrets: Vec<SavedTurret> = world
+            .query_filtered::<(&Building, &Parent), With<Turret>>()
+            .iter(world)
+            .filter_map(|(&building, parent)| {
+                Some(SavedTurret {
+                    hex: hex_of(world, parent)?,
+                    building,
+                })
+            })
+            .collect();
+        turrets.sort_by_key(|turret| turret.hex);
+
+        let mut portals: Vec<SavedPortal> = world
+            .query::<(&Portal, &Parent)>()
+            .iter(world)
+            .filter_map(|(portal, parent)| {
+                Some(SavedPortal {
+                    hex: hex_of(world, parent)?,
+                    enemies: portal.enemies().to_vec(),
+                    elapsed: portal.elapsed().as_secs_f32(),
+                })
+            })
+            .collect();
+        portals.sort_by_key(|portal| portal.hex);
+
+        let mut obstacles: Vec<[i32; 2]> = world
+            .query_filtered::<&Parent, With<Obstacle>>()
+            .iter(world)
+            .filter_map(|parent| hex_of(world, parent))
+            .collect();
+        obstacles.sort();
+
+        let mut enemies: Vec<SavedEnemy> = world
+            .query_filtered::<(&EnemyKind, &Transform, Option<&Interpolated>, &Destructible), With<Enemy>>()
+            .iter(world)
+            .map(|(&kind, transform, interpolated, destructible)| SavedEnemy {
+                kind,
+                // what is drawn may lag behind the simulation
+                position: interpolated
+                    .map_or(transform.translation, Interpolated::simulated)
+                    .xy()
+                    .to_array(),
+                health: destructible.health,
+            })
+            .collect();
+        enemies.sort_by(|a, b| {
+            (a.position[0].total_cmp(&b.position[0])).then(a.position[1].total_cmp(&b.position[1]))
+        });
+
+        let settings = world.resource::<DifficultySettings>();
+        Some(Self {
+            seed: *world.resource::<RunSeed>(),
+            difficulty: settings.preset,
+            adaptive: settings.adaptive,
+            map: world.resource::<MapDefinition>().clone(),
+            wave: world.resource::<Wave>().0,
+            next_wave: world.resource::<NextWave>().0.clone(),
+            overload,
+            energy: world.resource::<Energy>().balance(),
+            turrets,
+            portals,
+            obstacles,
+            enemies,
+            inventory,
+            enemy_pathing: world
+                .resource_mut::<RandomStreams>()
+                .get(RngStream::EnemyPathing)
+                .state(),
+            waves: Some(
+                world
+                    .resource_mut::<RandomStreams>()
+                    .get(RngStream::Waves)
+                    .state(),
+            ),
+            kills: world.resource::<Ledger>().kills(),
+        })
+    }
+
+    /// Replaces the run in progress with this one
+    pub fn restore(self, world: &mut World) {
+        world.insert_resource(self.seed);
+        world.insert_resource(DifficultySettings {
+            preset: self.difficulty,
+            adaptive: self.adaptive,
+        });
+        world.resource_mut::<Wave>().0 = self.wave;
+        world.resource_mut::<NextWave>().0 = self.next_wave;
+        world.resource_mut::<Energy>().restore(self.energy);
+        world.resource_mut::<Ledger>().restore(self.kills);
+        let mut streams = RandomStreams::new(self.seed);
+        streams.insert(
+            RngStream::EnemyPathing,
+            RandomDeterministic::from_state(self.enemy_pathing),
+        );
+        if let Some(waves) = self.waves {
+            streams.insert(RngStream::Waves, RandomDeterministic::from_state(waves));
+        }
+        world.insert_resource(streams);
+        if let Ok(mut overload) = world.query::<&mut Overload>().get_single_mut(world) {
+            overload.0 = self.overload;
+        }
+
+        // clear what the game started with
+        let spawned: Vec<Entity> = world
+            .query_filtered::<Entity, Or<(With<Turret>, With<Portal>, With<Enemy>, With<Bullet>)>>()
+            .iter(world)
+            .collect();
+        // and the obstacles cleared before the save
+        let cleared: Vec<Entity> = world
+            .query_filtered::<(Entity, &Parent), With<Obstacle>>()
+            .iter(world)
+            .filter(|(_, parent)| {
+                world
+                    .resource::<HexGrid>()
+                    .entity_to_hex(parent.get())
+                    .is_none_or(|hex| !self.obstacles.contains(&[hex.x, hex.y]))
+            })
+            .map(|(obstacle, _)| obstacle)
+            .collect();
+        for entity in spawned.into_iter().chain(cleared) {
+            world.entity_mut(entity).remove_parent();
+            world.entity_mut(entity).despawn_recursive();
+        }
+
+        for turret in self.turrets {
+            if let Some(parent_hex) = hex_entity(world, turret.hex) {
+                let id = world.spawn_empty().id();
+                RestoreTurretCmd {
+                    parent_hex,
+                    building: turret.building,
+                }
+                .apply(id, world);
+            }
+        }
+        for portal in self.portals {
+            if let Some(parent_hex) = hex_entity(world, portal.hex) {
+                let id = world.spawn_empty().id();
+                RestorePortalCmd {
+                    parent_hex,
+                    enemies: portal.enemies,
+                    elapsed: Duration::from_secs_f32(portal.elapsed),
+                }
+                .apply(id, world);
+            }
+        }
+        for enemy in self.enemies {
+            RestoreEnemyCmd {
+                position: Vec2::from_array(enemy.position),
+                kind: enemy.kind,
+                health: enemy.health,
+            }
+            .apply(world);
+        }
+        self.inventory.restore(world);
+        world.send_event(GridChanged);
+    }
+
+    /// Reads the run saved at `path`, `None` if there is none
+    pub fn load(path: &Path) -> Result<Option<Self>, SaveError> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(Some(ron::from_str(&content)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SaveError> {
+        let content = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+fn hex_entity(world: &World, [x, y]: [i32; 2]) -> Option<Entity> {
+    let entity = world
+        .resource::<HexGrid>()
+        .hex_to_entity(&Hex::new(x, y))
+        .copied();
+    if entity.is_none() {
+        warn!("Skipping saved content out of the grid at {:?}", [x, y]);
+    }
+    entity
+}
+
+fn send_save_action(keyboard_input: Res<Input<KeyCode>>, mut save: EventWriter<EventSaveRun>) {
+    if GameControl::SaveRun.just_pressed(&keyboard_input) {
+        save.send(EventSaveRun);
+    }
+}
+
+fn save_run(world: &mut World) {
+    let Some(saved) = SavedRun::capture(world) else {
+        return;
+    };
+    match saved.save(Path::new(SAVE_FILE)) {
+        Ok(()) => info!("Run saved to {}", SAVE_FILE),
+        Err(e) => error!("Could not save the run: {}", e),
+    }
+}
+
+fn restore_pending_run(world: &mut World) {
+    if let Some(PendingRun(saved)) = world.remove_resource::<PendingRun>() {
+        saved.restore(world);
+        info!("Run restored");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        buildings::{BuildingColor, BuildingMesh, BuildingSize},
+        random::SeedSource,
+        testing::TestRun,
+    };
+
+    #[test]
+    fn loading_a_save_gives_back_the_same_run() {
+        let mut run = TestRun::new();
+        let mut saved = SavedRun::capture(run.world()).unwrap();
+        saved.seed = RunSeed {
+            seed: 77,
+            source: SeedSource::Daily(20745),
+        };
+        saved.difficulty = Difficulty::Hard;
+        saved.wave = 3;
+        saved.next_wave = vec![EnemyKind::Runner, EnemyKind::Tank];
+        saved.overload = 0.75;
+        saved.energy = 42;
+        saved.kills = 12;
+        saved.turrets = vec![
+            SavedTurret {
+                hex: [-1, 2],
+                building: Building::new(
+                    BuildingMesh::Circle,
+                    BuildingSize::Big,
+                    BuildingColor::Pink,
+                ),
+            },
+            SavedTurret {
+                hex: [2, 0],
+                building: Building::new(
+                    BuildingMesh::Triangle,
+                    BuildingSize::Medium,
+                    BuildingColor::White,
+                ),
+            },
+        ];
+        saved.portals = vec![SavedPortal {
+            hex: [4, -4],
+            enemies: vec![EnemyKind::Tank, EnemyKind::Drone],
+            elapsed: 1.5,
+        }];
+        saved.enemies = vec![
+            SavedEnemy {
+                kind: EnemyKind::Drone,
+                position: [-30., 12.5],
+                health: 1.5,
+            },
+            SavedEnemy {
+                kind: EnemyKind::Tank,
+                position: [100., -40.],
+                health: 6.,
+            },
+        ];
+        saved.inventory.random = RunSeed::typed(77).stream(RngStream::Loot).state();
+
+        saved.clone().restore(run.world());
+        let captured = SavedRun::capture(run.world()).unwrap();
+        assert_eq!(captured, saved);
+
+        let loaded: SavedRun = ron::from_str(&ron::to_string(&captured).unwrap()).unwrap();
+        let mut other = TestRun::new();
+        loaded.restore(other.world());
+        assert_eq!(SavedRun::capture(other.world()).unwrap(), saved);
+    }
+}
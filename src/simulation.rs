@@ -0,0 +1,337 @@
+use std::time::{Duration, Instant};
+
+use bevy::{
+    asset::LoadState, input::InputPlugin, prelude::*, time::TimeUpdateStrategy, utils::HashSet,
+};
+use serde::Serialize;
+
+use crate::{
+    actions::ActionsPlugin,
+    autoplayer::{AutoPlayer, AutoPlayerPlugin, PlacementStrategy},
+    balance::{BalancePlugin, BaseBalance},
+    buildings::Building,
+    challenge::ChallengePlugin,
+    difficulty::{Difficulty, DifficultyPlugin, DifficultySettings, Wave},
+    economy::EconomyPlugin,
+    entities::{
+        crystal::CrystalTouched,
+        turret::{Turret, TurretStats},
+        EntityPlugin,
+    },
+    game_over::GameOverPlugin,
+    grid::{GridPlugin, HexGrid, HexMaterial, MapDefinition},
+    loading::TextureAssets,
+    network::NetworkPlugin,
+    overload::{Overload, OverloadDepleted, OverloadPlugin},
+    primitives::PrimitivesPlugin,
+    random::{RandomPlugin, RunSeed},
+    tick::{
+        count_ticks, record_simulated_translations, Tick, TickPlugin, TickSet, TICKS_PER_SECOND,
+    },
+    versus::VersusPlugin,
+    window::{WindowSize, DEFAULT_WINDOW_SIZE},
+    GameState,
+};
+
+pub struct SimulationPlugin;
+
+/// This plugin is the whole gameplay: the grid, the entities and their primitives, the overload,
+/// the buildings, the economy and the waves.
+/// It runs under `MinimalPlugins` with [`HeadlessPlugin`], [`crate::GamePlugin`] adds the rendering,
+/// the menu and the input of the player on top of it.
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<GameState>().add_plugins((
+            BalancePlugin,
+            TickPlugin,
+            DifficultyPlugin,
+            RandomPlugin,
+            ChallengePlugin,
+            ActionsPlugin,
+            AutoPlayerPlugin,
+            EntityPlugin,
+            GridPlugin,
+            PrimitivesPlugin,
+            OverloadPlugin,
+            EconomyPlugin,
+            GameOverPlugin,
+            VersusPlugin,
+            NetworkPlugin,
+        ));
+    }
+}
+
+/// Stands in for what the window and the renderer give to the simulation.
+/// Time goes one tick per update, as fast as the computer can.
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((AssetPlugin::default(), InputPlugin))
+            .init_asset::<Image>()
+            .init_asset::<Mesh>()
+            .init_asset::<ColorMaterial>()
+            .init_asset::<HexMaterial>()
+            .insert_resource(TextureAssets {
+                portal: Handle::default(),
+            })
+            .insert_resource(WindowSize {
+                size: DEFAULT_WINDOW_SIZE,
+            })
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+                1. / TICKS_PER_SECOND,
+            )));
+    }
+}
+
+/// how long to wait for the balance file before playing with the default balance
+const BALANCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct SimulationSettings {
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    /// the run stops after this long, or when it is lost
+    pub seconds: f32,
+    /// where the autoplayer builds
+    pub strategy: PlacementStrategy,
+    pub map: MapDefinition,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            difficulty: Difficulty::default(),
+            seconds: 600.,
+            strategy: PlacementStrategy::GreedyMaze,
+            map: MapDefinition::default(),
+        }
+    }
+}
+
+/// How a simulated run went
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SimulationReport {
+    pub seed: u64,
+    pub difficulty: Difficulty,
+    pub seconds: f32,
+    pub lost: bool,
+    /// portals opened before the end of the run, the last one doesn't count when the run was lost
+    pub waves_survived: u32,
+    /// enemies that reached the crystal
+    pub leaks: u32,
+    /// overload at the end of each second
+    pub overload: Vec<f32>,
+    pub turrets: Vec<TurretReport>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TurretReport {
+    /// axial coordinates of the hex the turret stands on
+    pub hex: [i32; 2],
+    pub building: String,
+    pub shots_fired: u32,
+    pub kills: u32,
+}
+
+/// What the report needs that the gameplay doesn't keep
+#[derive(Resource, Debug, Default)]
+pub(crate) struct SimulationRecord {
+    pub(crate) overload: Vec<f32>,
+    /// enemies that touched the crystal
+    pub(crate) leaked: HashSet<Entity>,
+    pub(crate) lost_at: Option<u64>,
+}
+
+/// Watches the run
+pub(crate) struct SimulatorPlugin;
+
+impl Plugin for SimulatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationRecord>().add_systems(
+            FixedUpdate,
+            record_run
+                .after(record_simulated_translations)
+                .before(count_ticks)
+                .in_set(TickSet::Finish)
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+/// Plays a whole run without a window, as fast as possible
+pub fn simulate(settings: &SimulationSettings) -> SimulationReport {
+    let mut app = headless_run(settings.seed, settings.difficulty, settings.map.clone());
+    app.insert_resource(AutoPlayer::new(settings.strategy.clone()));
+
+    let last_tick = (settings.seconds as f64 * TICKS_PER_SECOND) as u64;
+    while app.world.resource::<Tick>().0 < last_tick
+        && app.world.resource::<SimulationRecord>().lost_at.is_none()
+    {
+        app.update();
+    }
+    report(&mut app.world, settings)
+}
+
+/// A run that just started on `map`, without a window and without a player
+pub(crate) fn headless_run(seed: u64, difficulty: Difficulty, map: MapDefinition) -> App {
+    let mut app = headless_app(seed, difficulty, map);
+    start_run(&mut app);
+    app
+}
+
+/// The app of [`headless_run`] before its first update
+pub(crate) fn headless_app(seed: u64, difficulty: Difficulty, map: MapDefinition) -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        HeadlessPlugin,
+        SimulationPlugin,
+        SimulatorPlugin,
+    ))
+    .insert_resource(RunSeed::typed(seed))
+    .insert_resource(DifficultySettings {
+        preset: difficulty,
+        adaptive: false,
+    })
+    .insert_resource(map);
+    app
+}
+
+pub(crate) fn start_run(app: &mut App) {
+    load_balance(app);
+
+    app.world
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::Menu);
+    app.update();
+    app.world
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::Playing);
+    app.update();
+}
+
+/// Waits for the balance file, which loads on another thread
+fn load_balance(app: &mut App) {
+    let started = Instant::now();
+    loop {
+        app.update();
+        let Some(base) = app.world.get_resource::<BaseBalance>() else {
+            continue;
+        };
+        match app.world.resource::<AssetServer>().load_state(&base.0) {
+            LoadState::Loaded => break,
+            LoadState::Failed => {
+                warn!("Could not load the balance file, simulating with the default balance");
+                return;
+            }
+            LoadState::NotLoaded | LoadState::Loading => {}
+        }
+        if started.elapsed() > BALANCE_TIMEOUT {
+            warn!("The balance file takes too long to load, simulating with the default balance");
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    // the loaded balance is applied on the next updates
+    app.update();
+    app.update();
+}
+
+fn report(world: &mut World, settings: &SimulationSettings) -> SimulationReport {
+    let record = world
+        .remove_resource::<SimulationRecord>()
+        .unwrap_or_default();
+    let lost = record.lost_at.is_some();
+    let waves = world.resource::<Wave>().0;
+    let mut q_turrets = world.query_filtered::<(&TurretStats, &Building, &Parent), With<Turret>>();
+    let grid = world.resource::<HexGrid>();
+    let mut turrets: Vec<TurretReport> = q_turrets
+        .iter(world)
+        .filter_map(|(stats, building, parent)| {
+            let hex = grid.entity_to_hex(parent.get())?;
+            Some(TurretReport {
+                hex: [hex.x, hex.y],
+                building: format!(
+                    "{:?} {:?} {:?}",
+                    building.size(),
+                    building.color(),
+                    building.mesh()
+                ),
+                shots_fired: stats.shots_fired,
+                kills: stats.kills,
+            })
+        })
+        .collect();
+    turrets.sort_by_key(|turret| turret.hex);
+
+    SimulationReport {
+        seed: settings.seed,
+        difficulty: settings.difficulty,
+        seconds: (world.resource::<Tick>().0 as f64 / TICKS_PER_SECOND) as f32,
+        lost,
+        waves_survived: if lost { waves.saturating_sub(1) } else { waves },
+        leaks: record.leaked.len() as u32,
+        overload: record.overload,
+        turrets,
+    }
+}
+
+fn record_run(
+    tick: Res<Tick>,
+    mut record: ResMut<SimulationRecord>,
+    q_overload: Query<&Overload>,
+    mut crystal_touched: EventReader<CrystalTouched>,
+    mut overload_depleted: EventReader<OverloadDepleted>,
+) {
+    record
+        .leaked
+        .extend(crystal_touched.read().map(|touched| touched.enemy));
+    if overload_depleted.read().count() > 0 && record.lost_at.is_none() {
+        record.lost_at = Some(tick.0);
+    }
+    // the tick is counted right after
+    if (tick.0 + 1).is_multiple_of(TICKS_PER_SECOND as u64) {
+        let overload = q_overload.get_single().map_or(0., |overload| overload.0);
+        record.overload.push(overload);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::{LogLevel, ScheduleBuildSettings};
+
+    use super::*;
+
+    #[test]
+    fn a_simulated_run_is_reproducible() {
+        let settings = SimulationSettings {
+            seed: 41,
+            seconds: 90.,
+            ..default()
+        };
+        let report = simulate(&settings);
+        assert_eq!(report.overload.len(), 90);
+        assert!(!report.lost);
+        assert!(report.waves_survived > 0);
+        assert!(!report.turrets.is_empty());
+        assert_eq!(simulate(&settings), report);
+    }
+
+    #[test]
+    fn the_systems_of_a_tick_run_in_a_fixed_order() {
+        let mut app = headless_app(3, Difficulty::default(), MapDefinition::default());
+        // systems that touch the same data without an order could run either way around,
+        // and lockstep peers or replays would diverge
+        app.edit_schedule(FixedUpdate, |schedule| {
+            schedule.set_build_settings(ScheduleBuildSettings {
+                ambiguity_detection: LogLevel::Error,
+                ..default()
+            });
+        });
+        start_run(&mut app);
+        app.update();
+        assert!(app.world.resource::<Tick>().0 > 0);
+    }
+}
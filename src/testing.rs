@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use bevy::{
+    ecs::system::{Command, EntityCommand},
+    prelude::*,
+    time::TimeUpdateStrategy,
+};
+use hexx::Hex;
+
+use crate::{
+    actions::player_command::{free_hex, EventPlayerCommand, PlayerCommand},
+    buildings::Building,
+    difficulty::Difficulty,
+    entities::{
+        enemy::{Enemy, EnemyKind, SpawnEnemyCmd},
+        turret::{RestoreTurretCmd, Turret, TurretStats},
+    },
+    grid::{GridChanged, HexGrid, MapDefinition, NonConstructible},
+    replay::{Replay, ReplayPlayer, ReplayPlugin},
+    simulation::{headless_app, headless_run, start_run, SimulationRecord},
+    tick::{Tick, TICKS_PER_SECOND},
+};
+
+/// A run that just started: the grid and the crystal are there, no portal is opened
+pub(crate) struct TestRun {
+    pub(crate) app: App,
+}
+
+impl TestRun {
+    pub(crate) fn new() -> Self {
+        Self::on_map(MapDefinition::default())
+    }
+
+    pub(crate) fn on_map(map: MapDefinition) -> Self {
+        Self {
+            app: headless_run(0, Difficulty::default(), map),
+        }
+    }
+
+    /// A run drawn at frames of `frame_time`, instead of one tick per frame
+    pub(crate) fn with_frame_time(frame_time: Duration) -> Self {
+        let mut app = headless_app(0, Difficulty::default(), MapDefinition::default());
+        app.insert_resource(TimeUpdateStrategy::ManualDuration(frame_time));
+        start_run(&mut app);
+        Self { app }
+    }
+
+    /// A run recorded by the [`ReplayPlugin`], playing `replay` back when there is one
+    pub(crate) fn recorded(replay: Option<Replay>) -> Self {
+        let mut app = headless_app(0, Difficulty::default(), MapDefinition::default());
+        app.add_plugins(ReplayPlugin);
+        if let Some(replay) = replay {
+            app.insert_resource(replay.seed)
+                .insert_resource(ReplayPlayer::new(replay));
+        }
+        start_run(&mut app);
+        Self { app }
+    }
+
+    pub(crate) fn world(&mut self) -> &mut World {
+        &mut self.app.world
+    }
+
+    pub(crate) fn hex_entity(&self, hex: Hex) -> Entity {
+        *self
+            .app
+            .world
+            .resource::<HexGrid>()
+            .hex_to_entity(&hex)
+            .unwrap_or_else(|| panic!("{hex:?} is not on the map"))
+    }
+
+    /// Builds `building` on `hex` for free, with the rules of the player.
+    /// `None` when nothing can be built there
+    pub(crate) fn place_turret(&mut self, hex: Hex, building: Building) -> Option<Entity> {
+        let world = self.world();
+        let parent_hex = free_hex(world, [hex.x, hex.y])?;
+        let id = world.spawn_empty().id();
+        RestoreTurretCmd {
+            parent_hex,
+            building,
+        }
+        .apply(id, world);
+        world.send_event(GridChanged);
+        // the grid takes the turret into account at the next tick
+        self.advance_ticks(1);
+        Some(id)
+    }
+
+    /// Carries out `command` at the next tick, as if the player had sent it
+    pub(crate) fn command(&mut self, command: PlayerCommand) {
+        self.world().send_event(EventPlayerCommand(command));
+        self.advance_ticks(1);
+    }
+
+    pub(crate) fn spawn_enemy(&mut self, kind: EnemyKind, hex: Hex) -> Entity {
+        let before = self.enemies();
+        let position = self
+            .app
+            .world
+            .resource::<HexGrid>()
+            .layout
+            .hex_to_world_pos(hex);
+        SpawnEnemyCmd { position, kind }.apply(self.world());
+        self.enemies()
+            .into_iter()
+            .find(|enemy| !before.contains(enemy))
+            .expect("the enemy was spawned")
+    }
+
+    pub(crate) fn advance(&mut self, seconds: f32) {
+        self.advance_ticks((seconds as f64 * TICKS_PER_SECOND) as u64);
+    }
+
+    pub(crate) fn advance_ticks(&mut self, ticks: u64) {
+        let last_tick = self.tick() + ticks;
+        while self.tick() < last_tick {
+            self.app.update();
+        }
+    }
+
+    /// Advances until `condition` holds, for at most `seconds`. Tells whether it held
+    pub(crate) fn advance_until(
+        &mut self,
+        seconds: f32,
+        mut condition: impl FnMut(&mut Self) -> bool,
+    ) -> bool {
+        let last_tick = self.tick() + (seconds as f64 * TICKS_PER_SECOND) as u64;
+        while self.tick() < last_tick {
+            if condition(self) {
+                return true;
+            }
+            self.app.update();
+        }
+        condition(self)
+    }
+
+    pub(crate) fn tick(&self) -> u64 {
+        self.app.world.resource::<Tick>().0
+    }
+
+    pub(crate) fn enemies(&mut self) -> Vec<Entity> {
+        self.world()
+            .query_filtered::<Entity, With<Enemy>>()
+            .iter(&self.app.world)
+            .collect()
+    }
+
+    pub(crate) fn is_alive(&self, entity: Entity) -> bool {
+        self.app.world.get_entity(entity).is_some()
+    }
+
+    /// enemies that touched the crystal so far
+    pub(crate) fn leaks(&self) -> usize {
+        self.app.world.resource::<SimulationRecord>().leaked.len()
+    }
+
+    pub(crate) fn turret_stats(&self, turret: Entity) -> &TurretStats {
+        self.app
+            .world
+            .get::<TurretStats>(turret)
+            .expect("the turret is still there")
+    }
+
+    /// the turret standing on `hex`
+    pub(crate) fn turret_on(&mut self, hex: Hex) -> Option<Entity> {
+        let hex_entity = self.hex_entity(hex);
+        self.world()
+            .query_filtered::<(Entity, &Parent), With<Turret>>()
+            .iter(&self.app.world)
+            .find(|(_, parent)| parent.get() == hex_entity)
+            .map(|(turret, _)| turret)
+    }
+
+    pub(crate) fn is_constructible(&self, hex: Hex) -> bool {
+        !self
+            .app
+            .world
+            .entity(self.hex_entity(hex))
+            .contains::<NonConstructible>()
+    }
+}
@@ -0,0 +1,286 @@
+use bevy::{
+    ecs::event::event_update_system,
+    prelude::*,
+    transform::systems::{propagate_transforms, sync_simple_transforms},
+};
+
+use crate::GameState;
+
+pub struct TickPlugin;
+
+/// The gameplay runs in `FixedUpdate`, [`TICKS_PER_SECOND`] times per second of game time whatever
+/// the frame rate, so that two runs with the same seed and the same player commands play out the same way.
+/// Gameplay systems go in one of the [`GameplaySet`] steps of [`TickSet::Gameplay`] and their events
+/// are registered with [`TickEventApp::add_tick_event`]. `Update` only reads the simulation to draw it:
+/// moving entities are drawn between their last two simulated positions, see [`Interpolated`].
+impl Plugin for TickPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(TICKS_PER_SECOND))
+            .init_resource::<Tick>()
+            .configure_sets(
+                FixedUpdate,
+                (
+                    TickSet::Prepare,
+                    TickSet::Commands,
+                    TickSet::Gameplay,
+                    TickSet::Finish,
+                )
+                    .chain(),
+            )
+            .configure_sets(
+                FixedUpdate,
+                (
+                    GameplaySet::Inventory,
+                    GameplaySet::Waves,
+                    GameplaySet::Move,
+                    GameplaySet::Shoot,
+                    GameplaySet::Projectiles,
+                    GameplaySet::Damage,
+                    GameplaySet::Overload,
+                    GameplaySet::Outcome,
+                )
+                    .chain()
+                    .in_set(TickSet::Gameplay),
+            )
+            .add_systems(OnEnter(GameState::Playing), reset_tick)
+            .add_systems(
+                FixedUpdate,
+                (
+                    restore_simulated_translations,
+                    // the positions drawn last frame are not the simulated ones
+                    sync_simple_transforms,
+                    propagate_transforms,
+                )
+                    .chain()
+                    .in_set(TickSet::Prepare),
+            )
+            .add_systems(
+                FixedUpdate,
+                (apply_deferred, record_simulated_translations, count_ticks)
+                    .chain()
+                    .in_set(TickSet::Finish),
+            )
+            .add_systems(Update, interpolate_translations);
+    }
+}
+
+/// How many times per second the simulation advances
+pub const TICKS_PER_SECOND: f64 = 60.;
+
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TickSet {
+    /// puts the simulated positions back
+    Prepare,
+    /// carries out what the player did since the last tick
+    Commands,
+    Gameplay,
+    /// applies the commands of the tick, then clears the old tick events
+    Finish,
+}
+
+/// The steps of [`TickSet::Gameplay`], one after the other: the systems that touch the same data
+/// never run in an order picked by the scheduler, which would change the run from one time to the next
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameplaySet {
+    /// the buildings are held or rerolled
+    Inventory,
+    /// the portals release their enemies and the waves are counted
+    Waves,
+    /// the enemies walk the grid or fly
+    Move,
+    /// the turrets pick their targets and shoot
+    Shoot,
+    /// the bullets fly to their targets
+    Projectiles,
+    /// the damage is dealt, the destroyed entities are rewarded then removed
+    Damage,
+    /// the enemies touching the crystal and the overload
+    Overload,
+    /// what the tick led to: interest, score, end of the run
+    Outcome,
+}
+
+/// Number of ticks simulated since the run started
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tick(pub u64);
+
+/// Gives a moving entity a smooth motion between two ticks.
+/// The simulation only sees the translation of the last tick, whatever is drawn.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Interpolated {
+    previous: Vec3,
+    current: Vec3,
+}
+
+impl Interpolated {
+    pub fn at(translation: Vec3) -> Self {
+        Self {
+            previous: translation,
+            current: translation,
+        }
+    }
+
+    /// The translation at the last tick
+    pub fn simulated(&self) -> Vec3 {
+        self.current
+    }
+}
+
+pub trait TickEventApp {
+    /// Like `add_event`, but the events are kept for two ticks instead of two frames,
+    /// so that gameplay systems don't miss them when several frames pass without a tick
+    fn add_tick_event<E: Event>(&mut self) -> &mut Self;
+}
+
+impl TickEventApp for App {
+    fn add_tick_event<E: Event>(&mut self) -> &mut Self {
+        if !self.world.contains_resource::<Events<E>>() {
+            self.init_resource::<Events<E>>().add_systems(
+                FixedUpdate,
+                event_update_system::<E>
+                    .after(count_ticks)
+                    .in_set(TickSet::Finish),
+            );
+        }
+        self
+    }
+}
+
+fn restore_simulated_translations(mut q_moving: Query<(&mut Transform, &Interpolated)>) {
+    for (mut transform, interpolated) in &mut q_moving {
+        transform.translation = interpolated.current;
+    }
+}
+
+pub(crate) fn record_simulated_translations(mut q_moving: Query<(&Transform, &mut Interpolated)>) {
+    for (transform, mut interpolated) in &mut q_moving {
+        interpolated.previous = interpolated.current;
+        interpolated.current = transform.translation;
+    }
+}
+
+fn reset_tick(mut tick: ResMut<Tick>) {
+    tick.0 = 0;
+}
+
+pub(crate) fn count_ticks(mut tick: ResMut<Tick>) {
+    tick.0 += 1;
+}
+
+fn interpolate_translations(
+    time: Res<Time<Fixed>>,
+    mut q_moving: Query<(&mut Transform, &Interpolated)>,
+) {
+    let progress = time.overstep_percentage();
+    for (mut transform, interpolated) in &mut q_moving {
+        transform.translation = interpolated.previous.lerp(interpolated.current, progress);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hexx::Hex;
+
+    use super::*;
+    use crate::{
+        actions::player_command::{EventPlayerCommand, PlayerCommand},
+        buildings::{Building, BuildingColor, BuildingMesh, BuildingSize},
+        difficulty::Wave,
+        economy::Energy,
+        entities::enemy::{Enemy, EnemyKind},
+        overload::Overload,
+        primitives::destructible::Destructible,
+        testing::TestRun,
+    };
+
+    const SNAPSHOT_EVERY: u64 = 60;
+    /// at 30 frames per second the ticks go two by two, both runs stop on this one
+    const SETUP_TICK: u64 = 10;
+    const LAST_TICK: u64 = 45 * TICKS_PER_SECOND as u64;
+
+    /// What the run looks like at a given tick
+    #[derive(Debug, PartialEq)]
+    struct Snapshot {
+        tick: u64,
+        enemies: Vec<(EnemyKind, [f32; 2], f32)>,
+        energy: u32,
+        overload: f32,
+        wave: u32,
+    }
+
+    #[derive(Resource, Default)]
+    struct Snapshots(Vec<Snapshot>);
+
+    fn take_snapshot(
+        tick: Res<Tick>,
+        q_enemies: Query<(&EnemyKind, &Interpolated, &Destructible), With<Enemy>>,
+        energy: Res<Energy>,
+        q_overload: Query<&Overload>,
+        wave: Res<Wave>,
+        mut snapshots: ResMut<Snapshots>,
+    ) {
+        if !tick.0.is_multiple_of(SNAPSHOT_EVERY) {
+            return;
+        }
+        let mut enemies: Vec<_> = q_enemies
+            .iter()
+            .map(|(&kind, interpolated, destructible)| {
+                let position = interpolated.simulated().truncate().to_array();
+                (kind, position, destructible.health)
+            })
+            .collect();
+        enemies.sort_by(|a, b| a.1[0].total_cmp(&b.1[0]).then(a.1[1].total_cmp(&b.1[1])));
+        snapshots.0.push(Snapshot {
+            tick: tick.0,
+            enemies,
+            energy: energy.balance(),
+            overload: q_overload.single().0,
+            wave: wave.0,
+        });
+    }
+
+    /// Plays the same run with frames of `frame_time`
+    fn play(frame_time: Duration) -> Vec<Snapshot> {
+        let mut run = TestRun::with_frame_time(frame_time);
+        run.app.init_resource::<Snapshots>().add_systems(
+            FixedUpdate,
+            take_snapshot.after(count_ticks).in_set(TickSet::Finish),
+        );
+        run.advance_ticks(SETUP_TICK - run.tick());
+        // the portal opens on the tick the turret is taken into account
+        run.world()
+            .send_event(EventPlayerCommand(PlayerCommand::OpenPortal {
+                hex: [6, -3],
+            }));
+        run.place_turret(
+            Hex::new(2, -1),
+            Building::new(
+                BuildingMesh::Triangle,
+                BuildingSize::Small,
+                BuildingColor::Pink,
+            ),
+        )
+        .expect("the hex is free");
+
+        run.advance_ticks(LAST_TICK - run.tick());
+        std::mem::take(&mut run.world().resource_mut::<Snapshots>().0)
+            .into_iter()
+            .filter(|snapshot| snapshot.tick <= LAST_TICK)
+            .collect()
+    }
+
+    #[test]
+    fn frame_rate_does_not_change_the_run() {
+        let at_30_fps = play(Duration::from_secs_f64(1. / 30.));
+        let at_144_fps = play(Duration::from_secs_f64(1. / 144.));
+        assert_eq!(at_30_fps.len() as u64, LAST_TICK / SNAPSHOT_EVERY);
+        // the enemies came out of the portal, then the turret shot them all
+        assert!(at_30_fps
+            .iter()
+            .any(|snapshot| !snapshot.enemies.is_empty()));
+        assert!(at_30_fps.last().unwrap().enemies.is_empty());
+        assert_eq!(at_30_fps, at_144_fps);
+    }
+}
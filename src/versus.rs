@@ -0,0 +1,596 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use hexx::Hex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::{
+        game_control::GameControl,
+        player_command::{
+            collect_player_commands, execute_player_commands, EventPlayerCommand, PlayerCommand,
+            TickCommands,
+        },
+    },
+    entities::{
+        crystal::CrystalTouched,
+        enemy::{Enemy, EnemyKind},
+        portal::Portal,
+    },
+    grid::{HexGrid, MapDefinition},
+    tick::{GameplaySet, Tick, TickEventApp, TickSet, TICKS_PER_SECOND},
+    GameState,
+};
+
+pub struct VersusPlugin;
+
+/// This plugin runs a match between two players while a [`Versus`] resource exists:
+/// the attacker buys enemies with its own budget, the threat, and opens the portals that release
+/// them, the defender builds as in a solo run.
+/// The attacker wins as soon as an enemy touches the crystal, the defender by holding out.
+/// In real time, both play at once and the threat grows with time until the end of the match.
+/// In turns, the defender builds while the attacker buys, then the attacker opens its portals
+/// while the defender watches, for a few rounds
+impl Plugin for VersusPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_tick_event::<VersusOver>()
+            .add_systems(
+                FixedUpdate,
+                enforce_phase
+                    .after(collect_player_commands)
+                    .before(execute_player_commands)
+                    .in_set(TickSet::Commands)
+                    .run_if(resource_exists::<Versus>())
+                    .run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(
+                FixedUpdate,
+                referee
+                    .in_set(GameplaySet::Outcome)
+                    .run_if(resource_exists::<Versus>())
+                    .run_if(in_state(GameState::Playing)),
+            );
+    }
+}
+
+pub struct VersusUiPlugin;
+
+/// The controls of the attacker, on the first gamepad or on the keyboard, what both players
+/// need to know during the match, and who won
+impl Plugin for VersusUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::Playing),
+            spawn_versus_hud.run_if(resource_exists::<Versus>()),
+        )
+        .add_systems(
+            Update,
+            (
+                move_attacker_cursor,
+                send_attacker_commands,
+                draw_attacker_cursor,
+                update_versus_hud,
+                show_winner,
+            )
+                .chain()
+                .run_if(resource_exists::<Versus>().and_then(resource_exists::<HexGrid>()))
+                .run_if(in_state(GameState::Playing)),
+        );
+    }
+}
+
+/// how long a real time match lasts
+const MATCH_SECONDS: f64 = 240.;
+/// the threat of the attacker at the start of a real time match
+const START_THREAT: u32 = 6;
+/// what the attacker earns each second of a real time match
+const THREAT_PER_SECOND: u32 = 1;
+const ROUNDS: u32 = 5;
+/// what the attacker earns at the start of each round of a match in turns
+const THREAT_PER_ROUND: u32 = 15;
+const BUILD_SECONDS: f64 = 20.;
+/// how long the attacker has to open its portals in a round
+const ATTACK_SECONDS: f64 = 10.;
+
+const CURSOR_COLOR: Color = Color::ORANGE_RED;
+/// how far the stick must be pushed to move the cursor
+const STICK_THRESHOLD: f32 = 0.5;
+/// the delay between two moves of the cursor while the stick is held
+const STICK_REPEAT_SECONDS: f32 = 0.2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Attacker,
+    Defender,
+}
+
+impl Side {
+    /// The attacker opens the portals and buys the enemies, the defender does the rest
+    pub fn controls(&self, command: &PlayerCommand) -> bool {
+        let attacks = matches!(
+            command,
+            PlayerCommand::OpenPortal { .. } | PlayerCommand::QueueEnemy { .. }
+        );
+        attacks == (*self == Side::Attacker)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersusVariant {
+    RealTime,
+    Turns,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersusPhase {
+    /// the defender builds and the attacker buys enemies
+    Build,
+    /// the attacker opens its portals
+    Attack,
+}
+
+/// Sent when a versus match is over
+#[derive(Event, Debug, Clone, Copy)]
+pub struct VersusOver {
+    pub winner: Side,
+}
+
+/// What the attacker pays for an enemy
+pub fn enemy_cost(kind: EnemyKind) -> u32 {
+    match kind {
+        EnemyKind::Drone => 2,
+        EnemyKind::Runner => 3,
+        EnemyKind::Tank => 5,
+        EnemyKind::Flyer => 4,
+    }
+}
+
+/// The state of a versus match
+#[derive(Resource, Debug, Clone)]
+pub struct Versus {
+    pub variant: VersusVariant,
+    /// what the attacker can spend on enemies
+    pub threat: u32,
+    /// enemies bought for the next portal, in the order they come out
+    pub queued: Vec<EnemyKind>,
+    /// always [`VersusPhase::Attack`] in real time
+    pub phase: VersusPhase,
+    /// the tick the current phase started at
+    phase_start: u64,
+    /// starts at 1
+    pub round: u32,
+    pub winner: Option<Side>,
+}
+
+impl Versus {
+    pub fn new(variant: VersusVariant) -> Self {
+        let (threat, phase) = match variant {
+            VersusVariant::RealTime => (START_THREAT, VersusPhase::Attack),
+            VersusVariant::Turns => (THREAT_PER_ROUND, VersusPhase::Build),
+        };
+        Self {
+            variant,
+            threat,
+            queued: Vec::new(),
+            phase,
+            phase_start: 0,
+            round: 1,
+            winner: None,
+        }
+    }
+
+    /// Buys `kind` for the next portal, if the attacker can afford it
+    pub fn buy(&mut self, kind: EnemyKind) -> bool {
+        let cost = enemy_cost(kind);
+        if self.threat < cost {
+            return false;
+        }
+        self.threat -= cost;
+        self.queued.push(kind);
+        true
+    }
+
+    /// Whether `command` can be carried out now
+    pub fn allows(&self, command: &PlayerCommand) -> bool {
+        if self.winner.is_some() {
+            return false;
+        }
+        match (self.variant, self.phase, command) {
+            (VersusVariant::RealTime, _, _) => true,
+            (VersusVariant::Turns, VersusPhase::Build, PlayerCommand::OpenPortal { .. }) => false,
+            (VersusVariant::Turns, VersusPhase::Build, _) => true,
+            (VersusVariant::Turns, VersusPhase::Attack, PlayerCommand::OpenPortal { .. }) => true,
+            (VersusVariant::Turns, VersusPhase::Attack, _) => false,
+        }
+    }
+
+    /// Ticks left before the end of the match in real time, or of the phase in turns.
+    /// The attack phase also waits for the enemies to be gone
+    pub fn ticks_left(&self, tick: u64) -> u64 {
+        let seconds = match (self.variant, self.phase) {
+            (VersusVariant::RealTime, _) => MATCH_SECONDS,
+            (VersusVariant::Turns, VersusPhase::Build) => BUILD_SECONDS,
+            (VersusVariant::Turns, VersusPhase::Attack) => ATTACK_SECONDS,
+        };
+        let duration = (seconds * TICKS_PER_SECOND) as u64;
+        (self.phase_start + duration).saturating_sub(tick)
+    }
+
+    fn start_phase(&mut self, phase: VersusPhase, tick: u64) {
+        self.phase = phase;
+        self.phase_start = tick;
+    }
+}
+
+/// Drops the commands the rules of the match don't allow at the moment
+pub(crate) fn enforce_phase(versus: Res<Versus>, mut tick_commands: ResMut<TickCommands>) {
+    tick_commands.0.retain(|command| versus.allows(command));
+}
+
+fn referee(
+    tick: Res<Tick>,
+    mut versus: ResMut<Versus>,
+    mut crystal_touched: EventReader<CrystalTouched>,
+    q_portals: Query<(), With<Portal>>,
+    q_enemies: Query<(), With<Enemy>>,
+    mut versus_over: EventWriter<VersusOver>,
+) {
+    if versus.winner.is_some() {
+        return;
+    }
+    let winner = if crystal_touched.read().count() > 0 {
+        Some(Side::Attacker)
+    } else {
+        match (versus.variant, versus.phase) {
+            (VersusVariant::RealTime, _) => {
+                if (tick.0 + 1).is_multiple_of(TICKS_PER_SECOND as u64) {
+                    versus.threat += THREAT_PER_SECOND;
+                }
+                (versus.ticks_left(tick.0) == 0).then_some(Side::Defender)
+            }
+            (VersusVariant::Turns, VersusPhase::Build) => {
+                if versus.ticks_left(tick.0) == 0 {
+                    versus.start_phase(VersusPhase::Attack, tick.0);
+                }
+                None
+            }
+            (VersusVariant::Turns, VersusPhase::Attack) => {
+                let cleared = q_portals.is_empty() && q_enemies.is_empty();
+                let done = versus.queued.is_empty() || versus.ticks_left(tick.0) == 0;
+                if !(cleared && done) {
+                    None
+                } else if versus.round >= ROUNDS {
+                    Some(Side::Defender)
+                } else {
+                    // enemies that were not released are lost
+                    versus.queued.clear();
+                    versus.round += 1;
+                    versus.threat += THREAT_PER_ROUND;
+                    versus.start_phase(VersusPhase::Build, tick.0);
+                    None
+                }
+            }
+        }
+    };
+    if let Some(winner) = winner {
+        info!("Versus match over: {:?} wins", winner);
+        versus.winner = Some(winner);
+        versus_over.send(VersusOver { winner });
+    }
+}
+
+/// The hex the attacker opens its portals on
+#[derive(Resource, Debug, Clone, Copy)]
+struct AttackerCursor(Hex);
+
+#[derive(Component)]
+struct VersusHud;
+
+#[derive(Component)]
+struct WinnerScreen;
+
+fn spawn_versus_hud(mut commands: Commands, map: Res<MapDefinition>) {
+    commands.insert_resource(AttackerCursor(Hex::new(map.radius as i32 - 1, 0)));
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 24.0,
+                color: Color::rgb(0.9, 0.9, 0.9),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        })
+        .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        VersusHud,
+        Name::new("Versus HUD"),
+    ));
+}
+
+/// The inputs moving the cursor of the attacker
+#[derive(SystemParam)]
+struct AttackerInput<'w> {
+    gamepads: Res<'w, Gamepads>,
+    buttons: Res<'w, Input<GamepadButton>>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    keyboard_input: Res<'w, Input<KeyCode>>,
+}
+
+impl AttackerInput<'_> {
+    /// The direction pushed on the first gamepad or on the keyboard, in world space
+    fn direction(&self, stick_held: bool) -> Option<Vec2> {
+        let mut direction = Vec2::ZERO;
+        if GameControl::Up.just_pressed(&self.keyboard_input) {
+            direction.y += 1.;
+        }
+        if GameControl::Down.just_pressed(&self.keyboard_input) {
+            direction.y -= 1.;
+        }
+        if GameControl::Left.just_pressed(&self.keyboard_input) {
+            direction.x -= 1.;
+        }
+        if GameControl::Right.just_pressed(&self.keyboard_input) {
+            direction.x += 1.;
+        }
+        if let Some(gamepad) = self.gamepads.iter().next() {
+            let pad = [
+                (GamepadButtonType::DPadUp, Vec2::Y),
+                (GamepadButtonType::DPadDown, Vec2::NEG_Y),
+                (GamepadButtonType::DPadLeft, Vec2::NEG_X),
+                (GamepadButtonType::DPadRight, Vec2::X),
+            ];
+            for (button, towards) in pad {
+                if self
+                    .buttons
+                    .just_pressed(GamepadButton::new(gamepad, button))
+                {
+                    direction += towards;
+                }
+            }
+            let stick = Vec2::new(
+                self.axes
+                    .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+                    .unwrap_or(0.),
+                self.axes
+                    .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY))
+                    .unwrap_or(0.),
+            );
+            if stick.length() > STICK_THRESHOLD && !stick_held {
+                direction += stick.normalize();
+            }
+        }
+        (direction != Vec2::ZERO).then(|| direction.normalize())
+    }
+}
+
+fn move_attacker_cursor(
+    time: Res<Time>,
+    input: AttackerInput,
+    grid: Res<HexGrid>,
+    mut cursor: ResMut<AttackerCursor>,
+    mut stick_cooldown: Local<f32>,
+) {
+    *stick_cooldown = (*stick_cooldown - time.delta_seconds()).max(0.);
+    let Some(direction) = input.direction(*stick_cooldown > 0.) else {
+        return;
+    };
+    *stick_cooldown = STICK_REPEAT_SECONDS;
+    // one step is the distance between the centers of two neighbors
+    let step = grid.layout.hex_size.y * 3f32.sqrt();
+    let position = grid.layout.hex_to_world_pos(cursor.0) + direction * step;
+    let hex = grid.layout.world_pos_to_hex(position);
+    if grid.bounds.is_in_bounds(hex) {
+        cursor.0 = hex;
+    }
+}
+
+fn send_attacker_commands(
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    cursor: Res<AttackerCursor>,
+    mut player_commands: EventWriter<EventPlayerCommand>,
+) {
+    let gamepad = gamepads.iter().next();
+    let pressed = |control: GameControl, button: GamepadButtonType| {
+        control.just_pressed(&keyboard_input)
+            || gamepad
+                .is_some_and(|gamepad| buttons.just_pressed(GamepadButton::new(gamepad, button)))
+    };
+    let enemies = [
+        (
+            GameControl::QueueDrone,
+            GamepadButtonType::West,
+            EnemyKind::Drone,
+        ),
+        (
+            GameControl::QueueRunner,
+            GamepadButtonType::North,
+            EnemyKind::Runner,
+        ),
+        (
+            GameControl::QueueTank,
+            GamepadButtonType::East,
+            EnemyKind::Tank,
+        ),
+        (
+            GameControl::QueueFlyer,
+            GamepadButtonType::RightTrigger,
+            EnemyKind::Flyer,
+        ),
+    ];
+    for (control, button, kind) in enemies {
+        if pressed(control, button) {
+            player_commands.send(EventPlayerCommand(PlayerCommand::QueueEnemy { kind }));
+        }
+    }
+    if pressed(GameControl::OpenPortal, GamepadButtonType::South) {
+        let hex = [cursor.0.x, cursor.0.y];
+        player_commands.send(EventPlayerCommand(PlayerCommand::OpenPortal { hex }));
+    }
+}
+
+fn draw_attacker_cursor(mut gizmos: Gizmos, grid: Res<HexGrid>, cursor: Res<AttackerCursor>) {
+    let position = grid.layout.hex_to_world_pos(cursor.0);
+    gizmos.circle_2d(position, grid.layout.hex_size.x * 0.8, CURSOR_COLOR);
+}
+
+fn update_versus_hud(
+    tick: Res<Tick>,
+    versus: Res<Versus>,
+    mut hud: Query<&mut Text, With<VersusHud>>,
+) {
+    let Ok(mut text) = hud.get_single_mut() else {
+        return;
+    };
+    let seconds_left = versus.ticks_left(tick.0) as f64 / TICKS_PER_SECOND;
+    let timing = match (versus.variant, versus.phase) {
+        (VersusVariant::RealTime, _) => format!("{:.0}s left", seconds_left),
+        (VersusVariant::Turns, VersusPhase::Build) => format!(
+            "Round {}/{}: build, {:.0}s left",
+            versus.round, ROUNDS, seconds_left
+        ),
+        (VersusVariant::Turns, VersusPhase::Attack) => {
+            format!("Round {}/{}: attack", versus.round, ROUNDS)
+        }
+    };
+    let queued: Vec<String> = versus
+        .queued
+        .iter()
+        .map(|kind| format!("{:?}", kind))
+        .collect();
+    text.sections[0].value = format!(
+        "{}\nThreat: {}\nNext portal: {}",
+        timing,
+        versus.threat,
+        if queued.is_empty() {
+            "-".to_string()
+        } else {
+            queued.join(", ")
+        }
+    );
+}
+
+fn show_winner(
+    mut commands: Commands,
+    versus: Res<Versus>,
+    mut time: ResMut<Time<Virtual>>,
+    q_screen: Query<(), With<WinnerScreen>>,
+) {
+    let Some(winner) = versus.winner else {
+        return;
+    };
+    if !q_screen.is_empty() {
+        return;
+    }
+    // the board stays as it was when the match ended
+    time.pause();
+    let title = match winner {
+        Side::Attacker => "The attacker wins!",
+        Side::Defender => "The defender wins!",
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            WinnerScreen,
+            Name::new("Winner screen"),
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                title,
+                TextStyle {
+                    font_size: 60.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                    ..default()
+                },
+            ));
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestRun;
+
+    fn versus_run(variant: VersusVariant) -> TestRun {
+        let mut run = TestRun::new();
+        run.world().insert_resource(Versus::new(variant));
+        run
+    }
+
+    fn portal_count(run: &mut TestRun) -> usize {
+        run.world()
+            .query_filtered::<(), With<Portal>>()
+            .iter(&run.app.world)
+            .count()
+    }
+
+    #[test]
+    fn the_attacker_opens_portals_with_the_enemies_it_bought() {
+        let mut run = versus_run(VersusVariant::RealTime);
+        run.command(PlayerCommand::OpenPortal { hex: [8, 0] });
+        assert_eq!(portal_count(&mut run), 0);
+
+        run.command(PlayerCommand::QueueEnemy {
+            kind: EnemyKind::Runner,
+        });
+        run.command(PlayerCommand::QueueEnemy {
+            kind: EnemyKind::Tank,
+        });
+        let versus = run.app.world.resource::<Versus>();
+        // the tank is too expensive for what is left
+        assert_eq!(versus.queued, vec![EnemyKind::Runner]);
+        assert_eq!(versus.threat, START_THREAT - enemy_cost(EnemyKind::Runner));
+
+        run.command(PlayerCommand::OpenPortal { hex: [8, 0] });
+        assert_eq!(portal_count(&mut run), 1);
+        assert!(run.app.world.resource::<Versus>().queued.is_empty());
+    }
+
+    #[test]
+    fn in_turns_portals_wait_for_the_attack_phase() {
+        let mut run = versus_run(VersusVariant::Turns);
+        run.command(PlayerCommand::QueueEnemy {
+            kind: EnemyKind::Drone,
+        });
+        run.command(PlayerCommand::OpenPortal { hex: [8, 0] });
+        assert_eq!(portal_count(&mut run), 0);
+
+        run.advance(BUILD_SECONDS as f32);
+        assert_eq!(
+            run.app.world.resource::<Versus>().phase,
+            VersusPhase::Attack
+        );
+        run.command(PlayerCommand::OpenPortal { hex: [8, 0] });
+        assert_eq!(portal_count(&mut run), 1);
+    }
+
+    #[test]
+    fn a_leak_makes_the_attacker_win() {
+        let mut run = versus_run(VersusVariant::RealTime);
+        run.spawn_enemy(EnemyKind::Drone, Hex::new(2, 0));
+        assert!(run.advance_until(30., |run| run
+            .app
+            .world
+            .resource::<Versus>()
+            .winner
+            .is_some()));
+        assert_eq!(
+            run.app.world.resource::<Versus>().winner,
+            Some(Side::Attacker)
+        );
+    }
+}